@@ -0,0 +1,25 @@
+//! Recent config changes endpoint.
+
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::Json;
+use serde::Serialize;
+
+use crate::config::diff::ConfigChange;
+use crate::server::AppState;
+
+#[derive(Serialize)]
+pub struct ConfigChangesResponse {
+    changes: Vec<ConfigChange>,
+}
+
+/// The config changes applied since startup, newest first, each with the
+/// semantic diff between the versions.
+pub async fn configchanges_handler(
+    State(state): State<Arc<AppState>>,
+) -> Json<ConfigChangesResponse> {
+    Json(ConfigChangesResponse {
+        changes: state.config_changes.recent(),
+    })
+}
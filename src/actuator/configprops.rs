@@ -5,10 +5,12 @@ use std::sync::Arc;
 use axum::extract::State;
 use axum::Json;
 
-use crate::config::model::Config;
 use crate::server::AppState;
 
-pub async fn configprops_handler(State(state): State<Arc<AppState>>) -> Json<Config> {
+/// The loaded config, with values resolved from secret references masked.
+pub async fn configprops_handler(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
     let config = Arc::clone(&state.config.read().await.config);
-    Json((*config).clone())
+    let mut value = serde_json::to_value(&*config).unwrap_or_default();
+    config.secrets.mask_json(&mut value);
+    Json(value)
 }
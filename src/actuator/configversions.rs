@@ -0,0 +1,45 @@
+//! Stored config versions endpoint.
+
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use serde::Serialize;
+
+use crate::config::StoredVersion;
+use crate::server::AppState;
+
+#[derive(Serialize)]
+pub struct ConfigVersionsResponse {
+    source: &'static str,
+    namespace: String,
+    versions: Vec<StoredVersion>,
+}
+
+/// The versions kept by the database backend the config is loaded from,
+/// newest first. 404 when the config is not loaded from a database, or
+/// from one that keeps no versions.
+pub async fn configversions_handler(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ConfigVersionsResponse>, StatusCode> {
+    let Some(store) = state
+        .config_store
+        .as_ref()
+        .filter(|store| store.keeps_history())
+    else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    match store.history().await {
+        Ok(versions) => Ok(Json(ConfigVersionsResponse {
+            source: store.name(),
+            namespace: state.namespace.clone(),
+            versions,
+        })),
+        Err(e) => {
+            tracing::error!(error = %e, "failed to read config versions");
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    }
+}
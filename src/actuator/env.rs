@@ -1,10 +1,14 @@
 //! Environment variables endpoint with secret masking.
 
 use std::collections::BTreeMap;
+use std::sync::Arc;
 
+use axum::extract::State;
 use axum::Json;
 use serde::Serialize;
 
+use crate::server::AppState;
+
 #[derive(Serialize)]
 pub struct EnvResponse {
     #[serde(rename = "activeProfiles")]
@@ -31,7 +35,7 @@ fn is_sensitive(key: &str) -> bool {
     SENSITIVE_PATTERNS.iter().any(|pat| upper.contains(pat))
 }
 
-pub async fn env_handler() -> Json<EnvResponse> {
+pub async fn env_handler(State(state): State<Arc<AppState>>) -> Json<EnvResponse> {
     let mut properties = BTreeMap::new();
 
     for (key, value) in std::env::vars() {
@@ -44,7 +48,15 @@ pub async fn env_handler() -> Json<EnvResponse> {
     }
 
     Json(EnvResponse {
-        active_profiles: vec![],
+        active_profiles: state
+            .config
+            .read()
+            .await
+            .config
+            .profile
+            .iter()
+            .cloned()
+            .collect(),
         property_sources: vec![PropertySource {
             name: "systemEnvironment".to_string(),
             properties,
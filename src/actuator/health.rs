@@ -80,6 +80,7 @@ async fn build_readiness(state: &AppState) -> ComponentHealth {
         status: if is_ready { "UP" } else { "DOWN" }.to_string(),
         details: Some(serde_json::json!({
             "config_source": loaded.source_name,
+            "config_stale": loaded.stale,
             "routes_loaded": route_count,
         })),
     }
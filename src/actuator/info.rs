@@ -1,14 +1,20 @@
 //! Build and runtime information endpoint.
 
+use std::sync::Arc;
+
+use axum::extract::State;
 use axum::Json;
 use serde::Serialize;
 
+use crate::server::AppState;
+
 #[derive(Serialize)]
 pub struct InfoResponse {
     pub app: AppInfo,
     pub build: BuildInfo,
     pub git: GitInfo,
     pub rust: RustInfo,
+    pub config: ConfigInfo,
     pub features: Vec<&'static str>,
 }
 
@@ -38,7 +44,29 @@ pub struct RustInfo {
     pub version: &'static str,
 }
 
-pub async fn info_handler() -> Json<InfoResponse> {
+/// The loaded config's source and version.
+#[derive(Serialize)]
+pub struct ConfigInfo {
+    pub source: String,
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+}
+
+pub async fn info_handler(State(state): State<Arc<AppState>>) -> Json<InfoResponse> {
+    let config = {
+        let loaded = state.config.read().await;
+        let commit = loaded.version.commit();
+        ConfigInfo {
+            source: loaded.source_name.clone(),
+            version: loaded.version.short().to_string(),
+            commit: commit.map(|(sha, _)| sha.to_string()),
+            author: commit.map(|(_, author)| author.to_string()),
+        }
+    };
+
     Json(InfoResponse {
         app: AppInfo {
             name: env!("CARGO_PKG_NAME"),
@@ -58,6 +86,7 @@ pub async fn info_handler() -> Json<InfoResponse> {
         rust: RustInfo {
             version: env!("SWITCHBOARD_RUSTC_VERSION"),
         },
+        config,
         features: enabled_features(),
     })
 }
@@ -80,8 +109,12 @@ fn enabled_features() -> Vec<&'static str> {
         "postgres",
         #[cfg(feature = "mongodb")]
         "mongodb",
+        #[cfg(feature = "git")]
+        "git",
         #[cfg(feature = "sentry-integration")]
         "sentry-integration",
+        #[cfg(feature = "schema")]
+        "schema",
     ];
     features.to_vec()
 }
@@ -7,6 +7,7 @@ use axum::extract::State;
 use axum::Json;
 use serde::Serialize;
 
+use crate::config::model::HeaderRules;
 use crate::server::AppState;
 
 #[derive(Serialize)]
@@ -31,6 +32,7 @@ pub struct RouteMapping {
     pub timeout_ms: u64,
     pub targets: Vec<TargetMapping>,
     pub headers: HeaderMapping,
+    pub response_headers: HeaderMapping,
 }
 
 #[derive(Serialize)]
@@ -44,6 +46,17 @@ pub struct TargetMapping {
 pub struct HeaderMapping {
     pub add: HashMap<String, String>,
     pub strip: Vec<String>,
+    pub set_if_absent: HashMap<String, String>,
+}
+
+impl From<&HeaderRules> for HeaderMapping {
+    fn from(rules: &HeaderRules) -> Self {
+        Self {
+            add: rules.add.clone(),
+            strip: rules.strip.clone(),
+            set_if_absent: rules.set_if_absent.clone(),
+        }
+    }
 }
 
 pub async fn mappings_handler(State(state): State<Arc<AppState>>) -> Json<MappingsResponse> {
@@ -67,10 +80,8 @@ pub async fn mappings_handler(State(state): State<Arc<AppState>>) -> Json<Mappin
                     timeout_ms: t.timeout,
                 })
                 .collect(),
-            headers: HeaderMapping {
-                add: route.headers.add.clone(),
-                strip: route.headers.strip.clone(),
-            },
+            headers: HeaderMapping::from(&route.headers),
+            response_headers: HeaderMapping::from(&route.response_headers),
         })
         .collect();
 
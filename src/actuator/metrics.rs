@@ -3,7 +3,7 @@
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
-use axum::extract::{Path, State};
+use axum::extract::{Path, RawQuery, State};
 use axum::http::StatusCode;
 use axum::Json;
 use serde::Serialize;
@@ -19,7 +19,11 @@ const METRIC_NAMES: &[&str] = &[
     "requests.forwarded",
     "requests.failed",
     "requests.active",
+    "requests.hedged",
+    "requests.deduplicated",
+    "queue.depth",
     "config.reloads",
+    "config.rollbacks",
     "uptime.seconds",
 ];
 
@@ -33,6 +37,14 @@ pub async fn metrics_index_handler() -> Json<MetricsIndexResponse> {
 pub struct MetricDetailResponse {
     pub name: String,
     pub measurement: MetricMeasurement,
+    #[serde(rename = "availableTags", skip_serializing_if = "Vec::is_empty")]
+    pub available_tags: Vec<MetricTag>,
+}
+
+#[derive(Serialize)]
+pub struct MetricTag {
+    pub tag: &'static str,
+    pub values: Vec<String>,
 }
 
 #[derive(Serialize)]
@@ -44,7 +56,12 @@ pub struct MetricMeasurement {
 pub async fn metric_detail_handler(
     State(state): State<Arc<AppState>>,
     Path(name): Path<String>,
+    RawQuery(query): RawQuery,
 ) -> Result<Json<MetricDetailResponse>, StatusCode> {
+    if name == "queue.depth" {
+        return Ok(Json(queue_depth(&state, query.as_deref())));
+    }
+
     let (statistic, value) = match name.as_str() {
         "requests.forwarded" => (
             "COUNT",
@@ -55,10 +72,19 @@ pub async fn metric_detail_handler(
             "VALUE",
             state.stats.active_requests.load(Ordering::Relaxed) as f64,
         ),
+        "requests.hedged" => ("COUNT", state.stats.hedged.load(Ordering::Relaxed) as f64),
+        "requests.deduplicated" => (
+            "COUNT",
+            state.stats.deduplicated.load(Ordering::Relaxed) as f64,
+        ),
         "config.reloads" => (
             "COUNT",
             state.stats.config_reloads.load(Ordering::Relaxed) as f64,
         ),
+        "config.rollbacks" => (
+            "COUNT",
+            state.stats.config_rollbacks.load(Ordering::Relaxed) as f64,
+        ),
         "uptime.seconds" => ("VALUE", state.start_time.elapsed().as_secs_f64()),
         _ => return Err(StatusCode::NOT_FOUND),
     };
@@ -69,5 +95,35 @@ pub async fn metric_detail_handler(
             statistic: statistic.to_string(),
             value,
         },
+        available_tags: Vec::new(),
     }))
 }
+
+/// Ordered-delivery queue depth, summed over targets or filtered with
+/// `?tag=target:<url>`.
+fn queue_depth(state: &AppState, query: Option<&str>) -> MetricDetailResponse {
+    let depths = state.ordered_queues.depths();
+    let target = query.and_then(|q| {
+        url::form_urlencoded::parse(q.as_bytes())
+            .filter(|(key, _)| key == "tag")
+            .find_map(|(_, value)| value.strip_prefix("target:").map(str::to_string))
+    });
+
+    let value: u64 = depths
+        .iter()
+        .filter(|(url, _)| target.as_ref().map_or(true, |t| t == url))
+        .map(|(_, depth)| depth)
+        .sum();
+
+    MetricDetailResponse {
+        name: "queue.depth".into(),
+        measurement: MetricMeasurement {
+            statistic: "VALUE".into(),
+            value: value as f64,
+        },
+        available_tags: vec![MetricTag {
+            tag: "target",
+            values: depths.into_iter().map(|(url, _)| url).collect(),
+        }],
+    }
+}
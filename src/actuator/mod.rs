@@ -1,10 +1,12 @@
 //! Spring Boot-style actuator endpoints for operational monitoring.
 //!
 //! Provides health checks, build info, metrics, configuration inspection,
-//! environment variables, route mappings, and runtime log level management
-//! under the `/actuator` prefix.
+//! stored config versions, recent config changes, environment variables, route mappings, and
+//! runtime log level management under the `/actuator` prefix.
 
+mod configchanges;
 mod configprops;
+mod configversions;
 mod env;
 mod health;
 mod info;
@@ -49,6 +51,11 @@ pub fn actuator_router() -> Router<Arc<AppState>> {
         .route("/metrics", get(metrics::metrics_index_handler))
         .route("/metrics/{name}", get(metrics::metric_detail_handler))
         .route("/configprops", get(configprops::configprops_handler))
+        .route("/configchanges", get(configchanges::configchanges_handler))
+        .route(
+            "/configversions",
+            get(configversions::configversions_handler),
+        )
         .route("/mappings", get(mappings::mappings_handler))
         .route(
             "/loggers",
@@ -164,6 +171,8 @@ async fn index_handler() -> Json<ActuatorIndex> {
         ("metrics", "/actuator/metrics", false),
         ("metrics-name", "/actuator/metrics/{name}", true),
         ("configprops", "/actuator/configprops", false),
+        ("configversions", "/actuator/configversions", false),
+        ("configchanges", "/actuator/configchanges", false),
         ("mappings", "/actuator/mappings", false),
         ("loggers", "/actuator/loggers", false),
     ];
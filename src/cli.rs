@@ -1,7 +1,7 @@
 //! Command-line interface definitions using clap derive macros.
 //!
 //! Contains the top-level [`Cli`] parser, the [`Commands`] enum for
-//! subcommands (run, init, validate, health), and their associated
+//! subcommands (run, init, validate, health, config, schema), and their associated
 //! argument structs. Every flag has an environment variable equivalent
 //! for container deployments.
 
@@ -39,19 +39,39 @@ pub enum Commands {
 
     /// Check health of a running instance
     Health(HealthArgs),
+
+    /// Push or pull the config stored in a database backend
+    #[cfg(any(
+        feature = "dynamodb",
+        feature = "redis",
+        feature = "postgres",
+        feature = "mongodb",
+        feature = "sqlite"
+    ))]
+    Config(Box<ConfigArgs>),
+
+    /// Print the JSON Schema of the config file
+    #[cfg(feature = "schema")]
+    Schema(SchemaArgs),
 }
 
 #[derive(Args)]
 #[command(after_help = "\x1b[1mExamples:\x1b[0m\n  \
         switchboard run                                    Auto-detect config\n  \
         switchboard run -c routes.yaml                     Specific config file\n  \
+        switchboard run -c conf.d/                         Merge every file in a directory\n  \
+        cat routes.yaml | switchboard run -c -             Read the config from stdin\n  \
         switchboard run -c routes.yaml -p 8080 --pretty    Local dev mode\n  \
         switchboard run --redis-url redis://cache:6379      Redis config")]
 pub struct RunArgs {
-    /// Config file path (.yaml, .json, .toml)
+    /// Config file path (.yaml, .json, .toml), directory of config files, or - for stdin
     #[arg(short, long, env = "CONFIG_FILE")]
     pub config: Option<PathBuf>,
 
+    /// Inline config (YAML, JSON, or TOML, optionally base64), used when no --config is given
+    #[arg(long, env = "SWITCHBOARD_CONFIG", hide_env_values = true)]
+    pub config_inline: Option<String>,
+
     /// Listen port
     #[arg(short, long, env = "PORT", default_value_t = 3000)]
     pub port: u16,
@@ -64,41 +84,72 @@ pub struct RunArgs {
     #[arg(short, long, env = "SWITCHBOARD_NAMESPACE", default_value = "default")]
     pub namespace: String,
 
-    // -- Database Backends --
-    /// `DynamoDB` table name
-    #[cfg(feature = "dynamodb")]
-    #[arg(long, env = "DYNAMODB_TABLE", help_heading = "Database Backends")]
-    pub dynamodb_table: Option<String>,
+    /// Config profile to layer onto the base config
+    #[arg(long, env = "SWITCHBOARD_PROFILE")]
+    pub profile: Option<String>,
 
-    /// AWS region for `DynamoDB`
-    #[cfg(feature = "dynamodb")]
+    /// File to keep the last successfully loaded config in, served at startup
+    /// when no config source can be loaded
+    #[arg(long, env = "CONFIG_CACHE")]
+    pub config_cache: Option<PathBuf>,
+
+    // -- Remote Config --
+    /// URL to fetch the config from over HTTP(S)
+    #[arg(long, env = "CONFIG_URL", help_heading = "Remote Config")]
+    pub config_url: Option<String>,
+
+    /// Bearer token sent when fetching `--config-url`
     #[arg(
         long,
-        env = "DYNAMODB_REGION",
-        default_value = "us-east-1",
-        help_heading = "Database Backends"
+        env = "CONFIG_URL_TOKEN",
+        hide_env_values = true,
+        conflicts_with = "config_url_basic_auth",
+        help_heading = "Remote Config"
     )]
-    pub dynamodb_region: String,
+    pub config_url_token: Option<String>,
 
-    /// Redis connection URL
-    #[cfg(feature = "redis")]
-    #[arg(long, env = "REDIS_URL", help_heading = "Database Backends")]
-    pub redis_url: Option<String>,
+    /// Basic auth `user:password` sent when fetching `--config-url`
+    #[arg(
+        long,
+        env = "CONFIG_URL_BASIC_AUTH",
+        hide_env_values = true,
+        help_heading = "Remote Config"
+    )]
+    pub config_url_basic_auth: Option<String>,
 
-    /// `PostgreSQL` connection URL
-    #[cfg(feature = "postgres")]
-    #[arg(long, env = "POSTGRES_URL", help_heading = "Database Backends")]
-    pub postgres_url: Option<String>,
+    // -- Git Config --
+    /// Git repository to read the config from (local path or file:// URL)
+    #[cfg(feature = "git")]
+    #[arg(long, env = "GIT_REPO", help_heading = "Git Config")]
+    pub git_repo: Option<String>,
 
-    /// `MongoDB` connection URL
-    #[cfg(feature = "mongodb")]
-    #[arg(long, env = "MONGODB_URL", help_heading = "Database Backends")]
-    pub mongodb_url: Option<String>,
+    /// Branch, tag, or commit to read
+    #[cfg(feature = "git")]
+    #[arg(
+        long,
+        env = "GIT_REF",
+        default_value = "main",
+        help_heading = "Git Config"
+    )]
+    pub git_ref: String,
 
-    /// `SQLite` database path
-    #[cfg(feature = "sqlite")]
-    #[arg(long, env = "SQLITE_PATH", help_heading = "Database Backends")]
-    pub sqlite_path: Option<PathBuf>,
+    /// Config file path within the repository
+    #[cfg(feature = "git")]
+    #[arg(
+        long,
+        env = "GIT_PATH",
+        default_value = "switchboard.yaml",
+        help_heading = "Git Config"
+    )]
+    pub git_path: PathBuf,
+
+    /// Directory for the local mirror of the repository (default: a private temp directory removed on exit)
+    #[cfg(feature = "git")]
+    #[arg(long, env = "GIT_CACHE_DIR", help_heading = "Git Config")]
+    pub git_cache_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub database: DatabaseArgs,
 
     // -- Logging --
     /// Log level
@@ -143,7 +194,7 @@ pub struct RunArgs {
     )]
     pub max_body: usize,
 
-    /// Config refresh interval in seconds (for database backends)
+    /// Config refresh interval in seconds (files also reload when changed)
     #[arg(
         long,
         env = "POLL_INTERVAL_SECS",
@@ -151,6 +202,163 @@ pub struct RunArgs {
         help_heading = "Tuning"
     )]
     pub poll_interval: u64,
+
+    /// Seconds to watch the failure rate after a reload, rolling back if it rises (0 to disable)
+    #[arg(
+        long,
+        env = "ROLLBACK_WINDOW_SECS",
+        default_value_t = 60,
+        help_heading = "Tuning"
+    )]
+    pub rollback_window: u64,
+
+    /// Rise in the share of failed requests (0 to 1) that rolls a reload back
+    #[arg(
+        long,
+        env = "ROLLBACK_THRESHOLD",
+        default_value_t = 0.5,
+        value_parser = parse_rate,
+        help_heading = "Tuning"
+    )]
+    pub rollback_threshold: f64,
+
+    /// Requests to see after a reload before judging its failure rate
+    #[arg(
+        long,
+        env = "ROLLBACK_MIN_REQUESTS",
+        default_value_t = 20,
+        help_heading = "Tuning"
+    )]
+    pub rollback_min_requests: u64,
+
+    /// Config changes to keep for /actuator/configchanges
+    #[arg(
+        long,
+        env = "CONFIG_CHANGES_LIMIT",
+        default_value_t = 50,
+        help_heading = "Tuning"
+    )]
+    pub config_changes_limit: usize,
+
+    /// Max entries in the in-memory dedup store
+    #[arg(
+        long,
+        env = "DEDUP_MAX_ENTRIES",
+        default_value_t = 10_000,
+        help_heading = "Tuning"
+    )]
+    pub dedup_max_entries: usize,
+
+    /// Max requests queued or in flight per ordered secondary target
+    #[arg(
+        long,
+        env = "ORDERED_QUEUE_LIMIT",
+        default_value_t = crate::proxy::ordering::DEFAULT_CAPACITY,
+        help_heading = "Tuning"
+    )]
+    pub ordered_queue_limit: usize,
+
+    /// Redis URL for a dedup store shared across instances (default: in-memory)
+    #[cfg(feature = "redis")]
+    #[arg(long, env = "DEDUP_REDIS_URL", help_heading = "Tuning")]
+    pub dedup_redis_url: Option<String>,
+}
+
+/// Connection settings for the database config backends, shared by
+/// `switchboard run` and `switchboard config`.
+#[derive(Args)]
+pub struct DatabaseArgs {
+    /// `DynamoDB` table name
+    #[cfg(feature = "dynamodb")]
+    #[arg(long, env = "DYNAMODB_TABLE", help_heading = "Database Backends")]
+    pub dynamodb_table: Option<String>,
+
+    /// AWS region for `DynamoDB`
+    #[cfg(feature = "dynamodb")]
+    #[arg(
+        long,
+        env = "DYNAMODB_REGION",
+        default_value = "us-east-1",
+        help_heading = "Database Backends"
+    )]
+    pub dynamodb_region: String,
+
+    /// Redis connection URL
+    #[cfg(feature = "redis")]
+    #[arg(long, env = "REDIS_URL", help_heading = "Database Backends")]
+    pub redis_url: Option<String>,
+
+    /// `PostgreSQL` connection URL
+    #[cfg(feature = "postgres")]
+    #[arg(long, env = "POSTGRES_URL", help_heading = "Database Backends")]
+    pub postgres_url: Option<String>,
+
+    /// `MongoDB` connection URL
+    #[cfg(feature = "mongodb")]
+    #[arg(long, env = "MONGODB_URL", help_heading = "Database Backends")]
+    pub mongodb_url: Option<String>,
+
+    /// `SQLite` database path
+    #[cfg(feature = "sqlite")]
+    #[arg(long, env = "SQLITE_PATH", help_heading = "Database Backends")]
+    pub sqlite_path: Option<PathBuf>,
+
+    /// Table layout for the `SQLite` and `PostgreSQL` backends
+    #[cfg(any(feature = "postgres", feature = "sqlite"))]
+    #[arg(
+        long,
+        env = "SQL_SCHEMA",
+        value_enum,
+        default_value = "document",
+        help_heading = "Database Backends"
+    )]
+    pub sql_schema: SqlSchema,
+
+    /// Ed25519 public key (base64) that database configs must be signed
+    /// with; unsigned or tampered configs are rejected
+    #[cfg(any(
+        feature = "dynamodb",
+        feature = "redis",
+        feature = "postgres",
+        feature = "mongodb",
+        feature = "sqlite"
+    ))]
+    #[arg(
+        long,
+        env = "CONFIG_VERIFY_KEY",
+        value_parser = crate::config::payload::decode_key,
+        help_heading = "Signed Configs"
+    )]
+    pub config_verify_key: Option<[u8; 32]>,
+
+    /// AES-256 key (base64) that decrypts encrypted database configs
+    #[cfg(any(
+        feature = "dynamodb",
+        feature = "redis",
+        feature = "postgres",
+        feature = "mongodb",
+        feature = "sqlite"
+    ))]
+    #[arg(
+        long,
+        env = "CONFIG_DECRYPT_KEY",
+        hide_env_values = true,
+        value_parser = crate::config::payload::decode_key,
+        conflicts_with = "config_decrypt_key_file",
+        help_heading = "Signed Configs"
+    )]
+    pub config_decrypt_key: Option<[u8; 32]>,
+
+    /// File holding the `--config-decrypt-key`
+    #[cfg(any(
+        feature = "dynamodb",
+        feature = "redis",
+        feature = "postgres",
+        feature = "mongodb",
+        feature = "sqlite"
+    ))]
+    #[arg(long, env = "CONFIG_DECRYPT_KEY_FILE", help_heading = "Signed Configs")]
+    pub config_decrypt_key_file: Option<PathBuf>,
 }
 
 #[derive(Args)]
@@ -178,13 +386,28 @@ pub struct InitArgs {
 
 #[derive(Args)]
 pub struct ValidateArgs {
-    /// Config file to validate
-    #[arg(default_value = "switchboard.yaml")]
-    pub config: PathBuf,
+    /// Config file or directory to validate, or - for stdin [default: switchboard.yaml]
+    pub config: Option<PathBuf>,
+
+    /// Inline config to validate (YAML, JSON, or TOML, optionally base64), used when no file is given
+    #[arg(long, env = "SWITCHBOARD_CONFIG", hide_env_values = true)]
+    pub config_inline: Option<String>,
 
     /// Output format
     #[arg(long, default_value = "text")]
     pub format: ValidateFormat,
+
+    /// Config profile to layer onto the base config
+    #[arg(long, env = "SWITCHBOARD_PROFILE")]
+    pub profile: Option<String>,
+}
+
+#[cfg(feature = "schema")]
+#[derive(Args)]
+pub struct SchemaArgs {
+    /// Write the schema to a file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
 }
 
 #[derive(Args)]
@@ -198,6 +421,119 @@ pub struct HealthArgs {
     pub json: bool,
 }
 
+#[derive(Args)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub command: ConfigCommands,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Validate a config file and store it in a database backend
+    Push(ConfigPushArgs),
+
+    /// Print the config stored in a database backend
+    Pull(ConfigPullArgs),
+
+    /// List the config versions stored in a database backend
+    History(ConfigHistoryArgs),
+
+    /// Make an earlier stored config version the active one
+    Rollback(ConfigRollbackArgs),
+}
+
+#[derive(Args)]
+#[command(after_help = "\x1b[1mExamples:\x1b[0m\n  \
+        switchboard config push routes.yaml --redis-url redis://cache:6379\n  \
+        switchboard config push conf.d/ -n staging --sqlite-path sb.db --dry-run\n  \
+        switchboard config push routes.yaml --redis-url redis://cache:6379 --sign-key-file signing.pem")]
+pub struct ConfigPushArgs {
+    /// Config file or directory to push
+    pub config: PathBuf,
+
+    /// Config namespace to write
+    #[arg(short, long, env = "SWITCHBOARD_NAMESPACE", default_value = "default")]
+    pub namespace: String,
+
+    /// Validate and print the config JSON, without writing it
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Author recorded with the new version [default: $USER]
+    #[arg(long, env = "SWITCHBOARD_AUTHOR")]
+    pub author: Option<String>,
+
+    /// Comment recorded with the new version
+    #[arg(short, long)]
+    pub message: Option<String>,
+
+    /// Ed25519 private key (PKCS#8 PEM or DER) to sign the config with
+    #[arg(long, env = "CONFIG_SIGN_KEY_FILE")]
+    pub sign_key_file: Option<PathBuf>,
+
+    /// Encrypt the config with the `--config-decrypt-key`
+    #[arg(long)]
+    pub encrypt: bool,
+
+    #[command(flatten)]
+    pub database: DatabaseArgs,
+}
+
+#[derive(Args)]
+#[command(after_help = "\x1b[1mExamples:\x1b[0m\n  \
+        switchboard config pull --redis-url redis://cache:6379\n  \
+        switchboard config pull -n staging --postgres-url postgres://db/sb -o staging.toml")]
+pub struct ConfigPullArgs {
+    /// Config namespace to read
+    #[arg(short, long, env = "SWITCHBOARD_NAMESPACE", default_value = "default")]
+    pub namespace: String,
+
+    /// Output format [default: from the output file's extension, else yaml]
+    #[arg(short, long)]
+    pub format: Option<ConfigFormat>,
+
+    /// Write the config to a file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub database: DatabaseArgs,
+}
+
+#[derive(Args)]
+#[command(after_help = "\x1b[1mExamples:\x1b[0m\n  \
+        switchboard config history --redis-url redis://cache:6379\n  \
+        switchboard config history -n staging --sqlite-path sb.db --json")]
+pub struct ConfigHistoryArgs {
+    /// Config namespace to list
+    #[arg(short, long, env = "SWITCHBOARD_NAMESPACE", default_value = "default")]
+    pub namespace: String,
+
+    /// Output as JSON
+    #[arg(long)]
+    pub json: bool,
+
+    #[command(flatten)]
+    pub database: DatabaseArgs,
+}
+
+#[derive(Args)]
+#[command(after_help = "\x1b[1mExamples:\x1b[0m\n  \
+        switchboard config rollback 3 --redis-url redis://cache:6379\n  \
+        switchboard config rollback 12 -n staging --postgres-url postgres://db/sb")]
+pub struct ConfigRollbackArgs {
+    /// Version to activate, as listed by `switchboard config history`
+    #[arg(id = "config_version", value_name = "VERSION")]
+    pub version: u64,
+
+    /// Config namespace to roll back
+    #[arg(short, long, env = "SWITCHBOARD_NAMESPACE", default_value = "default")]
+    pub namespace: String,
+
+    #[command(flatten)]
+    pub database: DatabaseArgs,
+}
+
 #[derive(Clone, Debug, ValueEnum)]
 pub enum LogLevel {
     Trace,
@@ -238,8 +574,36 @@ impl ConfigFormat {
     }
 }
 
+/// How the SQL backends lay out a config in their tables.
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SqlSchema {
+    /// One JSON document per namespace, with version history
+    Document,
+    /// Routes, targets, header rules and defaults in their own tables
+    Normalized,
+}
+
 #[derive(Clone, Debug, ValueEnum)]
 pub enum ValidateFormat {
     Text,
     Json,
 }
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate <= 1.0 => Ok(rate),
+        _ => Err("expected a number greater than 0 and at most 1".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_definition_is_consistent() {
+        use clap::CommandFactory;
+        Cli::command().debug_assert();
+    }
+}
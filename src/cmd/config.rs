@@ -0,0 +1,453 @@
+//! `switchboard config push|pull|history|rollback` — manage the config
+//! stored in a database backend.
+//!
+//! `push` reads a config file (or directory) the way `switchboard run`
+//! would, validates it with and without each of its profiles, and stores
+//! it as the JSON document the database sources load. Fragments are
+//! merged into one document; secret references are resolved for
+//! validation only and stored as written. Each push becomes a new numbered
+//! version recording its author and an optional comment, and can be signed
+//! and encrypted (see [`payload`](crate::config::payload)). `pull` prints
+//! the active config as YAML, JSON, or TOML, `history` lists the stored
+//! versions, and `rollback` makes an earlier version active again.
+//! With `--sql-schema normalized`, `push` replaces the rows of the
+//! normalized tables instead and no history is kept.
+
+use std::path::Path;
+use std::sync::Arc;
+
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+use crate::cli::SqlSchema;
+use crate::cli::{
+    ConfigArgs, ConfigCommands, ConfigFormat, ConfigHistoryArgs, ConfigPullArgs, ConfigPushArgs,
+    ConfigRollbackArgs, DatabaseArgs,
+};
+use crate::cmd::init::serialize::serialize_config;
+use crate::config::model::Config;
+use crate::config::payload::{PayloadKeys, SigningKey};
+use crate::config::secrets::placeholders;
+use crate::config::sources::{include, parse_config_str};
+use crate::config::validation::validate;
+use crate::config::{profiles, ConfigStore, VersionMeta};
+use crate::error::SwitchboardError;
+
+pub async fn execute(args: ConfigArgs) -> Result<(), SwitchboardError> {
+    match args.command {
+        ConfigCommands::Push(ref args) => push(args).await,
+        ConfigCommands::Pull(ref args) => pull(args).await,
+        ConfigCommands::History(ref args) => history(args).await,
+        ConfigCommands::Rollback(ref args) => rollback(args).await,
+    }
+}
+
+async fn push(args: &ConfigPushArgs) -> Result<(), SwitchboardError> {
+    let config = prepare(&args.config)?;
+    let json = to_json(&config)?;
+    let signer = args
+        .sign_key_file
+        .as_deref()
+        .map(|path| read_key_file(path, SigningKey::from_pkcs8))
+        .transpose()?;
+    let keys = payload_keys(&args.database)?.sealing(signer, args.encrypt)?;
+    #[cfg(any(feature = "postgres", feature = "sqlite"))]
+    if keys.seals() && args.database.sql_schema == SqlSchema::Normalized {
+        return Err(envelopes_need_documents(if args.encrypt {
+            "--encrypt"
+        } else {
+            "--sign-key-file"
+        }));
+    }
+
+    if args.dry_run {
+        println!("{json}");
+        if keys.seals() {
+            // The signature covers the version the store assigns
+            eprintln!("Signing and encryption are applied when the config is written");
+        }
+        eprintln!(
+            "\u{2713} {} is valid ({} routes); dry run, nothing written to namespace '{}'",
+            args.config.display(),
+            config.routes.len(),
+            args.namespace
+        );
+        return Ok(());
+    }
+
+    let store = require_store(&args.database, &args.namespace, keys).await?;
+    let meta = VersionMeta {
+        author: args
+            .author
+            .clone()
+            .or_else(|| std::env::var("USER").ok())
+            .unwrap_or_else(|| "unknown".into()),
+        comment: args.message.clone(),
+    };
+    let version = store.push_json(&json, &meta).await?;
+    eprintln!(
+        "\u{2713} Pushed {} ({} routes) to {} namespace '{}' as version {version}",
+        args.config.display(),
+        config.routes.len(),
+        store.name(),
+        args.namespace
+    );
+    Ok(())
+}
+
+async fn pull(args: &ConfigPullArgs) -> Result<(), SwitchboardError> {
+    let keys = payload_keys(&args.database)?;
+    let store = require_store(&args.database, &args.namespace, keys.clone()).await?;
+    let label = format!("{}::{}", store.name(), args.namespace);
+    let stored = store
+        .read_json()
+        .await?
+        .ok_or_else(|| SwitchboardError::Database {
+            backend: store.name(),
+            source: format!("no config stored for namespace '{}'", args.namespace).into(),
+        })?;
+    let json = keys.open(&stored.json, &label, &args.namespace, stored.version)?;
+    let config: Config =
+        serde_json::from_str(&json).map_err(|e| SwitchboardError::ConfigParse {
+            path: label,
+            source: Box::new(e),
+        })?;
+
+    let format = args.format.clone().unwrap_or_else(|| {
+        let ext = args
+            .output
+            .as_deref()
+            .and_then(Path::extension)
+            .and_then(|e| e.to_str());
+        match ext {
+            Some("json") => ConfigFormat::Json,
+            Some("toml") => ConfigFormat::Toml,
+            _ => ConfigFormat::Yaml,
+        }
+    });
+    let content = serialize_config(&config, &format)?;
+
+    match &args.output {
+        Some(path) => {
+            std::fs::write(path, content)?;
+            eprintln!(
+                "\u{2713} Wrote {} namespace '{}' to {}",
+                store.name(),
+                args.namespace,
+                path.display()
+            );
+        }
+        None => print!("{content}"),
+    }
+    Ok(())
+}
+
+async fn history(args: &ConfigHistoryArgs) -> Result<(), SwitchboardError> {
+    let store = require_store(
+        &args.database,
+        &args.namespace,
+        payload_keys(&args.database)?,
+    )
+    .await?;
+    let versions = store.history().await?;
+
+    if args.json {
+        let json = serde_json::to_string_pretty(&versions)
+            .map_err(|e| SwitchboardError::Io(std::io::Error::other(e.to_string())))?;
+        println!("{json}");
+        return Ok(());
+    }
+
+    if versions.is_empty() {
+        eprintln!(
+            "No versions stored in {} namespace '{}'",
+            store.name(),
+            args.namespace
+        );
+        return Ok(());
+    }
+    let row = |marker: &str, version: &str, created: &str, author: &str, comment: &str| {
+        let line = format!("{marker} {version:>7}  {created:<20}  {author:<16}  {comment}");
+        println!("{}", line.trim_end());
+    };
+    row(" ", "VERSION", "CREATED", "AUTHOR", "COMMENT");
+    for version in &versions {
+        row(
+            if version.active { "*" } else { " " },
+            &version.version.to_string(),
+            &version.created_at_utc(),
+            &version.author,
+            version.comment.as_deref().unwrap_or_default(),
+        );
+    }
+    Ok(())
+}
+
+async fn rollback(args: &ConfigRollbackArgs) -> Result<(), SwitchboardError> {
+    let store = require_store(
+        &args.database,
+        &args.namespace,
+        payload_keys(&args.database)?,
+    )
+    .await?;
+    store.activate(args.version).await?;
+    eprintln!(
+        "\u{2713} Activated version {} of {} namespace '{}'",
+        args.version,
+        store.name(),
+        args.namespace
+    );
+    Ok(())
+}
+
+/// Read the config at `path` with its fragments merged, and validate it
+/// as loaded without a profile and with each profile it defines. Secret
+/// references are validated as [`placeholders`], without reading this
+/// host's environment or files. The returned config is unresolved: secret
+/// references and profiles are kept for the server to apply.
+pub fn prepare(path: &Path) -> Result<Config, SwitchboardError> {
+    if !path.exists() {
+        return Err(SwitchboardError::ConfigFileNotFound {
+            path: path.to_path_buf(),
+        });
+    }
+
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let display = path.display().to_string();
+    let parse_root = |content: &str| parse_config_str(ext, content, &display);
+    let fragments = include::read_fragments(path, &parse_root)?;
+    let (mut config, origins) = include::merge(fragments)?;
+    // Database sources load a single document
+    config.include.clear();
+
+    let names: Vec<String> = config.profiles.keys().cloned().collect();
+    for profile in std::iter::once(None).chain(names.iter().map(|n| Some(n.as_str()))) {
+        let resolved = placeholders(profiles::apply(config.clone(), profile)?)?;
+        if let Err(mut errors) = validate(&resolved) {
+            origins.annotate(&mut errors);
+            if let Some(profile) = profile {
+                for error in &mut errors {
+                    error.message = format!("{} (with profile '{profile}')", error.message);
+                }
+            }
+            return Err(SwitchboardError::ConfigValidation { errors });
+        }
+    }
+    Ok(config)
+}
+
+fn to_json(config: &Config) -> Result<String, SwitchboardError> {
+    serde_json::to_string_pretty(config)
+        .map_err(|e| SwitchboardError::Io(std::io::Error::other(e.to_string())))
+}
+
+async fn require_store(
+    db: &DatabaseArgs,
+    namespace: &str,
+    keys: PayloadKeys,
+) -> Result<Arc<dyn ConfigStore>, SwitchboardError> {
+    open_store_with(db, namespace, None, keys)
+        .await?
+        .ok_or_else(|| SwitchboardError::NoConfigSource {
+            hint: "Provide a database backend flag, e.g. --redis-url or --sqlite-path.".into(),
+        })
+}
+
+/// The keys stored configs are opened with, from `--config-verify-key` and
+/// `--config-decrypt-key` or its file.
+pub fn payload_keys(db: &DatabaseArgs) -> Result<PayloadKeys, SwitchboardError> {
+    let decrypt = match db.config_decrypt_key_file {
+        Some(ref path) => Some(read_key_file(path, |contents| {
+            crate::config::payload::decode_key(&String::from_utf8_lossy(contents))
+        })?),
+        None => db.config_decrypt_key,
+    };
+    Ok(PayloadKeys::new(db.config_verify_key, decrypt))
+}
+
+fn read_key_file<T>(
+    path: &Path,
+    parse: impl FnOnce(&[u8]) -> Result<T, String>,
+) -> Result<T, SwitchboardError> {
+    let contents = std::fs::read(path).map_err(|e| {
+        SwitchboardError::Io(std::io::Error::new(
+            e.kind(),
+            format!("cannot read {}: {e}", path.display()),
+        ))
+    })?;
+    parse(&contents).map_err(|reason| {
+        SwitchboardError::Io(std::io::Error::other(format!(
+            "{}: {reason}",
+            path.display()
+        )))
+    })
+}
+
+/// The database backend selected by `db`, in the priority order
+/// `switchboard run` loads them in, or `None` if no backend flag is given.
+pub async fn open_store(
+    db: &DatabaseArgs,
+    namespace: &str,
+    profile: Option<&str>,
+) -> Result<Option<Arc<dyn ConfigStore>>, SwitchboardError> {
+    open_store_with(db, namespace, profile, payload_keys(db)?).await
+}
+
+/// [`open_store`] with the given payload keys, which also seal pushes.
+async fn open_store_with(
+    db: &DatabaseArgs,
+    namespace: &str,
+    profile: Option<&str>,
+    keys: PayloadKeys,
+) -> Result<Option<Arc<dyn ConfigStore>>, SwitchboardError> {
+    let profile = profile.map(str::to_string);
+
+    #[cfg(feature = "dynamodb")]
+    if let Some(ref table) = db.dynamodb_table {
+        let store = crate::config::sources::dynamodb::DynamoDbSource::new(
+            table,
+            &db.dynamodb_region,
+            namespace,
+        )
+        .await?
+        .with_payload_keys(keys)
+        .with_profile(profile);
+        return Ok(Some(Arc::new(store)));
+    }
+
+    #[cfg(feature = "redis")]
+    if let Some(ref url) = db.redis_url {
+        let store = crate::config::sources::redis_source::RedisSource::new(url, namespace)
+            .await?
+            .with_payload_keys(keys)
+            .with_profile(profile);
+        return Ok(Some(Arc::new(store)));
+    }
+
+    #[cfg(feature = "postgres")]
+    if let Some(ref url) = db.postgres_url {
+        if normalized_schema(db, &keys, "postgres")? {
+            let store = crate::config::sources::postgres_normalized::NormalizedPostgresSource::new(
+                url, namespace,
+            )
+            .await?
+            .with_profile(profile);
+            return Ok(Some(Arc::new(store)));
+        }
+        let store = crate::config::sources::postgres::PostgresSource::new(url, namespace)
+            .await?
+            .with_payload_keys(keys)
+            .with_profile(profile);
+        return Ok(Some(Arc::new(store)));
+    }
+
+    #[cfg(feature = "mongodb")]
+    if let Some(ref url) = db.mongodb_url {
+        let store = crate::config::sources::mongodb_source::MongoDbSource::new(url, namespace)
+            .await?
+            .with_payload_keys(keys)
+            .with_profile(profile);
+        return Ok(Some(Arc::new(store)));
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Some(ref path) = db.sqlite_path {
+        if normalized_schema(db, &keys, "sqlite")? {
+            let store = crate::config::sources::sqlite_normalized::NormalizedSqliteSource::new(
+                path, namespace,
+            )
+            .await?
+            .with_profile(profile);
+            return Ok(Some(Arc::new(store)));
+        }
+        let store = crate::config::sources::sqlite::SqliteSource::new(path, namespace)
+            .await?
+            .with_payload_keys(keys)
+            .with_profile(profile);
+        return Ok(Some(Arc::new(store)));
+    }
+
+    Ok(None)
+}
+
+/// Whether `--sql-schema normalized` is selected. Envelopes are only kept
+/// by the document schema, so payload keys cannot be used with it.
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+fn normalized_schema(
+    db: &DatabaseArgs,
+    keys: &PayloadKeys,
+    backend: &str,
+) -> Result<bool, SwitchboardError> {
+    if db.sql_schema == SqlSchema::Document {
+        return Ok(false);
+    }
+    if !keys.is_empty() {
+        return Err(envelopes_need_documents(backend));
+    }
+    Ok(true)
+}
+
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+fn envelopes_need_documents(path: &str) -> SwitchboardError {
+    SwitchboardError::ConfigPayload {
+        path: path.into(),
+        reason: "signed and encrypted configs need --sql-schema document".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prepare_merges_fragments_and_keeps_references() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir(dir.join("teams")).unwrap();
+        std::fs::write(
+            dir.join("switchboard.yaml"),
+            "include: [teams/*.yaml]\n\
+             routes:\n  - path: /a\n    targets:\n      - url: http://a:80\n\
+             \x20   headers:\n      add:\n        Authorization: \"Bearer ${env:SB_PUSH_UNSET}\"\n\
+             profiles:\n  prod:\n    defaults:\n      timeout: 100\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("teams/b.yaml"),
+            "routes:\n  - path: /b\n    targets:\n      - url: \"http://${env:SB_PUSH_HOST:-b}:80\"\n",
+        )
+        .unwrap();
+
+        let config = prepare(&dir.join("switchboard.yaml")).unwrap();
+        assert!(config.include.is_empty());
+        assert!(config.profiles.contains_key("prod"));
+        assert_eq!(config.routes.len(), 2);
+        assert_eq!(
+            config.routes[1].targets[0].url,
+            "http://${env:SB_PUSH_HOST:-b}:80"
+        );
+        // Not set on the pushing host, and not needed there
+        assert_eq!(
+            config.routes[0].headers.add["Authorization"],
+            "Bearer ${env:SB_PUSH_UNSET}"
+        );
+
+        // The pushed JSON is what the database sources load
+        let json = to_json(&config).unwrap();
+        let loaded: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.routes.len(), 2);
+    }
+
+    #[test]
+    fn prepare_validates_each_profile() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(
+            dir.join("switchboard.yaml"),
+            "routes:\n  - path: /a\n    targets:\n      - url: http://a:80\n\
+             profiles:\n  broken:\n    routes:\n      - path: /a\n        targets: []\n",
+        )
+        .unwrap();
+
+        let err = prepare(&dir.join("switchboard.yaml")).unwrap_err();
+        assert!(err.to_string().contains("with profile 'broken'"), "{err}");
+    }
+}
@@ -62,13 +62,23 @@ pub async fn execute(args: HealthArgs) -> Result<(), SwitchboardError> {
     match serde_json::from_str::<HealthResponse>(&body_str) {
         Ok(health) => {
             let uptime = format_uptime(health.uptime_seconds);
-            println!("\u{2713} switchboard is healthy ({})", args.url);
+            if health.config.stale {
+                println!(
+                    "! switchboard is degraded ({}): serving the last known good config",
+                    args.url
+                );
+            } else {
+                println!("\u{2713} switchboard is healthy ({})", args.url);
+            }
             println!("  uptime:         {uptime}");
             println!("  config source:  {}", health.config.source);
             println!(
                 "  config version: {} (loaded {}s ago)",
                 health.config.version, health.config.loaded_ago_seconds
             );
+            if let Some(author) = &health.config.author {
+                println!("  config author:  {author}");
+            }
             println!(
                 "  routes:         {} routes, {} targets",
                 health.config.routes, health.config.targets
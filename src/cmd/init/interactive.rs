@@ -52,6 +52,10 @@ pub fn run(args: &InitArgs) -> Result<(), SwitchboardError> {
         actuator,
         defaults,
         routes,
+        include: Vec::new(),
+        profiles: Default::default(),
+        profile: None,
+        secrets: Default::default(),
     };
 
     // Validate the assembled config
@@ -179,7 +183,12 @@ fn prompt_defaults() -> Result<Defaults, SwitchboardError> {
         forward_headers,
         proxy_headers,
         strip_hop_by_hop,
+        forwarded_style: ForwardedStyle::default(),
+        trusted_proxies: Vec::new(),
         headers: HeaderRules::default(),
+        response_headers: HeaderRules::default(),
+        pool: PoolSettings::default(),
+        outbound_proxy: None,
     })
 }
 
@@ -264,7 +273,11 @@ fn prompt_single_route() -> Result<Route, SwitchboardError> {
         path,
         methods,
         timeout,
+        forwarded_style: None,
         headers: HeaderRules::default(),
+        response_headers: HeaderRules::default(),
+        hedge: None,
+        dedup: None,
         targets,
     })
 }
@@ -334,6 +347,10 @@ fn prompt_single_target(is_first: bool) -> Result<Target, SwitchboardError> {
         url,
         primary,
         timeout,
+        pool: None,
+        outbound_proxy: None,
+        ordering_key: None,
+        signing: None,
     })
 }
 
@@ -5,7 +5,7 @@
 //! - **Interactive mode** (`--interactive`): walks through a step-by-step wizard.
 
 mod interactive;
-mod serialize;
+pub(crate) mod serialize;
 mod template;
 
 use crate::cli::InitArgs;
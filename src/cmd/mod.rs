@@ -1,12 +1,23 @@
 //! Subcommand dispatch and execution.
 //!
 //! The [`dispatch`] function routes the parsed CLI to the appropriate
-//! subcommand handler: [`run`], [`init`], [`validate`], or [`health`].
+//! subcommand handler: [`run`], [`init`], [`validate`], [`health`], or
+//! [`config`].
 //! Each handler lives in its own submodule.
 
+#[cfg(any(
+    feature = "dynamodb",
+    feature = "redis",
+    feature = "postgres",
+    feature = "mongodb",
+    feature = "sqlite"
+))]
+pub mod config;
 pub mod health;
 pub mod init;
 pub mod run;
+#[cfg(feature = "schema")]
+pub mod schema;
 pub mod validate;
 
 use crate::cli::{Cli, Commands};
@@ -18,6 +29,16 @@ pub async fn dispatch(cli: Cli) -> Result<(), SwitchboardError> {
         Some(Commands::Init(ref args)) => init::execute(args),
         Some(Commands::Validate(ref args)) => validate::execute(args),
         Some(Commands::Health(args)) => health::execute(args).await,
+        #[cfg(any(
+            feature = "dynamodb",
+            feature = "redis",
+            feature = "postgres",
+            feature = "mongodb",
+            feature = "sqlite"
+        ))]
+        Some(Commands::Config(args)) => config::execute(*args).await,
+        #[cfg(feature = "schema")]
+        Some(Commands::Schema(ref args)) => schema::execute(args),
         None => {
             print_welcome();
             Ok(())
@@ -10,10 +10,16 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use crate::cli::RunArgs;
+use crate::config::diff::{self, ChangeLog, ConfigChange};
+use crate::config::snapshot::Snapshot;
 use crate::config::sources;
-use crate::config::{ConfigResolver, ConfigSource};
+use crate::config::sources::inline::InlineSource;
+use crate::config::{
+    unix_now, ChangeStream, ConfigResolver, ConfigSource, ConfigStore, ConfigVersion,
+};
 use crate::error::SwitchboardError;
 use crate::logging;
+use crate::rollback::{Counts, Probation, RollbackPolicy, Verdict};
 use crate::server::{self, AppState, LoadedConfig, Stats};
 
 pub async fn execute(args: RunArgs) -> Result<(), SwitchboardError> {
@@ -30,40 +36,47 @@ pub async fn execute(args: RunArgs) -> Result<(), SwitchboardError> {
         .as_ref()
         .map(|dsn| crate::sentry_integration::init(dsn, args.sentry_environment.as_deref()));
 
-    let resolver = resolve_config_sources(&args).await?;
-    let (mut config, version) = resolver.load_with_fallback().await?;
+    let config_store = open_config_store(&args).await?;
+    let resolver = resolve_config_sources(&args, config_store.clone()).await?;
+    let resolved = resolver.load_with_fallback().await?;
+    if !resolved.stale {
+        resolver
+            .save_snapshot(&resolved.config, &resolved.version, &resolved.source)
+            .await;
+    }
+    let mut config = resolved.config;
 
     // Apply CLI timeout override if it differs from the config default
     if args.timeout != config.defaults.timeout {
         config.defaults.timeout = args.timeout;
     }
 
-    // Apply env var overrides for actuator auth
-    if let Ok(username) = std::env::var("ACTUATOR_AUTH_USERNAME") {
-        config.actuator.auth.username = Some(username);
-    }
-    if let Ok(password) = std::env::var("ACTUATOR_AUTH_PASSWORD") {
-        config.actuator.auth.password = Some(password);
-    }
+    config.apply_actuator_auth_env();
 
     let route_count = config.routes.len();
     let target_count = config.total_targets();
 
-    let loaded_config = tokio::sync::RwLock::new(LoadedConfig {
-        config: Arc::new(config),
-        version,
-        source_name: resolver.primary_name().to_string(),
-        loaded_at: Instant::now(),
-    });
+    let dedup_store = build_dedup_store(&args).await?;
+
+    let loaded_config = tokio::sync::RwLock::new(LoadedConfig::new(
+        config,
+        resolved.version,
+        resolved.source,
+        resolved.stale,
+    ));
 
     #[cfg(feature = "actuator")]
     let state = Arc::new(AppState {
         config: loaded_config,
-        http_client: server::build_http_client(),
+        http_clients: server::HttpClients::new(),
+        ordered_queues: server::OrderedQueues::with_capacity(args.ordered_queue_limit),
+        dedup_store,
         start_time: Instant::now(),
         namespace: args.namespace.clone(),
         stats: Stats::new(),
+        config_changes: ChangeLog::new(args.config_changes_limit),
         log_reload_handle: Some(log_reload_handle),
+        config_store,
         current_log_level: tokio::sync::RwLock::new(
             format!("{}", args.log_level.to_tracing_level()).to_uppercase(),
         ),
@@ -72,10 +85,13 @@ pub async fn execute(args: RunArgs) -> Result<(), SwitchboardError> {
     #[cfg(not(feature = "actuator"))]
     let state = Arc::new(AppState {
         config: loaded_config,
-        http_client: server::build_http_client(),
+        http_clients: server::HttpClients::new(),
+        ordered_queues: server::OrderedQueues::with_capacity(args.ordered_queue_limit),
+        dedup_store,
         start_time: Instant::now(),
         namespace: args.namespace.clone(),
         stats: Stats::new(),
+        config_changes: ChangeLog::new(args.config_changes_limit),
     });
 
     // Shutdown signal: dropping shutdown_tx closes the channel and stops the refresh loop
@@ -84,8 +100,20 @@ pub async fn execute(args: RunArgs) -> Result<(), SwitchboardError> {
     // Spawn config refresh loop with cancellation
     let refresh_state = state.clone();
     let poll_interval = args.poll_interval;
+    let rollback = RollbackPolicy {
+        window: Duration::from_secs(args.rollback_window),
+        threshold: args.rollback_threshold,
+        min_requests: args.rollback_min_requests,
+    };
     let refresh_handle = tokio::spawn(async move {
-        config_refresh_loop(refresh_state, resolver, poll_interval, shutdown_rx).await;
+        config_refresh_loop(
+            refresh_state,
+            resolver,
+            poll_interval,
+            rollback,
+            shutdown_rx,
+        )
+        .await;
     });
 
     let router = server::build_router(state, args.max_body);
@@ -124,83 +152,134 @@ pub async fn execute(args: RunArgs) -> Result<(), SwitchboardError> {
     Ok(())
 }
 
-async fn resolve_config_sources(args: &RunArgs) -> Result<ConfigResolver, SwitchboardError> {
-    let mut primary: Option<Box<dyn ConfigSource>> = None;
-
-    // Check DB backends in priority order
-    #[cfg(feature = "dynamodb")]
-    if primary.is_none() {
-        if let Some(ref table) = args.dynamodb_table {
-            let source = sources::dynamodb::DynamoDbSource::new(
-                table,
-                &args.dynamodb_region,
-                &args.namespace,
-            )
-            .await?;
-            primary = Some(Box::new(source));
-        }
-    }
-
+/// The store backing route `dedup` windows: Redis when `--dedup-redis-url`
+/// is set, otherwise a bounded in-memory map.
+async fn build_dedup_store(
+    args: &RunArgs,
+) -> Result<Arc<dyn server::DedupStore>, SwitchboardError> {
     #[cfg(feature = "redis")]
-    if primary.is_none() {
-        if let Some(ref url) = args.redis_url {
-            let source = sources::redis_source::RedisSource::new(url, &args.namespace).await?;
-            primary = Some(Box::new(source));
-        }
+    if let Some(ref url) = args.dedup_redis_url {
+        let store = crate::proxy::dedup::RedisDedupStore::new(url, &args.namespace).await?;
+        return Ok(Arc::new(store));
     }
 
-    #[cfg(feature = "postgres")]
-    if primary.is_none() {
-        if let Some(ref url) = args.postgres_url {
-            let source = sources::postgres::PostgresSource::new(url, &args.namespace).await?;
-            primary = Some(Box::new(source));
-        }
+    Ok(Arc::new(server::MemoryDedupStore::new(
+        args.dedup_max_entries,
+    )))
+}
+
+/// The database backend selected by the `--*-url`/`--dynamodb-table`/
+/// `--sqlite-path` flags, if any.
+#[cfg(any(
+    feature = "dynamodb",
+    feature = "redis",
+    feature = "postgres",
+    feature = "mongodb",
+    feature = "sqlite"
+))]
+async fn open_config_store(
+    args: &RunArgs,
+) -> Result<Option<Arc<dyn ConfigStore>>, SwitchboardError> {
+    crate::cmd::config::open_store(&args.database, &args.namespace, args.profile.as_deref()).await
+}
+
+#[cfg(not(any(
+    feature = "dynamodb",
+    feature = "redis",
+    feature = "postgres",
+    feature = "mongodb",
+    feature = "sqlite"
+)))]
+#[allow(clippy::unused_async)]
+async fn open_config_store(
+    _args: &RunArgs,
+) -> Result<Option<Arc<dyn ConfigStore>>, SwitchboardError> {
+    Ok(None)
+}
+
+/// Every configured source, in priority order: a database backend, git,
+/// `--config-url`, then the local config.
+async fn resolve_config_sources(
+    args: &RunArgs,
+    store: Option<Arc<dyn ConfigStore>>,
+) -> Result<ConfigResolver, SwitchboardError> {
+    let mut chain: Vec<Box<dyn ConfigSource>> = Vec::new();
+
+    if let Some(store) = store {
+        chain.push(Box::new(store));
     }
 
-    #[cfg(feature = "mongodb")]
-    if primary.is_none() {
-        if let Some(ref url) = args.mongodb_url {
-            let source = sources::mongodb_source::MongoDbSource::new(url, &args.namespace).await?;
-            primary = Some(Box::new(source));
-        }
+    #[cfg(feature = "git")]
+    if let Some(ref repo) = args.git_repo {
+        chain.push(Box::new(
+            sources::git_source::GitSource::new(
+                repo,
+                &args.git_ref,
+                &args.git_path,
+                args.git_cache_dir.clone(),
+            )?
+            .with_profile(args.profile.clone()),
+        ));
     }
 
-    #[cfg(feature = "sqlite")]
-    if primary.is_none() {
-        if let Some(ref path) = args.sqlite_path {
-            let source = sources::sqlite::SqliteSource::new(path, &args.namespace).await?;
-            primary = Some(Box::new(source));
-        }
+    if let Some(ref url) = args.config_url {
+        let source = sources::http_source::HttpSource::new(url, config_url_auth(args))?
+            .with_profile(args.profile.clone());
+        chain.push(Box::new(source));
     }
 
-    // File-based source
-    let file_source = resolve_file_source(args.config.as_deref()).await?;
+    if let Some(source) = resolve_file_source(args).await? {
+        chain.push(source);
+    }
 
-    if let Some(source) = file_source {
-        if let Some(db_primary) = primary {
-            // DB is primary, file is fallback
-            return Ok(ConfigResolver::new(db_primary, Some(source)));
-        }
-        primary = Some(source);
+    if chain.is_empty() {
+        return Err(SwitchboardError::NoConfigSource {
+            hint: "Provide --config <file>, SWITCHBOARD_CONFIG, --config-url <url>, \
+                   or a database backend flag.\n  \
+                   Run 'switchboard init' to create a config file."
+                .into(),
+        });
     }
 
-    primary.map_or_else(
-        || {
-            Err(SwitchboardError::NoConfigSource {
-                hint: "Provide --config <file> or a database backend flag.\n  \
-                       Run 'switchboard init' to create a config file."
-                    .into(),
-            })
-        },
-        |p| Ok(ConfigResolver::new(p, None)),
-    )
+    let resolver = ConfigResolver::new(chain);
+    Ok(match args.config_cache {
+        Some(ref path) => resolver.with_snapshot(Snapshot::new(path.clone())),
+        None => resolver,
+    })
 }
 
+fn config_url_auth(args: &RunArgs) -> Option<sources::http_source::HttpAuth> {
+    use sources::http_source::HttpAuth;
+
+    if let Some(ref token) = args.config_url_token {
+        return Some(HttpAuth::Bearer(token.clone()));
+    }
+    args.config_url_basic_auth.as_ref().map(|credentials| {
+        let (username, password) = credentials.split_once(':').unwrap_or((credentials, ""));
+        HttpAuth::Basic {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    })
+}
+
+/// The local config: `--config` (a file, a directory, or `-` for stdin),
+/// else an inline `SWITCHBOARD_CONFIG`, else a file auto-detected in the
+/// current directory.
 async fn resolve_file_source(
-    explicit: Option<&std::path::Path>,
+    args: &RunArgs,
 ) -> Result<Option<Box<dyn ConfigSource>>, SwitchboardError> {
-    if let Some(path) = explicit {
-        return create_file_source(path).map(Some);
+    if let Some(ref path) = args.config {
+        if path.as_os_str() == "-" {
+            let source = InlineSource::from_stdin()?.with_profile(args.profile.clone());
+            return Ok(Some(Box::new(source)));
+        }
+        return create_file_source(path, args.profile.clone()).map(Some);
+    }
+
+    if let Some(ref content) = args.config_inline {
+        let source = InlineSource::from_env(content)?.with_profile(args.profile.clone());
+        return Ok(Some(Box::new(source)));
     }
 
     // Auto-detect in current directory
@@ -215,79 +294,242 @@ async fn resolve_file_source(
         let path = PathBuf::from(name);
         if tokio::fs::try_exists(&path).await.unwrap_or(false) {
             tracing::info!(path = %path.display(), "auto-detected config file");
-            return create_file_source(&path).map(Some);
+            return create_file_source(&path, args.profile.clone()).map(Some);
         }
     }
 
     Ok(None)
 }
 
-fn create_file_source(path: &std::path::Path) -> Result<Box<dyn ConfigSource>, SwitchboardError> {
+fn create_file_source(
+    path: &std::path::Path,
+    profile: Option<String>,
+) -> Result<Box<dyn ConfigSource>, SwitchboardError> {
+    if path.is_dir() {
+        let source = sources::file_source::FileSource::directory(path.to_path_buf());
+        return Ok(Box::new(source.with_profile(profile)));
+    }
+
     let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
 
-    match ext {
+    let source: sources::file_source::FileSource = match ext {
         #[cfg(feature = "yaml")]
-        "yaml" | "yml" => Ok(Box::new(sources::yaml::new(path.to_path_buf()))),
+        "yaml" | "yml" => sources::yaml::new(path.to_path_buf()),
 
         #[cfg(feature = "json")]
-        "json" => Ok(Box::new(sources::json::new(path.to_path_buf()))),
+        "json" => sources::json::new(path.to_path_buf()),
 
         #[cfg(feature = "toml")]
-        "toml" => Ok(Box::new(sources::toml_source::new(path.to_path_buf()))),
+        "toml" => sources::toml_source::new(path.to_path_buf()),
 
-        other => Err(SwitchboardError::UnsupportedFormat(other.to_string())),
-    }
+        other => return Err(SwitchboardError::UnsupportedFormat(other.to_string())),
+    };
+    Ok(Box::new(source.with_profile(profile)))
 }
 
 async fn config_refresh_loop(
     state: Arc<AppState>,
     resolver: ConfigResolver,
     interval_secs: u64,
+    policy: RollbackPolicy,
     mut shutdown: tokio::sync::watch::Receiver<bool>,
 ) {
     let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
     interval.tick().await; // Skip first immediate tick
 
+    // Push notifications from sources that can watch for changes, including
+    // fallbacks; polling continues as a fallback
+    let mut changes = resolver.watch().await;
+
+    // Request counters when the current config was loaded, the reload being
+    // watched for a rising failure rate, and the version last rolled back
+    let mut served_since = Counts::read(&state.stats);
+    let mut probation: Option<Probation> = None;
+    let mut rejected: Option<ConfigVersion> = None;
+
     loop {
         tokio::select! {
             _ = interval.tick() => {}
+            change = next_change(&mut changes) => {
+                if change.is_none() {
+                    tracing::warn!("config watch stopped, relying on polling");
+                    changes = None;
+                    continue;
+                }
+            }
+            () = next_probation_check(probation.is_some()) => {
+                let Some(watched) = probation.take() else {
+                    continue;
+                };
+                match watched.verdict(Counts::read(&state.stats), &policy) {
+                    Verdict::Pending => probation = Some(watched),
+                    Verdict::Passed => {
+                        let loaded = state.config.read().await.clone();
+                        tracing::info!(
+                            version = loaded.version.short(),
+                            "reloaded config passed the rollback window"
+                        );
+                        resolver
+                            .save_snapshot(&loaded.config, &loaded.version, &loaded.source_name)
+                            .await;
+                    }
+                    Verdict::RollBack { failure_rate } => {
+                        let bad = roll_back(&state, watched, failure_rate).await;
+                        served_since = Counts::read(&state.stats);
+                        rejected = Some(bad);
+                    }
+                }
+                continue;
+            }
             _ = shutdown.changed() => {
                 tracing::debug!("config refresh loop shutting down");
                 return;
             }
         }
 
-        let current_version = {
+        let (current_version, current_source, stale) = {
             let config = state.config.read().await;
-            config.version.clone()
+            (
+                config.version.clone(),
+                config.source_name.clone(),
+                config.stale,
+            )
         };
 
-        match resolver.primary().has_changed(&current_version).await {
-            Ok(true) => {
+        // While a fallback or the snapshot is served, retry the whole chain
+        // so that the primary takes over again once it recovers
+        let on_primary = !stale && current_source == resolver.primary_name();
+        if on_primary {
+            match resolver.primary().has_changed(&current_version).await {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    tracing::warn!(error = %e, "config change check failed");
+                    continue;
+                }
+            }
+        }
+
+        match resolver.load_live().await {
+            Ok(resolved) => {
+                if !on_primary
+                    && resolved.source == current_source
+                    && resolved.version == current_version
+                {
+                    continue;
+                }
+                if rejected.as_ref() == Some(&resolved.version) {
+                    tracing::debug!(
+                        version = resolved.version.short(),
+                        "source still serves the rolled back config, keeping current config"
+                    );
+                    continue;
+                }
+                rejected = None;
                 tracing::info!("config change detected, reloading");
-                match resolver.load_with_fallback().await {
-                    Ok((config, version)) => {
-                        let route_count = config.routes.len();
-                        let mut loaded = state.config.write().await;
-                        loaded.config = Arc::new(config);
-                        loaded.version = version;
-                        loaded.loaded_at = std::time::Instant::now();
-                        drop(loaded);
-                        state
-                            .stats
-                            .config_reloads
-                            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                        tracing::info!(routes = route_count, "config reloaded");
-                    }
-                    Err(e) => {
-                        tracing::error!(error = %e, "config reload failed, keeping current config");
+
+                let route_count = resolved.config.routes.len();
+                let reloaded =
+                    LoadedConfig::new(resolved.config, resolved.version, resolved.source, false);
+                let previous =
+                    std::mem::replace(&mut *state.config.write().await, reloaded.clone());
+                // Prune after the swap, so that requests starting from now on
+                // cannot bring back a client of the old config
+                state.http_clients.retain_for(&reloaded.config);
+                state
+                    .stats
+                    .config_reloads
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                tracing::info!(routes = route_count, "config reloaded");
+                record_change(&state, "reload", &previous, &reloaded);
+
+                let now = Counts::read(&state.stats);
+                if policy.enabled() {
+                    // A reload during the window is compared against the
+                    // config from before the window too
+                    match probation {
+                        Some(ref mut watched) => watched.restart(now),
+                        None => {
+                            probation =
+                                Some(Probation::new(previous, now.since(served_since), now));
+                        }
                     }
+                } else {
+                    resolver
+                        .save_snapshot(&reloaded.config, &reloaded.version, &reloaded.source_name)
+                        .await;
                 }
+                served_since = now;
+            }
+            Err(e) if on_primary => {
+                tracing::error!(error = %e, "config reload failed, keeping current config");
             }
-            Ok(false) => {}
             Err(e) => {
-                tracing::warn!(error = %e, "config change check failed");
+                tracing::warn!(error = %e, "no config source recovered, keeping current config");
             }
         }
     }
 }
+
+/// Restore the config from before `watched`'s reload, returning the
+/// version rolled back from.
+async fn roll_back(state: &AppState, watched: Probation, failure_rate: f64) -> ConfigVersion {
+    let mut restored = watched.previous;
+    restored.loaded_at = Instant::now();
+    let bad = std::mem::replace(&mut *state.config.write().await, restored.clone());
+    state.http_clients.retain_for(&restored.config);
+    state
+        .stats
+        .config_rollbacks
+        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    tracing::error!(
+        version = bad.version.short(),
+        restored = restored.version.short(),
+        failure_rate,
+        baseline = watched.baseline,
+        "config reload raised the failure rate, rolled back to the previous config; \
+         the new version is ignored until the source changes again"
+    );
+    record_change(state, "rollback", &bad, &restored);
+    bad.version
+}
+
+/// Log the semantic diff between two served configs and keep it for
+/// `/actuator/configchanges`.
+fn record_change(state: &AppState, kind: &'static str, old: &LoadedConfig, new: &LoadedConfig) {
+    let diff = diff::diff(&old.config, &new.config);
+    tracing::info!(
+        kind,
+        source = %new.source_name,
+        old_version = old.version.short(),
+        new_version = new.version.short(),
+        summary = %diff.summary(),
+        diff = %serde_json::to_string(&diff).unwrap_or_default(),
+        "config changed"
+    );
+    state.config_changes.push(ConfigChange {
+        at: unix_now(),
+        kind,
+        source: new.source_name.clone(),
+        old_version: old.version.short().to_string(),
+        new_version: new.version.short().to_string(),
+        diff,
+    });
+}
+
+/// Fires every second while a reload is on probation, never otherwise.
+async fn next_probation_check(watching: bool) {
+    if watching {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    } else {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// The next change notification, or never when the source is not watched.
+async fn next_change(changes: &mut Option<ChangeStream>) -> Option<()> {
+    match changes {
+        Some(changes) => changes.recv().await,
+        None => std::future::pending().await,
+    }
+}
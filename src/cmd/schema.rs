@@ -0,0 +1,21 @@
+//! `switchboard schema` — print the JSON Schema of the config file.
+//!
+//! Point an editor's YAML/TOML/JSON language server at the output to get
+//! completion and validation while editing configs.
+
+use crate::cli::SchemaArgs;
+use crate::config::schema::config_schema;
+use crate::error::SwitchboardError;
+
+pub fn execute(args: &SchemaArgs) -> Result<(), SwitchboardError> {
+    let schema = format!("{:#}", config_schema());
+
+    match &args.output {
+        Some(path) => {
+            std::fs::write(path, schema + "\n")?;
+            eprintln!("\u{2713} Wrote config schema to {}", path.display());
+        }
+        None => println!("{schema}"),
+    }
+    Ok(())
+}
@@ -1,63 +1,55 @@
 //! `switchboard validate` — check a configuration file for errors.
 //!
-//! Parses and validates the config file, reporting results in either
-//! human-readable text or machine-readable JSON format.
+//! Parses the config file (or directory) and any fragments it includes, or
+//! an inline config from `SWITCHBOARD_CONFIG` or stdin, resolves secret
+//! references, and validates the result, reporting results (with resolved
+//! secrets masked) in either human-readable text or machine-readable JSON
+//! format.
+
+use std::path::PathBuf;
 
 use crate::cli::{ValidateArgs, ValidateFormat};
-use crate::config::sources::parse_config_str;
+use crate::config::model::Config;
+use crate::config::sources::inline::InlineSource;
+use crate::config::sources::{include, parse_config_str, resolve_and_validate};
 use crate::config::validation;
-use crate::error::SwitchboardError;
+use crate::error::{SwitchboardError, ValidationError};
 
 pub fn execute(args: &ValidateArgs) -> Result<(), SwitchboardError> {
-    let path = &args.config;
-
-    if !path.exists() {
-        return Err(SwitchboardError::ConfigFileNotFound { path: path.clone() });
-    }
-
-    let content = std::fs::read_to_string(path)?;
-
-    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
-    let config = parse_config_str(ext, &content, &path.display().to_string())?;
-
-    if let Err(errors) = validation::validate(&config) {
-        match args.format {
-            ValidateFormat::Text => {
-                eprintln!("\u{2717} {} has {} errors\n", path.display(), errors.len());
-                for error in &errors {
-                    eprintln!("{error}");
-                }
-            }
-            ValidateFormat::Json => {
-                let json_errors: Vec<serde_json::Value> = errors
-                    .iter()
-                    .map(|e| {
-                        serde_json::json!({
-                            "route": e.route,
-                            "field": e.field,
-                            "message": e.message,
-                            "suggestion": e.suggestion,
-                        })
-                    })
-                    .collect();
-                println!(
-                    "{}",
-                    serde_json::json!({
-                        "valid": false,
-                        "errors": json_errors,
-                    })
-                );
+    let profile = args.profile.as_deref();
+    let (display, loaded) = match (&args.config, &args.config_inline) {
+        (Some(path), _) if path.as_os_str() == "-" => {
+            load_inline(InlineSource::from_stdin()?, profile)
+        }
+        (None, Some(content)) => load_inline(InlineSource::from_env(content)?, profile),
+        (path, _) => {
+            let path = path
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("switchboard.yaml"));
+            if !path.exists() {
+                return Err(SwitchboardError::ConfigFileNotFound { path });
             }
+            let display = path.display().to_string();
+            let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+            let parse_root = |content: &str| parse_config_str(ext, content, &display);
+            let loaded = include::load(&path, &parse_root, profile).map(|(config, _)| config);
+            (display, loaded)
         }
-        return Err(SwitchboardError::ConfigValidation { errors });
-    }
+    };
+
+    let config = match loaded {
+        Ok(config) => config,
+        Err(SwitchboardError::ConfigValidation { errors }) => {
+            report_errors(args, &display, &errors);
+            return Err(SwitchboardError::ConfigValidation { errors });
+        }
+        Err(e) => return Err(e),
+    };
 
     match args.format {
         ValidateFormat::Text => {
-            println!(
-                "\u{2713} {}",
-                validation::format_validation_report(&path.display().to_string(), &config)
-            );
+            let report = validation::format_validation_report(&display, &config);
+            println!("\u{2713} {}", config.secrets.mask(&report));
         }
         ValidateFormat::Json => {
             let total_targets = config.total_targets();
@@ -74,3 +66,45 @@ pub fn execute(args: &ValidateArgs) -> Result<(), SwitchboardError> {
 
     Ok(())
 }
+
+/// Parse and validate an inline config, returning its label for reports.
+fn load_inline(
+    source: InlineSource,
+    profile: Option<&str>,
+) -> (String, Result<Config, SwitchboardError>) {
+    let loaded = source
+        .parse()
+        .and_then(|config| resolve_and_validate(config, profile));
+    (source.label().to_string(), loaded)
+}
+
+fn report_errors(args: &ValidateArgs, display: &str, errors: &[ValidationError]) {
+    match args.format {
+        ValidateFormat::Text => {
+            eprintln!("\u{2717} {display} has {} errors\n", errors.len());
+            for error in errors {
+                eprintln!("{error}");
+            }
+        }
+        ValidateFormat::Json => {
+            let json_errors: Vec<serde_json::Value> = errors
+                .iter()
+                .map(|e| {
+                    serde_json::json!({
+                        "route": e.route,
+                        "field": e.field,
+                        "message": e.message,
+                        "suggestion": e.suggestion,
+                    })
+                })
+                .collect();
+            println!(
+                "{}",
+                serde_json::json!({
+                    "valid": false,
+                    "errors": json_errors,
+                })
+            );
+        }
+    }
+}
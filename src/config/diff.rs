@@ -0,0 +1,416 @@
+//! Semantic diffs between configs, and the log of recent config changes.
+//!
+//! [`diff`] compares two loaded configs the way an operator reads them:
+//! routes are matched by path and their targets by URL, so reordering
+//! either is not a change, and every other field is compared by its dotted
+//! name (`defaults.timeout`, `headers.add.X-Env`). Values resolved from
+//! secret references are masked. The refresh loop logs the diff of every
+//! reload and rollback and keeps the most recent ones in a [`ChangeLog`],
+//! served at `/actuator/configchanges`.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use super::model::Config;
+use super::serialize_utc;
+
+/// What changed between two configs.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ConfigDiff {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub routes_added: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub routes_removed: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub routes_modified: Vec<RouteDiff>,
+    /// Changes under `defaults`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub defaults: Vec<FieldChange>,
+    /// Changes to the other top-level sections, such as `actuator`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub other: Vec<FieldChange>,
+}
+
+/// Changes to a route present in both configs.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RouteDiff {
+    pub path: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub targets_added: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub targets_removed: Vec<String>,
+    /// Changes to the route's fields, and to its targets' fields as
+    /// `targets[<url>].<field>`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<FieldChange>,
+}
+
+/// A field that was added, removed, or changed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<Value>,
+}
+
+impl ConfigDiff {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// A one-line summary, e.g. `1 route added, 2 routes modified`.
+    #[must_use]
+    pub fn summary(&self) -> String {
+        let count = |n: usize, what: &str| match n {
+            0 => None,
+            1 => Some(format!("1 route {what}")),
+            n => Some(format!("{n} routes {what}")),
+        };
+        let parts: Vec<String> = [
+            count(self.routes_added.len(), "added"),
+            count(self.routes_removed.len(), "removed"),
+            count(self.routes_modified.len(), "modified"),
+            (!self.defaults.is_empty()).then(|| "defaults changed".to_string()),
+            (!self.other.is_empty()).then(|| "settings changed".to_string()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if parts.is_empty() {
+            "no changes".into()
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+/// The changes from `old` to `new`.
+#[must_use]
+pub fn diff(old: &Config, new: &Config) -> ConfigDiff {
+    let mut old = masked(old);
+    let mut new = masked(new);
+    let mut result = ConfigDiff::default();
+
+    let old_routes = keyed(take(&mut old, "routes"), "path");
+    let new_routes = keyed(take(&mut new, "routes"), "path");
+    for (path, new_route) in &new_routes {
+        match old_routes.get(path) {
+            None => result.routes_added.push(path.clone()),
+            Some(old_route) => {
+                if let Some(route) = diff_route(path, old_route.clone(), new_route.clone()) {
+                    result.routes_modified.push(route);
+                }
+            }
+        }
+    }
+    result.routes_removed = old_routes
+        .keys()
+        .filter(|path| !new_routes.contains_key(*path))
+        .cloned()
+        .collect();
+
+    diff_values(
+        "defaults",
+        &take(&mut old, "defaults"),
+        &take(&mut new, "defaults"),
+        &mut result.defaults,
+    );
+    diff_values("", &old, &new, &mut result.other);
+    result
+}
+
+fn diff_route(path: &str, mut old: Value, mut new: Value) -> Option<RouteDiff> {
+    let old_targets = keyed(take(&mut old, "targets"), "url");
+    let new_targets = keyed(take(&mut new, "targets"), "url");
+
+    let mut route = RouteDiff {
+        path: path.to_string(),
+        targets_added: Vec::new(),
+        targets_removed: Vec::new(),
+        changes: Vec::new(),
+    };
+    diff_values("", &old, &new, &mut route.changes);
+    for (url, new_target) in &new_targets {
+        match old_targets.get(url) {
+            None => route.targets_added.push(url.clone()),
+            Some(old_target) => diff_values(
+                &format!("targets[{url}]"),
+                old_target,
+                new_target,
+                &mut route.changes,
+            ),
+        }
+    }
+    route.targets_removed = old_targets
+        .keys()
+        .filter(|url| !new_targets.contains_key(*url))
+        .cloned()
+        .collect();
+
+    let unchanged = route.targets_added.is_empty()
+        && route.targets_removed.is_empty()
+        && route.changes.is_empty();
+    (!unchanged).then_some(route)
+}
+
+/// Record the differences between `old` and `new` under `prefix`: objects
+/// are compared key by key (a missing or null object counting as empty),
+/// anything else as a whole.
+fn diff_values(prefix: &str, old: &Value, new: &Value, out: &mut Vec<FieldChange>) {
+    if old == new {
+        return;
+    }
+    let empty = Map::new();
+    let (old_fields, new_fields) = match (old, new) {
+        (Value::Object(old), Value::Object(new)) => (old, new),
+        (Value::Null, Value::Object(new)) => (&empty, new),
+        (Value::Object(old), Value::Null) => (old, &empty),
+        _ => {
+            out.push(FieldChange {
+                field: prefix.to_string(),
+                old: Some(old.clone()).filter(|v| !v.is_null()),
+                new: Some(new.clone()).filter(|v| !v.is_null()),
+            });
+            return;
+        }
+    };
+
+    let mut keys: Vec<&String> = old_fields.keys().chain(new_fields.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        let field = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+        diff_values(
+            &field,
+            old_fields.get(key).unwrap_or(&Value::Null),
+            new_fields.get(key).unwrap_or(&Value::Null),
+            out,
+        );
+    }
+}
+
+/// The config as JSON, with resolved secret values masked.
+fn masked(config: &Config) -> Value {
+    let mut value = serde_json::to_value(config).unwrap_or_default();
+    config.secrets.mask_json(&mut value);
+    value
+}
+
+fn take(value: &mut Value, key: &str) -> Value {
+    value
+        .as_object_mut()
+        .and_then(|object| object.remove(key))
+        .unwrap_or(Value::Null)
+}
+
+/// The entries of a JSON array by their `key` field. Repeated keys are
+/// told apart by a `#n` suffix.
+fn keyed(list: Value, key: &str) -> BTreeMap<String, Value> {
+    let mut entries = BTreeMap::new();
+    let Value::Array(list) = list else {
+        return entries;
+    };
+    for entry in list {
+        let name = entry
+            .get(key)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let mut unique = name.clone();
+        let mut n = 1;
+        while entries.contains_key(&unique) {
+            n += 1;
+            unique = format!("{name}#{n}");
+        }
+        entries.insert(unique, entry);
+    }
+    entries
+}
+
+/// A config change applied by the refresh loop.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigChange {
+    /// When the change was applied, in seconds since the Unix epoch.
+    #[serde(serialize_with = "serialize_utc")]
+    pub at: u64,
+    /// `reload` or `rollback`.
+    pub kind: &'static str,
+    pub source: String,
+    pub old_version: String,
+    pub new_version: String,
+    pub diff: ConfigDiff,
+}
+
+/// The most recent config changes, newest first.
+#[derive(Debug)]
+pub struct ChangeLog {
+    limit: usize,
+    changes: Mutex<VecDeque<ConfigChange>>,
+}
+
+impl ChangeLog {
+    #[must_use]
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            changes: Mutex::new(VecDeque::with_capacity(limit)),
+        }
+    }
+
+    pub fn push(&self, change: ConfigChange) {
+        if self.limit == 0 {
+            return;
+        }
+        let mut changes = self.changes.lock().unwrap();
+        changes.truncate(self.limit - 1);
+        changes.push_front(change);
+    }
+
+    #[must_use]
+    pub fn recent(&self) -> Vec<ConfigChange> {
+        self.changes.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+impl Default for ChangeLog {
+    fn default() -> Self {
+        Self::new(50)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::secrets::interpolate;
+
+    fn config(json: &str) -> Config {
+        interpolate(serde_json::from_str(json).unwrap()).unwrap()
+    }
+
+    fn change(field: &str, old: Option<Value>, new: Option<Value>) -> FieldChange {
+        FieldChange {
+            field: field.into(),
+            old,
+            new,
+        }
+    }
+
+    #[test]
+    fn reordering_is_not_a_change() {
+        let old = config(
+            r#"{"routes": [
+                {"path": "/a", "targets": [{"url": "http://a:80"}, {"url": "http://b:80"}]},
+                {"path": "/b", "targets": [{"url": "http://b:80"}]}
+            ]}"#,
+        );
+        let new = config(
+            r#"{"routes": [
+                {"path": "/b", "targets": [{"url": "http://b:80"}]},
+                {"path": "/a", "targets": [{"url": "http://b:80"}, {"url": "http://a:80"}]}
+            ]}"#,
+        );
+        let diff = diff(&old, &new);
+        assert!(diff.is_empty(), "{diff:?}");
+        assert_eq!(diff.summary(), "no changes");
+    }
+
+    #[test]
+    fn reports_routes_targets_headers_and_defaults() {
+        let old = config(
+            r#"{"defaults": {"timeout": 1000},
+                "routes": [
+                    {"path": "/a", "targets": [{"url": "http://a:80", "timeout": 100}]},
+                    {"path": "/gone", "targets": [{"url": "http://g:80"}]}
+                ]}"#,
+        );
+        let new = config(
+            r#"{"defaults": {"timeout": 2000},
+                "actuator": {"enabled": true},
+                "routes": [
+                    {"path": "/a",
+                     "headers": {"add": {"X-Env": "prod"}},
+                     "targets": [{"url": "http://a:80", "timeout": 200}, {"url": "http://typo:80"}]},
+                    {"path": "/new", "targets": [{"url": "http://n:80"}]}
+                ]}"#,
+        );
+
+        let diff = diff(&old, &new);
+        assert_eq!(diff.routes_added, ["/new"]);
+        assert_eq!(diff.routes_removed, ["/gone"]);
+        assert_eq!(
+            diff.routes_modified,
+            [RouteDiff {
+                path: "/a".into(),
+                targets_added: vec!["http://typo:80".into()],
+                targets_removed: vec![],
+                changes: vec![
+                    change("headers.add.X-Env", None, Some("prod".into())),
+                    change(
+                        "targets[http://a:80].timeout",
+                        Some(100.into()),
+                        Some(200.into())
+                    ),
+                ],
+            }]
+        );
+        assert_eq!(
+            diff.defaults,
+            [change(
+                "defaults.timeout",
+                Some(1000.into()),
+                Some(2000.into())
+            )]
+        );
+        assert_eq!(
+            diff.other,
+            [change("actuator.enabled", None, Some(true.into()))]
+        );
+        assert_eq!(
+            diff.summary(),
+            "1 route added, 1 route removed, 1 route modified, defaults changed, settings changed"
+        );
+    }
+
+    #[test]
+    fn masks_resolved_secrets() {
+        // Safety: env var mutation is unsafe since Rust 1.66 due to thread-safety
+        // concerns. Acceptable here because no other test reads this var.
+        unsafe { std::env::set_var("SWITCHBOARD_TEST_DIFF_TOKEN", "hunter2") };
+        let old = config(r#"{"routes": [{"path": "/a", "targets": [{"url": "http://a:80"}]}]}"#);
+        let new = config(
+            r#"{"routes": [{"path": "/a",
+                "headers": {"add": {"Authorization": "Bearer ${env:SWITCHBOARD_TEST_DIFF_TOKEN}"}},
+                "targets": [{"url": "http://a:80"}]}]}"#,
+        );
+        let json = serde_json::to_string(&diff(&old, &new)).unwrap();
+        assert!(!json.contains("hunter2"), "{json}");
+        assert!(json.contains("Bearer ******"), "{json}");
+    }
+
+    #[test]
+    fn change_log_keeps_the_latest() {
+        let log = ChangeLog::new(2);
+        for version in ["a", "b", "c"] {
+            log.push(ConfigChange {
+                at: 0,
+                kind: "reload",
+                source: "yaml".into(),
+                old_version: String::new(),
+                new_version: version.into(),
+                diff: ConfigDiff::default(),
+            });
+        }
+        let versions: Vec<_> = log.recent().into_iter().map(|c| c.new_version).collect();
+        assert_eq!(versions, ["c", "b"]);
+    }
+}
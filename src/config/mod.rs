@@ -1,23 +1,61 @@
 //! Configuration loading, validation, and hot-reloading.
 //!
-//! Defines the [`ConfigSource`] trait for pluggable config backends,
-//! the [`ConfigResolver`] for primary/fallback source resolution, and
+//! Defines the [`ConfigSource`] trait for pluggable config backends
+//! (and [`ConfigStore`] for the versioned, writable ones),
+//! the [`ConfigResolver`] for resolving a chain of sources, and
 //! the [`ConfigVersion`] enum for change detection. Submodules provide
-//! the data model, validation logic, and concrete source implementations.
+//! the data model, validation logic, profile overlays, secret
+//! interpolation, signed and encrypted payloads, and concrete source
+//! implementations.
 
+pub mod diff;
 pub mod model;
+pub mod payload;
+pub mod profiles;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod secrets;
+pub mod snapshot;
 pub mod sources;
 pub mod validation;
 
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 
 use crate::error::SwitchboardError;
 use model::Config;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Notifications that a source may have changed; see [`ConfigSource::watch`].
+pub type ChangeStream = tokio::sync::mpsc::Receiver<()>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[non_exhaustive]
 pub enum ConfigVersion {
     Hash(String),
+    /// The git commit the config was read from.
+    Commit {
+        sha: String,
+        author: String,
+    },
+}
+
+impl ConfigVersion {
+    /// A short form for display: the first 8 characters of the hash or SHA.
+    #[must_use]
+    pub fn short(&self) -> &str {
+        match self {
+            Self::Hash(id) | Self::Commit { sha: id, .. } => id.get(..8).unwrap_or(id),
+        }
+    }
+
+    /// The commit SHA and author, for versions read from git.
+    #[must_use]
+    pub fn commit(&self) -> Option<(&str, &str)> {
+        match self {
+            Self::Hash(_) => None,
+            Self::Commit { sha, author } => Some((sha, author)),
+        }
+    }
 }
 
 // async_trait is required here because ConfigSource is used as Box<dyn ConfigSource>
@@ -27,45 +65,451 @@ pub trait ConfigSource: Send + Sync {
     fn name(&self) -> &'static str;
     async fn load(&self) -> Result<(Config, ConfigVersion), SwitchboardError>;
     async fn has_changed(&self, current: &ConfigVersion) -> Result<bool, SwitchboardError>;
+
+    /// Push notifications of possible changes, for sources that can watch
+    /// for them. The refresh loop re-checks [`has_changed`](Self::has_changed)
+    /// on each notification and keeps polling as a fallback.
+    async fn watch(&self) -> Result<Option<ChangeStream>, SwitchboardError> {
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl<T: ConfigSource + ?Sized> ConfigSource for std::sync::Arc<T> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    async fn load(&self) -> Result<(Config, ConfigVersion), SwitchboardError> {
+        (**self).load().await
+    }
+
+    async fn has_changed(&self, current: &ConfigVersion) -> Result<bool, SwitchboardError> {
+        (**self).has_changed(current).await
+    }
+
+    async fn watch(&self) -> Result<Option<ChangeStream>, SwitchboardError> {
+        (**self).watch().await
+    }
+}
+
+/// A [`ConfigSource`] that stores configs as numbered versions, one of
+/// which is active, as used by `switchboard config push`, `history` and
+/// `rollback`. The stored form is the JSON document the source parses on
+/// load.
+#[async_trait]
+pub trait ConfigStore: ConfigSource {
+    /// The active config JSON, or `None` if nothing is stored yet.
+    async fn read_json(&self) -> Result<Option<StoredJson>, SwitchboardError>;
+
+    /// Store `json` as a new version and make it active, returning its
+    /// version number. Version numbers increase with every push. The JSON
+    /// is sealed with the source's [`PayloadKeys`](payload::PayloadKeys)
+    /// for the version it is stored as.
+    async fn push_json(&self, json: &str, meta: &VersionMeta) -> Result<u64, SwitchboardError>;
+
+    /// Whether versions are kept, so that [`history`](Self::history) and
+    /// [`activate`](Self::activate) are supported.
+    fn keeps_history(&self) -> bool {
+        true
+    }
+
+    /// Every stored version, newest first.
+    async fn history(&self) -> Result<Vec<StoredVersion>, SwitchboardError>;
+
+    /// Make a stored version active again.
+    async fn activate(&self, version: u64) -> Result<(), SwitchboardError>;
+}
+
+/// The active config of a [`ConfigStore`], as stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredJson {
+    pub json: String,
+    /// The number of the active version, if the store has one recorded.
+    pub version: Option<u64>,
+}
+
+/// Who pushed a config version, and why.
+#[derive(Debug, Clone, Default)]
+pub struct VersionMeta {
+    pub author: String,
+    pub comment: Option<String>,
+}
+
+/// A config version kept by a [`ConfigStore`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StoredVersion {
+    pub version: u64,
+    pub author: String,
+    /// When the version was pushed, in seconds since the Unix epoch.
+    #[serde(serialize_with = "serialize_utc")]
+    pub created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    pub active: bool,
+}
+
+impl StoredVersion {
+    /// `created_at` as an RFC 3339 UTC timestamp.
+    #[must_use]
+    pub fn created_at_utc(&self) -> String {
+        utc_timestamp(self.created_at)
+    }
+}
+
+fn serialize_utc<S: serde::Serializer>(secs: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&utc_timestamp(*secs))
 }
 
+/// Seconds since the Unix epoch as `YYYY-MM-DDTHH:MM:SSZ`.
+fn utc_timestamp(secs: u64) -> String {
+    // Civil-from-days, after Howard Hinnant's date algorithms
+    let days = secs / 86_400;
+    let rem = secs % 86_400;
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// The current time in seconds since the Unix epoch, for version stamps.
+#[must_use]
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// An ordered chain of config sources, tried in turn until one loads,
+/// with an optional last-known-good [`Snapshot`](snapshot::Snapshot)
+/// behind them.
 pub struct ConfigResolver {
-    primary: Box<dyn ConfigSource>,
-    fallback: Option<Box<dyn ConfigSource>>,
+    sources: Vec<Box<dyn ConfigSource>>,
+    snapshot: Option<snapshot::Snapshot>,
+}
+
+/// A config loaded by a [`ConfigResolver`].
+#[derive(Debug)]
+pub struct Resolved {
+    pub config: Config,
+    pub version: ConfigVersion,
+    /// The name of the source the config came from.
+    pub source: String,
+    /// Whether the config is the last-known-good snapshot, served because
+    /// no live source could be loaded.
+    pub stale: bool,
 }
 
 impl ConfigResolver {
+    /// A resolver over `sources`, highest priority first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sources` is empty.
     #[must_use]
-    pub fn new(primary: Box<dyn ConfigSource>, fallback: Option<Box<dyn ConfigSource>>) -> Self {
-        Self { primary, fallback }
-    }
-
-    pub async fn load_with_fallback(&self) -> Result<(Config, ConfigVersion), SwitchboardError> {
-        match self.primary.load().await {
-            Ok(result) => Ok(result),
-            Err(primary_err) => {
-                if let Some(ref fallback) = self.fallback {
-                    tracing::warn!(
-                        primary = self.primary.name(),
-                        fallback = fallback.name(),
-                        error = %primary_err,
-                        "primary config source failed, using fallback"
-                    );
-                    fallback.load().await
-                } else {
-                    Err(primary_err)
-                }
+    pub fn new(sources: Vec<Box<dyn ConfigSource>>) -> Self {
+        assert!(!sources.is_empty(), "a config resolver needs a source");
+        Self {
+            sources,
+            snapshot: None,
+        }
+    }
+
+    /// Serve `snapshot` at startup if no source loads; see
+    /// [`save_snapshot`](Self::save_snapshot).
+    #[must_use]
+    pub fn with_snapshot(mut self, snapshot: snapshot::Snapshot) -> Self {
+        self.snapshot = Some(snapshot);
+        self
+    }
+
+    /// Load from the first source that succeeds. Returns the last source's
+    /// error if all of them fail.
+    pub async fn load_live(&self) -> Result<Resolved, SwitchboardError> {
+        for (source, next) in self.sources.iter().zip(&self.sources[1..]) {
+            match self.load_from(&**source).await {
+                Ok(resolved) => return Ok(resolved),
+                Err(e) => tracing::warn!(
+                    source = source.name(),
+                    fallback = next.name(),
+                    error = %e,
+                    "config source failed, using fallback"
+                ),
+            }
+        }
+        self.load_from(&*self.sources[self.sources.len() - 1]).await
+    }
+
+    async fn load_from(&self, source: &dyn ConfigSource) -> Result<Resolved, SwitchboardError> {
+        let (config, version) = source.load().await?;
+        Ok(Resolved {
+            config,
+            version,
+            source: source.name().to_string(),
+            stale: false,
+        })
+    }
+
+    /// Record a config loaded from `source` as the last known good one.
+    /// Failures are logged, and without a snapshot this does nothing.
+    pub async fn save_snapshot(&self, config: &Config, version: &ConfigVersion, source: &str) {
+        let Some(ref snapshot) = self.snapshot else {
+            return;
+        };
+        if let Err(e) = snapshot.save(config, version, source).await {
+            tracing::warn!(
+                path = %snapshot.path().display(),
+                error = %e,
+                "failed to save config snapshot"
+            );
+        }
+    }
+
+    /// Load from the first live source that succeeds, else from the
+    /// snapshot. Returns the last live source's error if the snapshot
+    /// cannot be loaded either.
+    pub async fn load_with_fallback(&self) -> Result<Resolved, SwitchboardError> {
+        let live_err = match self.load_live().await {
+            Ok(resolved) => return Ok(resolved),
+            Err(e) => e,
+        };
+        let Some(ref snapshot) = self.snapshot else {
+            return Err(live_err);
+        };
+
+        match snapshot.load().await {
+            Ok(restored) => {
+                tracing::warn!(
+                    error = %live_err,
+                    path = %snapshot.path().display(),
+                    source = %restored.source,
+                    saved_at = %utc_timestamp(restored.saved_at),
+                    "no config source could be loaded, serving the last known good config"
+                );
+                Ok(Resolved {
+                    config: restored.config,
+                    version: restored.version,
+                    source: restored.source,
+                    stale: true,
+                })
+            }
+            Err(e) => {
+                tracing::warn!(
+                    path = %snapshot.path().display(),
+                    error = %e,
+                    "cannot load config snapshot"
+                );
+                Err(live_err)
             }
         }
     }
 
+    /// Change notifications from every source that can watch for them,
+    /// merged into one stream, or `None` if none can. A source that fails
+    /// to start watching is logged and left to polling.
+    pub async fn watch(&self) -> Option<ChangeStream> {
+        let mut streams = Vec::new();
+        for source in &self.sources {
+            match source.watch().await {
+                Ok(Some(stream)) => streams.push(stream),
+                Ok(None) => {}
+                Err(e) => tracing::warn!(
+                    source = source.name(),
+                    error = %e,
+                    "cannot watch config source, relying on polling"
+                ),
+            }
+        }
+        if streams.len() <= 1 {
+            return streams.pop();
+        }
+
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        for mut stream in streams {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::select! {
+                        () = tx.closed() => return,
+                        change = stream.recv() => {
+                            if change.is_none() {
+                                return;
+                            }
+                            // A notification already pending covers this one
+                            let _ = tx.try_send(());
+                        }
+                    }
+                }
+            });
+        }
+        Some(rx)
+    }
+
     #[must_use]
     pub fn primary_name(&self) -> &str {
-        self.primary.name()
+        self.sources[0].name()
     }
 
     #[must_use]
     pub fn primary(&self) -> &dyn ConfigSource {
-        &*self.primary
+        &*self.sources[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A source serving a config with one route at `path`, or failing.
+    struct Stub {
+        name: &'static str,
+        path: Option<&'static str>,
+    }
+
+    #[async_trait]
+    impl ConfigSource for Stub {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn load(&self) -> Result<(Config, ConfigVersion), SwitchboardError> {
+            let path = self.path.ok_or_else(|| SwitchboardError::HttpRequest {
+                source: format!("{} is down", self.name).into(),
+            })?;
+            let json = format!(
+                r#"{{"routes":[{{"path":"{path}","targets":[{{"url":"http://a:80"}}]}}]}}"#
+            );
+            Ok((
+                serde_json::from_str(&json).unwrap(),
+                ConfigVersion::Hash(path.into()),
+            ))
+        }
+
+        async fn has_changed(&self, _: &ConfigVersion) -> Result<bool, SwitchboardError> {
+            Ok(false)
+        }
+    }
+
+    /// A source that cannot load, only report changes.
+    struct Notifying(std::sync::Mutex<Option<ChangeStream>>);
+
+    #[async_trait]
+    impl ConfigSource for Notifying {
+        fn name(&self) -> &'static str {
+            "notifying"
+        }
+
+        async fn load(&self) -> Result<(Config, ConfigVersion), SwitchboardError> {
+            Err(SwitchboardError::HttpRequest {
+                source: "notifying is down".into(),
+            })
+        }
+
+        async fn has_changed(&self, _: &ConfigVersion) -> Result<bool, SwitchboardError> {
+            Ok(false)
+        }
+
+        async fn watch(&self) -> Result<Option<ChangeStream>, SwitchboardError> {
+            Ok(self.0.lock().unwrap().take())
+        }
+    }
+
+    fn chain(sources: &[(&'static str, Option<&'static str>)]) -> ConfigResolver {
+        ConfigResolver::new(
+            sources
+                .iter()
+                .map(|&(name, path)| Box::new(Stub { name, path }) as Box<dyn ConfigSource>)
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn resolver_uses_the_first_source_that_loads() {
+        let resolver = chain(&[("db", None), ("git", Some("/git")), ("file", Some("/file"))]);
+        let resolved = resolver.load_with_fallback().await.unwrap();
+        assert_eq!(resolved.source, "git");
+        assert_eq!(resolved.config.routes[0].path, "/git");
+        assert!(!resolved.stale);
+
+        let err = chain(&[("db", None), ("git", None)])
+            .load_live()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("git is down"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn resolver_serves_the_snapshot_when_every_source_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("last-good.json");
+
+        // Without a snapshot saved yet, the live error is returned
+        let down = || {
+            chain(&[("db", None), ("file", None)])
+                .with_snapshot(snapshot::Snapshot::new(path.clone()))
+        };
+        let err = down().load_with_fallback().await.unwrap_err();
+        assert!(err.to_string().contains("file is down"), "{err}");
+
+        let up = chain(&[("db", Some("/db"))]).with_snapshot(snapshot::Snapshot::new(path.clone()));
+        let resolved = up.load_with_fallback().await.unwrap();
+        up.save_snapshot(&resolved.config, &resolved.version, &resolved.source)
+            .await;
+
+        let resolved = down().load_with_fallback().await.unwrap();
+        assert_eq!(resolved.source, "db");
+        assert_eq!(resolved.config.routes[0].path, "/db");
+        assert!(resolved.stale);
+
+        // Only startup falls back to the snapshot
+        assert!(down().load_live().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn resolver_merges_change_notifications_of_every_source() {
+        use tokio::time::error::Elapsed;
+
+        let (primary_tx, primary_rx) = tokio::sync::mpsc::channel(1);
+        let (fallback_tx, fallback_rx) = tokio::sync::mpsc::channel(1);
+        let notifying = |rx| Box::new(Notifying(std::sync::Mutex::new(Some(rx))));
+        let resolver = ConfigResolver::new(vec![
+            notifying(primary_rx),
+            Box::new(Stub {
+                name: "git",
+                path: Some("/git"),
+            }),
+            notifying(fallback_rx),
+        ]);
+        let mut changes = resolver.watch().await.unwrap();
+        async fn next(changes: &mut ChangeStream) -> Result<Option<()>, Elapsed> {
+            tokio::time::timeout(std::time::Duration::from_secs(1), changes.recv()).await
+        }
+
+        fallback_tx.send(()).await.unwrap();
+        assert_eq!(next(&mut changes).await, Ok(Some(())));
+        primary_tx.send(()).await.unwrap();
+        assert_eq!(next(&mut changes).await, Ok(Some(())));
+
+        // The merged stream ends once every source's stream has
+        drop((primary_tx, fallback_tx));
+        assert_eq!(next(&mut changes).await, Ok(None));
+    }
+
+    #[test]
+    fn utc_timestamps() {
+        assert_eq!(utc_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(utc_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(utc_timestamp(1_767_225_599), "2025-12-31T23:59:59Z");
     }
 }
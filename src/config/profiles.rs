@@ -0,0 +1,247 @@
+//! Environment profiles layered onto a base config.
+//!
+//! A config may define named overlays under `profiles:`; the one selected
+//! with `--profile`/`SWITCHBOARD_PROFILE` is deep-merged onto the rest of
+//! the config when it is loaded or reloaded, before secret references are
+//! resolved and the result is validated. Merge rules:
+//!
+//! - maps are merged key by key, recursively;
+//! - `routes` are matched by `path`: a matching route is merged into the
+//!   base route, any other route is appended;
+//! - every other list (`targets`, `methods`, `trusted_proxies`, ...) and
+//!   every scalar replaces the base value;
+//! - `null` removes the base value, restoring its default.
+
+use serde_json::{Map, Value};
+
+use crate::config::model::Config;
+use crate::error::{SwitchboardError, ValidationError};
+
+/// Merge the overlay named `profile` onto `config`, recording its name in
+/// [`Config::profile`]. The `profiles` map is dropped from the result
+/// either way. Every overlay, selected or not, must merge into a valid
+/// config shape, so a typo in a profile is caught before it is deployed.
+pub fn apply(mut config: Config, profile: Option<&str>) -> Result<Config, SwitchboardError> {
+    let profiles = std::mem::take(&mut config.profiles);
+    if profiles.is_empty() && profile.is_none() {
+        return Ok(config);
+    }
+    if let Some(name) = profile.filter(|name| !profiles.contains_key(*name)) {
+        let available: Vec<&str> = profiles.keys().map(String::as_str).collect();
+        return Err(SwitchboardError::ConfigValidation {
+            errors: vec![profile_error(
+                name,
+                format!("profile '{name}' is not defined in the config"),
+                if available.is_empty() {
+                    Some("add it under 'profiles:' or unset --profile/SWITCHBOARD_PROFILE".into())
+                } else {
+                    Some(format!("defined profiles: {}", available.join(", ")))
+                },
+            )],
+        });
+    }
+
+    let base = serde_json::to_value(&config).map_err(|e| SwitchboardError::ConfigParse {
+        path: "config".into(),
+        source: Box::new(e),
+    })?;
+    let mut selected = None;
+    let mut errors = Vec::new();
+    for (name, overlay) in profiles {
+        match merge(base.clone(), &name, overlay) {
+            Ok(merged) if profile == Some(name.as_str()) => selected = Some(merged),
+            Ok(_) => {}
+            Err(error) => errors.push(error),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(SwitchboardError::ConfigValidation { errors });
+    }
+
+    let Some(mut merged) = selected else {
+        return Ok(config);
+    };
+    merged.secrets = config.secrets;
+    merged.profile = profile.map(str::to_string);
+    Ok(merged)
+}
+
+/// Merge one overlay onto the serialized base config.
+fn merge(mut base: Value, name: &str, overlay: Value) -> Result<Config, ValidationError> {
+    let Value::Object(overlay) = overlay else {
+        return Err(profile_error(
+            name,
+            "a profile must be a map of config settings".into(),
+            None,
+        ));
+    };
+    for key in ["profiles", "include"] {
+        if overlay.contains_key(key) {
+            return Err(profile_error(
+                name,
+                format!("'{key}' cannot be set inside a profile"),
+                None,
+            ));
+        }
+    }
+
+    if let Value::Object(base) = &mut base {
+        merge_maps(base, overlay, true);
+    }
+    serde_json::from_value(base).map_err(|e| profile_error(name, e.to_string(), None))
+}
+
+fn merge_maps(base: &mut Map<String, Value>, overlay: Map<String, Value>, root: bool) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (_, Value::Null) => {
+                base.remove(&key);
+            }
+            (Some(Value::Object(base)), Value::Object(overlay)) => {
+                merge_maps(base, overlay, false);
+            }
+            (Some(Value::Array(base)), Value::Array(overlay)) if root && key == "routes" => {
+                merge_routes(base, overlay);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn merge_routes(base: &mut Vec<Value>, overlay: Vec<Value>) {
+    for route in overlay {
+        let existing = route.get("path").and_then(|path| {
+            base.iter_mut()
+                .find(|r| r.get("path") == Some(path) && r.is_object())
+        });
+        match (existing, route) {
+            (Some(Value::Object(base)), Value::Object(route)) => merge_maps(base, route, false),
+            (_, route) => base.push(route),
+        }
+    }
+}
+
+fn profile_error(name: &str, message: String, suggestion: Option<String>) -> ValidationError {
+    ValidationError {
+        route: "(root)".into(),
+        field: format!("profiles.{name}"),
+        message,
+        suggestion,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(json: &str) -> Config {
+        serde_json::from_str(json).unwrap()
+    }
+
+    const BASE: &str = r#"{
+        "defaults": {
+            "timeout": 5000,
+            "headers": {"add": {"X-Env": "dev", "X-Team": "core"}}
+        },
+        "routes": [
+            {
+                "path": "/orders",
+                "methods": ["GET", "POST"],
+                "targets": [{"url": "http://orders.dev:8080"}]
+            },
+            {"path": "/users", "targets": [{"url": "http://users.dev:8080"}]}
+        ],
+        "profiles": {
+            "prod": {
+                "defaults": {
+                    "timeout": 2000,
+                    "headers": {"add": {"X-Env": "prod", "X-Team": null}}
+                },
+                "routes": [
+                    {"path": "/orders", "targets": [{"url": "http://orders.prod:8080"}]},
+                    {"path": "/audit", "targets": [{"url": "http://audit.prod:8080"}]}
+                ]
+            }
+        }
+    }"#;
+
+    #[test]
+    fn no_profile_drops_overlays() {
+        let config = apply(config(BASE), None).unwrap();
+        assert!(config.profiles.is_empty());
+        assert_eq!(config.defaults.timeout, 5000);
+        assert_eq!(config.routes.len(), 2);
+    }
+
+    #[test]
+    fn profile_deep_merges_maps() {
+        let config = apply(config(BASE), Some("prod")).unwrap();
+        assert_eq!(config.defaults.timeout, 2000);
+        let add = &config.defaults.headers.add;
+        assert_eq!(add.get("X-Env").map(String::as_str), Some("prod"));
+        assert!(!add.contains_key("X-Team"));
+    }
+
+    #[test]
+    fn profile_routes_merge_by_path() {
+        let config = apply(config(BASE), Some("prod")).unwrap();
+        let paths: Vec<&str> = config.routes.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, vec!["/orders", "/users", "/audit"]);
+
+        let orders = &config.routes[0];
+        assert_eq!(orders.targets.len(), 1);
+        assert_eq!(orders.targets[0].url, "http://orders.prod:8080");
+        // Keys the overlay leaves out are kept
+        assert_eq!(orders.methods, vec!["GET", "POST"]);
+        assert_eq!(config.routes[1].targets[0].url, "http://users.dev:8080");
+    }
+
+    #[test]
+    fn unknown_profile_lists_defined_ones() {
+        let err = apply(config(BASE), Some("staging")).unwrap_err();
+        let SwitchboardError::ConfigValidation { errors } = err else {
+            panic!("expected validation error");
+        };
+        assert_eq!(errors[0].field, "profiles.staging");
+        assert_eq!(
+            errors[0].suggestion.as_deref(),
+            Some("defined profiles: prod")
+        );
+    }
+
+    #[test]
+    fn overlay_typos_are_rejected() {
+        let json = r#"{"routes": [], "profiles": {"prod": {"defaults": {"timeuot": 1}}}}"#;
+        assert!(apply(config(json), Some("prod")).is_err());
+    }
+
+    #[test]
+    fn inactive_profiles_are_checked() {
+        let json = r#"{"routes": [], "profiles": {
+            "dev": {"defaults": {"timeout": 1}},
+            "prod": {"defaults": {"timeuot": 1}}
+        }}"#;
+        for profile in [None, Some("dev")] {
+            let err = apply(config(json), profile).unwrap_err();
+            let SwitchboardError::ConfigValidation { errors } = err else {
+                panic!("expected validation error");
+            };
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].field, "profiles.prod");
+            assert!(
+                errors[0].message.contains("timeuot"),
+                "{}",
+                errors[0].message
+            );
+        }
+    }
+
+    #[test]
+    fn applied_profile_is_recorded() {
+        assert_eq!(apply(config(BASE), None).unwrap().profile, None);
+        let config = apply(config(BASE), Some("prod")).unwrap();
+        assert_eq!(config.profile.as_deref(), Some("prod"));
+    }
+}
@@ -0,0 +1,14 @@
+//! JSON Schema (draft 2020-12) for the config file.
+//!
+//! Derived from the serde model with `schemars`, so it follows the model
+//! as it changes: doc comments become descriptions, serde defaults become
+//! `default` values, enums list their variants, and `deny_unknown_fields`
+//! becomes `additionalProperties: false`.
+
+use super::model::Config;
+
+/// The JSON Schema of [`Config`].
+#[must_use]
+pub fn config_schema() -> serde_json::Value {
+    schemars::schema_for!(Config).to_value()
+}
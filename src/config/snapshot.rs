@@ -0,0 +1,227 @@
+//! Last-known-good config snapshots.
+//!
+//! With `--config-cache`, every config served from a live source (once it
+//! passes the rollback watch window, see [`crate::rollback`]) is written to
+//! a snapshot file, and [`ConfigResolver`](super::ConfigResolver)
+//! serves that snapshot at startup when no live source can be loaded, so an
+//! instance can restart while its database is unreachable. The snapshot
+//! holds the config as it was served: with the profile applied and secret
+//! references resolved. It is written atomically and, on Unix, readable by
+//! the owner only.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use super::model::Config;
+use super::secrets::Secrets;
+use super::validation::validate;
+use super::{unix_now, ConfigVersion};
+use crate::error::SwitchboardError;
+
+pub struct Snapshot {
+    path: PathBuf,
+    /// The version last written, to skip rewriting an unchanged config.
+    saved: Mutex<Option<ConfigVersion>>,
+}
+
+/// A snapshot restored from disk.
+#[derive(Debug)]
+pub struct Restored {
+    pub config: Config,
+    /// The version of the config when it was saved.
+    pub version: ConfigVersion,
+    /// The source the config was loaded from when it was saved.
+    pub source: String,
+    /// When the snapshot was saved, in seconds since the Unix epoch.
+    pub saved_at: u64,
+}
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    saved_at: u64,
+    source: &'a str,
+    version: &'a ConfigVersion,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<&'a str>,
+    secrets: &'a Secrets,
+    config: &'a Config,
+}
+
+#[derive(Deserialize)]
+struct SnapshotFile {
+    saved_at: u64,
+    source: String,
+    version: ConfigVersion,
+    #[serde(default)]
+    profile: Option<String>,
+    #[serde(default)]
+    secrets: Secrets,
+    config: Config,
+}
+
+impl Snapshot {
+    #[must_use]
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            saved: Mutex::new(None),
+        }
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write `config` as the last known good one, unless it is the version
+    /// written last.
+    pub async fn save(
+        &self,
+        config: &Config,
+        version: &ConfigVersion,
+        source: &str,
+    ) -> Result<(), SwitchboardError> {
+        if self.saved.lock().unwrap().as_ref() == Some(version) {
+            return Ok(());
+        }
+
+        let json = serde_json::to_vec(&SnapshotRef {
+            saved_at: unix_now(),
+            source,
+            version,
+            profile: config.profile.as_deref(),
+            secrets: &config.secrets,
+            config,
+        })
+        .map_err(|e| SwitchboardError::Io(std::io::Error::other(e)))?;
+
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || write_atomic(&path, &json))
+            .await
+            .map_err(|e| SwitchboardError::Io(std::io::Error::other(e)))??;
+
+        *self.saved.lock().unwrap() = Some(version.clone());
+        Ok(())
+    }
+
+    /// Read and re-validate the last snapshot written.
+    pub async fn load(&self) -> Result<Restored, SwitchboardError> {
+        let label = self.path.display().to_string();
+        let bytes = tokio::fs::read(&self.path).await.map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                SwitchboardError::ConfigFileNotFound {
+                    path: self.path.clone(),
+                }
+            } else {
+                SwitchboardError::Io(e)
+            }
+        })?;
+        let file: SnapshotFile =
+            serde_json::from_slice(&bytes).map_err(|e| SwitchboardError::ConfigParse {
+                path: label,
+                source: Box::new(e),
+            })?;
+
+        let mut config = file.config;
+        config.profile = file.profile;
+        config.secrets = file.secrets;
+        if let Err(mut errors) = validate(&config) {
+            config.secrets.mask_errors(&mut errors);
+            return Err(SwitchboardError::ConfigValidation { errors });
+        }
+
+        *self.saved.lock().unwrap() = Some(file.version.clone());
+        Ok(Restored {
+            config,
+            version: file.version,
+            source: file.source,
+            saved_at: file.saved_at,
+        })
+    }
+}
+
+/// Write beside `path` and rename, so that a crash mid-write never leaves a
+/// truncated snapshot behind.
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&partial)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    std::fs::rename(&partial, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::secrets::interpolate;
+
+    fn config(json: &str) -> Config {
+        interpolate(serde_json::from_str(json).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn round_trips_the_served_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("last-good.json");
+        // Safety: env var mutation is unsafe since Rust 1.66 due to thread-safety
+        // concerns. Acceptable here because no other test reads this var.
+        unsafe { std::env::set_var("SWITCHBOARD_TEST_SNAPSHOT_HOST", "orders") };
+        let mut served = config(
+            r#"{"routes": [{"path": "/a", "targets": [
+                {"url": "http://${env:SWITCHBOARD_TEST_SNAPSHOT_HOST}:80/$${literal}"}
+            ]}]}"#,
+        );
+        served.profile = Some("prod".into());
+        let version = ConfigVersion::Hash("abc".into());
+
+        let snapshot = Snapshot::new(path.clone());
+        snapshot.save(&served, &version, "redis").await.unwrap();
+
+        // Restored as served: references are not resolved a second time
+        let restored = Snapshot::new(path.clone()).load().await.unwrap();
+        assert_eq!(restored.source, "redis");
+        assert_eq!(restored.version, version);
+        assert_eq!(
+            restored.config.routes[0].targets[0].url,
+            "http://orders:80/${literal}"
+        );
+        assert_eq!(restored.config.secrets, served.secrets);
+        assert_eq!(restored.config.profile.as_deref(), Some("prod"));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_or_invalid_snapshots_are_errors() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let err = Snapshot::new(dir.join("none.json"))
+            .load()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SwitchboardError::ConfigFileNotFound { .. }));
+
+        std::fs::write(dir.join("bad.json"), "{\"saved_at\": 0").unwrap();
+        let err = Snapshot::new(dir.join("bad.json"))
+            .load()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SwitchboardError::ConfigParse { .. }));
+    }
+}
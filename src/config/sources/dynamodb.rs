@@ -5,6 +5,13 @@
 //! (String) and a `config_json` attribute (String) containing the serialized
 //! [`Config`](crate::config::model::Config).
 //!
+//! `switchboard config push` writes through [`ConfigStore`]: every push is
+//! kept as an item keyed `{namespace}#v{version}`, numbered by the
+//! namespace item's `latest_version` counter, and the namespace item holds
+//! a copy of the active version along with its number (`active_version`),
+//! so that `config rollback` only has to copy an older version back. A
+//! push writes the new version and the namespace item in one transaction.
+//!
 //! # CLI arguments
 //!
 //! | Flag                 | Env var            | Default       |
@@ -12,18 +19,32 @@
 //! | `--dynamodb-table`   | `DYNAMODB_TABLE`   | *(required)*  |
 //! | `--dynamodb-region`  | `DYNAMODB_REGION`  | `us-east-1`   |
 
+use std::collections::HashMap;
+
 use async_trait::async_trait;
-use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsError;
+use aws_sdk_dynamodb::types::{AttributeValue, KeysAndAttributes, Put, TransactWriteItem, Update};
 use aws_sdk_dynamodb::Client;
 
 use super::{parse_validate_hash, sha256_hex};
-use crate::config::{ConfigSource, ConfigVersion};
+use crate::config::payload::PayloadKeys;
+use crate::config::{
+    unix_now, ConfigSource, ConfigStore, ConfigVersion, StoredJson, StoredVersion, VersionMeta,
+};
 use crate::error::SwitchboardError;
 
+/// Most keys a `BatchGetItem` request may ask for.
+const BATCH_GET_LIMIT: usize = 100;
+
+/// How often a push is retried after losing a race with another push.
+const PUSH_ATTEMPTS: usize = 5;
+
 pub struct DynamoDbSource {
     client: Client,
     table: String,
     namespace: String,
+    keys: PayloadKeys,
+    profile: Option<String>,
 }
 
 impl DynamoDbSource {
@@ -39,30 +60,212 @@ impl DynamoDbSource {
             client,
             table: table.to_string(),
             namespace: namespace.to_string(),
+            keys: PayloadKeys::default(),
+            profile: None,
         })
     }
 
-    async fn fetch_config_json(&self) -> Result<String, SwitchboardError> {
+    /// Open stored configs with `keys`, to decrypt them and check their
+    /// signatures, and seal pushed ones with them.
+    #[must_use]
+    pub fn with_payload_keys(mut self, keys: PayloadKeys) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// Merge the named profile onto every loaded config.
+    #[must_use]
+    pub fn with_profile(mut self, profile: Option<String>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    async fn fetch_config_json(&self) -> Result<StoredJson, SwitchboardError> {
+        self.read_json()
+            .await?
+            .ok_or_else(|| SwitchboardError::Database {
+                backend: "dynamodb",
+                source: format!(
+                    "no item found for namespace '{}' in table '{}'",
+                    self.namespace, self.table
+                )
+                .into(),
+            })
+    }
+}
+
+#[async_trait]
+impl ConfigSource for DynamoDbSource {
+    fn name(&self) -> &'static str {
+        "dynamodb"
+    }
+
+    async fn load(
+        &self,
+    ) -> Result<(crate::config::model::Config, ConfigVersion), SwitchboardError> {
+        let json = self.fetch_config_json().await?;
+        parse_validate_hash(
+            &json,
+            &format!("dynamodb://{}:{}", self.table, self.namespace),
+            &self.namespace,
+            &self.keys,
+            self.profile.as_deref(),
+        )
+    }
+
+    async fn has_changed(&self, current: &ConfigVersion) -> Result<bool, SwitchboardError> {
+        let stored = self.fetch_config_json().await?;
+        Ok(*current != ConfigVersion::Hash(sha256_hex(stored.json.as_bytes())))
+    }
+}
+
+fn db_error(e: impl std::error::Error + Send + Sync + 'static) -> SwitchboardError {
+    SwitchboardError::Database {
+        backend: "dynamodb",
+        source: Box::new(e),
+    }
+}
+
+type Item = HashMap<String, AttributeValue>;
+
+fn number(item: &Item, name: &str) -> Option<u64> {
+    item.get(name)?.as_n().ok()?.parse().ok()
+}
+
+fn string(item: &Item, name: &str) -> Option<String> {
+    item.get(name)?.as_s().ok().cloned()
+}
+
+impl DynamoDbSource {
+    /// The key of the item holding `version` of this namespace's config.
+    fn version_key(&self, version: u64) -> AttributeValue {
+        AttributeValue::S(format!("{}#v{version}", self.namespace))
+    }
+
+    async fn get(&self, key: AttributeValue) -> Result<Option<Item>, SwitchboardError> {
         let output = self
             .client
             .get_item()
             .table_name(&self.table)
-            .key("namespace", AttributeValue::S(self.namespace.clone()))
+            .key("namespace", key)
+            .consistent_read(true)
             .send()
             .await
-            .map_err(|e| SwitchboardError::Database {
-                backend: "dynamodb",
-                source: Box::new(e),
-            })?;
+            .map_err(db_error)?;
+        Ok(output.item)
+    }
 
-        let item = output.item.ok_or_else(|| SwitchboardError::Database {
-            backend: "dynamodb",
-            source: format!(
-                "no item found for namespace '{}' in table '{}'",
-                self.namespace, self.table
+    /// A write storing `version` of the config, failing if it exists.
+    fn put_version(
+        &self,
+        version: u64,
+        config_json: &str,
+        author: &str,
+        comment: Option<&str>,
+    ) -> Result<TransactWriteItem, SwitchboardError> {
+        let mut put = Put::builder()
+            .table_name(&self.table)
+            .item("namespace", self.version_key(version))
+            .item("config_json", AttributeValue::S(config_json.to_string()))
+            .item("author", AttributeValue::S(author.to_string()))
+            .item("created_at", AttributeValue::N(unix_now().to_string()))
+            .condition_expression("attribute_not_exists(#ns)")
+            .expression_attribute_names("#ns", "namespace");
+        if let Some(comment) = comment {
+            put = put.item("comment", AttributeValue::S(comment.to_string()));
+        }
+        Ok(TransactWriteItem::builder()
+            .put(put.build().map_err(db_error)?)
+            .build())
+    }
+
+    /// A write making `version` the latest and active version, failing if
+    /// the latest version is no longer `latest`.
+    fn set_latest(
+        &self,
+        version: u64,
+        config_json: &str,
+        latest: Option<u64>,
+    ) -> Result<TransactWriteItem, SwitchboardError> {
+        let mut update = Update::builder()
+            .table_name(&self.table)
+            .key("namespace", AttributeValue::S(self.namespace.clone()))
+            .update_expression(
+                "SET config_json = :json, active_version = :version, latest_version = :version",
             )
-            .into(),
-        })?;
+            .expression_attribute_values(":json", AttributeValue::S(config_json.to_string()))
+            .expression_attribute_values(":version", AttributeValue::N(version.to_string()));
+        update = match latest {
+            Some(latest) => update
+                .condition_expression("latest_version = :latest")
+                .expression_attribute_values(":latest", AttributeValue::N(latest.to_string())),
+            None => update.condition_expression("attribute_not_exists(latest_version)"),
+        };
+        Ok(TransactWriteItem::builder()
+            .update(update.build().map_err(db_error)?)
+            .build())
+    }
+
+    /// Fetch the metadata of the given versions, [`BATCH_GET_LIMIT`] keys
+    /// per request.
+    async fn get_versions(&self, versions: &[u64]) -> Result<Vec<Item>, SwitchboardError> {
+        let mut items = Vec::with_capacity(versions.len());
+        for chunk in versions.chunks(BATCH_GET_LIMIT) {
+            let mut keys = KeysAndAttributes::builder()
+                .projection_expression("#ns, author, created_at, #comment")
+                .expression_attribute_names("#ns", "namespace")
+                .expression_attribute_names("#comment", "comment")
+                .consistent_read(true);
+            for &version in chunk {
+                keys = keys.keys(HashMap::from([(
+                    "namespace".to_string(),
+                    self.version_key(version),
+                )]));
+            }
+            let mut request = Some(keys.build().map_err(db_error)?);
+
+            // Keys left over when a response hits its size limit are retried
+            while let Some(keys) = request.take() {
+                let output = self
+                    .client
+                    .batch_get_item()
+                    .request_items(&self.table, keys)
+                    .send()
+                    .await
+                    .map_err(db_error)?;
+                if let Some(mut responses) = output.responses {
+                    items.extend(responses.remove(&self.table).unwrap_or_default());
+                }
+                request = output
+                    .unprocessed_keys
+                    .and_then(|mut unprocessed| unprocessed.remove(&self.table))
+                    .filter(|keys| !keys.keys.is_empty());
+            }
+        }
+        Ok(items)
+    }
+
+    async fn set_active(&self, version: u64, config_json: &str) -> Result<(), SwitchboardError> {
+        self.client
+            .update_item()
+            .table_name(&self.table)
+            .key("namespace", AttributeValue::S(self.namespace.clone()))
+            .update_expression("SET config_json = :json, active_version = :version")
+            .expression_attribute_values(":json", AttributeValue::S(config_json.to_string()))
+            .expression_attribute_values(":version", AttributeValue::N(version.to_string()))
+            .send()
+            .await
+            .map_err(db_error)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ConfigStore for DynamoDbSource {
+    async fn read_json(&self) -> Result<Option<StoredJson>, SwitchboardError> {
+        let Some(item) = self.get(AttributeValue::S(self.namespace.clone())).await? else {
+            return Ok(None);
+        };
 
         let attr = item
             .get("config_json")
@@ -86,29 +289,109 @@ impl DynamoDbSource {
                     .into(),
                 })
             },
-            |json| Ok(json.clone()),
+            |json| {
+                Ok(Some(StoredJson {
+                    json: json.clone(),
+                    version: number(&item, "active_version"),
+                }))
+            },
         )
     }
-}
 
-#[async_trait]
-impl ConfigSource for DynamoDbSource {
-    fn name(&self) -> &'static str {
-        "dynamodb"
+    async fn push_json(&self, json: &str, meta: &VersionMeta) -> Result<u64, SwitchboardError> {
+        for _ in 0..PUSH_ATTEMPTS {
+            let current = self.get(AttributeValue::S(self.namespace.clone())).await?;
+            let latest = current
+                .as_ref()
+                .and_then(|item| number(item, "latest_version"));
+            let mut version = latest.unwrap_or(0) + 1;
+            let mut writes = Vec::new();
+
+            // A config stored before versioning becomes version 1
+            let unversioned = current
+                .as_ref()
+                .filter(|_| latest.is_none())
+                .and_then(|item| string(item, "config_json"));
+            if let Some(existing) = unversioned {
+                writes.push(self.put_version(
+                    version,
+                    &existing,
+                    "unknown",
+                    Some("stored before versioning"),
+                )?);
+                version += 1;
+            }
+            let json = self.keys.seal(json, &self.namespace, version)?;
+            writes.push(self.put_version(version, &json, &meta.author, meta.comment.as_deref())?);
+            writes.push(self.set_latest(version, &json, latest)?);
+
+            // Every write happens or none does; a concurrent push cancels
+            // the transaction and the next attempt numbers after it
+            match self
+                .client
+                .transact_write_items()
+                .set_transact_items(Some(writes))
+                .send()
+                .await
+            {
+                Ok(_) => return Ok(version),
+                Err(e)
+                    if e.as_service_error().is_some_and(
+                        TransactWriteItemsError::is_transaction_canceled_exception,
+                    ) => {}
+                Err(e) => return Err(db_error(e)),
+            }
+        }
+        Err(SwitchboardError::Database {
+            backend: "dynamodb",
+            source: format!(
+                "push to namespace '{}' kept conflicting with concurrent pushes",
+                self.namespace
+            )
+            .into(),
+        })
     }
 
-    async fn load(
-        &self,
-    ) -> Result<(crate::config::model::Config, ConfigVersion), SwitchboardError> {
-        let json = self.fetch_config_json().await?;
-        parse_validate_hash(
-            &json,
-            &format!("dynamodb://{}:{}", self.table, self.namespace),
-        )
+    async fn history(&self) -> Result<Vec<StoredVersion>, SwitchboardError> {
+        let Some(item) = self.get(AttributeValue::S(self.namespace.clone())).await? else {
+            return Ok(Vec::new());
+        };
+        let active = number(&item, "active_version");
+        let latest = number(&item, "latest_version").unwrap_or(0);
+
+        let numbers: Vec<u64> = (1..=latest).collect();
+        let mut versions = Vec::with_capacity(numbers.len());
+        for item in self.get_versions(&numbers).await? {
+            let Some(version) =
+                string(&item, "namespace").and_then(|key| key.rsplit_once("#v")?.1.parse().ok())
+            else {
+                continue;
+            };
+            versions.push(StoredVersion {
+                version,
+                author: string(&item, "author").unwrap_or_default(),
+                created_at: number(&item, "created_at").unwrap_or_default(),
+                comment: string(&item, "comment"),
+                active: active == Some(version),
+            });
+        }
+        versions.sort_by_key(|v| std::cmp::Reverse(v.version));
+        Ok(versions)
     }
 
-    async fn has_changed(&self, current: &ConfigVersion) -> Result<bool, SwitchboardError> {
-        let json = self.fetch_config_json().await?;
-        Ok(*current != ConfigVersion::Hash(sha256_hex(json.as_bytes())))
+    async fn activate(&self, version: u64) -> Result<(), SwitchboardError> {
+        let json = self
+            .get(self.version_key(version))
+            .await?
+            .and_then(|item| string(&item, "config_json"))
+            .ok_or_else(|| SwitchboardError::Database {
+                backend: "dynamodb",
+                source: format!(
+                    "version {version} not found for namespace '{}'",
+                    self.namespace
+                )
+                .into(),
+            })?;
+        self.set_active(version, &json).await
     }
 }
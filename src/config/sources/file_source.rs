@@ -2,49 +2,125 @@
 //!
 //! [`FileSource`] implements [`ConfigSource`]
 //! for any file format by accepting a deserialization function at
-//! construction time. It reads the file asynchronously via Tokio,
-//! validates the result, and computes a SHA256 hash for version tracking.
+//! construction time. It reads the file (plus any fragments it includes,
+//! or every config file of a directory) on a blocking thread, resolves
+//! secret references, validates the result, and computes a SHA256 hash
+//! over all files for version tracking. With the `watch` feature, the
+//! directories holding those files are watched for push reloads.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use async_trait::async_trait;
 
-use super::sha256_hex;
+use super::include;
+#[cfg(feature = "watch")]
+use super::watch;
 use crate::config::model::Config;
-use crate::config::validation::validate;
+#[cfg(feature = "watch")]
+use crate::config::ChangeStream;
 use crate::config::{ConfigSource, ConfigVersion};
 use crate::error::SwitchboardError;
 
+type Deserialize = fn(&str) -> Result<Config, Box<dyn std::error::Error + Send + Sync>>;
+
 pub struct FileSource {
     path: PathBuf,
     name: &'static str,
-    deserialize: fn(&str) -> Result<Config, Box<dyn std::error::Error + Send + Sync>>,
+    deserialize: Option<Deserialize>,
+    profile: Option<String>,
 }
 
 impl FileSource {
     #[must_use]
-    pub fn new(
-        path: PathBuf,
-        name: &'static str,
-        deserialize: fn(&str) -> Result<Config, Box<dyn std::error::Error + Send + Sync>>,
-    ) -> Self {
+    pub fn new(path: PathBuf, name: &'static str, deserialize: Deserialize) -> Self {
         Self {
             path,
             name,
-            deserialize,
+            deserialize: Some(deserialize),
+            profile: None,
+        }
+    }
+
+    /// A source merging every config file in a directory (`conf.d` style).
+    #[must_use]
+    pub const fn directory(path: PathBuf) -> Self {
+        Self {
+            path,
+            name: "directory",
+            deserialize: None,
+            profile: None,
         }
     }
 
-    async fn read_content(&self) -> Result<String, SwitchboardError> {
-        tokio::fs::read_to_string(&self.path).await.map_err(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                SwitchboardError::ConfigFileNotFound {
-                    path: self.path.clone(),
-                }
-            } else {
-                SwitchboardError::Io(e)
-            }
+    /// Merge the named profile onto every loaded config.
+    #[must_use]
+    pub fn with_profile(mut self, profile: Option<String>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Run `f` on a blocking thread with a parser for the root file.
+    async fn with_files<T, F>(&self, f: F) -> Result<T, SwitchboardError>
+    where
+        T: Send + 'static,
+        F: FnOnce(
+                &Path,
+                &dyn Fn(&str) -> Result<Config, SwitchboardError>,
+            ) -> Result<T, SwitchboardError>
+            + Send
+            + 'static,
+    {
+        let path = self.path.clone();
+        let deserialize = self.deserialize;
+        tokio::task::spawn_blocking(move || {
+            let parse_root = root_parser(&path, deserialize);
+            f(&path, &parse_root)
         })
+        .await
+        .map_err(|e| SwitchboardError::Io(std::io::Error::other(e)))?
+    }
+}
+
+/// The directories holding the config's files: the directory itself, or
+/// the root file's directory and those of the fragments it includes.
+/// Reads the files, so call it on a blocking thread.
+#[cfg(feature = "watch")]
+fn watched_dirs(
+    path: &Path,
+    parse_root: &dyn Fn(&str) -> Result<Config, SwitchboardError>,
+) -> Vec<PathBuf> {
+    if path.is_dir() {
+        return vec![path.to_path_buf()];
+    }
+    let files = include::read_fragments(path, parse_root).map_or_else(
+        |_| vec![path.to_path_buf()],
+        |fragments| fragments.into_iter().map(|f| f.path).collect(),
+    );
+
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    for file in files {
+        let dir = match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    dirs
+}
+
+fn root_parser(
+    path: &Path,
+    deserialize: Option<Deserialize>,
+) -> impl Fn(&str) -> Result<Config, SwitchboardError> {
+    let display = path.display().to_string();
+    move |content: &str| match deserialize {
+        Some(deserialize) => deserialize(content).map_err(|e| SwitchboardError::ConfigParse {
+            path: display.clone(),
+            source: e,
+        }),
+        None => Err(SwitchboardError::UnsupportedFormat(display.clone())),
     }
 }
 
@@ -55,24 +131,23 @@ impl ConfigSource for FileSource {
     }
 
     async fn load(&self) -> Result<(Config, ConfigVersion), SwitchboardError> {
-        let content = self.read_content().await?;
-
-        let config = (self.deserialize)(&content).map_err(|e| SwitchboardError::ConfigParse {
-            path: self.path.display().to_string(),
-            source: e,
-        })?;
-
-        if let Err(errors) = validate(&config) {
-            return Err(SwitchboardError::ConfigValidation { errors });
-        }
-
-        let hash = sha256_hex(content.as_bytes());
-        Ok((config, ConfigVersion::Hash(hash)))
+        let profile = self.profile.clone();
+        self.with_files(move |path, parse_root| include::load(path, parse_root, profile.as_deref()))
+            .await
     }
 
     async fn has_changed(&self, current: &ConfigVersion) -> Result<bool, SwitchboardError> {
-        let content = self.read_content().await?;
-        let hash = sha256_hex(content.as_bytes());
-        Ok(*current != ConfigVersion::Hash(hash))
+        let version = self
+            .with_files(|path, parse_root| include::current_version(path, parse_root))
+            .await?;
+        Ok(*current != version)
+    }
+
+    #[cfg(feature = "watch")]
+    async fn watch(&self) -> Result<Option<ChangeStream>, SwitchboardError> {
+        let dirs = self
+            .with_files(|path, parse_root| Ok(watched_dirs(path, parse_root)))
+            .await?;
+        watch::watch_dirs(dirs).map(Some)
     }
 }
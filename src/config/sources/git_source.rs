@@ -0,0 +1,242 @@
+//! Git repository config source.
+//!
+//! [`GitSource`] implements [`ConfigSource`] by reading a config file at a
+//! branch, tag, or commit of a git repository, so that configs can be
+//! reviewed and merged like code. The repository (a local path or
+//! `file://` URL) is fetched into a private bare mirror on every load and
+//! poll, pruning deleted branches; the commit the ref points to is the [`ConfigVersion`], so a poll
+//! only reads the file again when a new commit lands.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use git2::{Commit, FetchOptions, FetchPrune, Repository};
+
+use super::{parse_config_str, resolve_and_validate};
+use crate::config::model::Config;
+use crate::config::{ConfigSource, ConfigVersion};
+use crate::error::SwitchboardError;
+
+/// Refs mirrored from the repository: branches and tags.
+const REFSPECS: &[&str] = &[
+    "+refs/heads/*:refs/remotes/origin/*",
+    "+refs/tags/*:refs/tags/*",
+];
+
+pub struct GitSource {
+    inner: Arc<Inner>,
+    profile: Option<String>,
+}
+
+struct Inner {
+    url: String,
+    reference: String,
+    file: PathBuf,
+    mirror_path: PathBuf,
+    /// Owns the mirror directory when no cache dir was given; it is
+    /// removed with the source.
+    _scratch: Option<tempfile::TempDir>,
+    /// Serializes fetches into the mirror.
+    mirror: Mutex<Option<Repository>>,
+}
+
+impl GitSource {
+    /// A source reading `file` at `reference` of the repository at `url`.
+    /// The mirror lives in `cache_dir`, which must not belong to another
+    /// user, or in a fresh private temp directory.
+    pub fn new(
+        url: &str,
+        reference: &str,
+        file: &Path,
+        cache_dir: Option<PathBuf>,
+    ) -> Result<Self, SwitchboardError> {
+        let (mirror_path, scratch) = match cache_dir {
+            Some(dir) => {
+                check_owner(&dir)?;
+                (dir, None)
+            }
+            None => {
+                let scratch = tempfile::Builder::new()
+                    .prefix("switchboard-git-")
+                    .tempdir()?;
+                (scratch.path().to_path_buf(), Some(scratch))
+            }
+        };
+        Ok(Self {
+            inner: Arc::new(Inner {
+                url: url.to_string(),
+                reference: reference.to_string(),
+                file: file.to_path_buf(),
+                mirror_path,
+                _scratch: scratch,
+                mirror: Mutex::new(None),
+            }),
+            profile: None,
+        })
+    }
+
+    /// Merge the named profile onto every loaded config.
+    #[must_use]
+    pub fn with_profile(mut self, profile: Option<String>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Run `f` on a blocking thread with the mirror freshly fetched and the
+    /// commit `reference` points to.
+    async fn with_commit<T, F>(&self, f: F) -> Result<T, SwitchboardError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Inner, &Repository, &Commit<'_>) -> Result<T, SwitchboardError> + Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        tokio::task::spawn_blocking(move || {
+            let mut mirror = inner
+                .mirror
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            let repo = match mirror.take() {
+                Some(repo) => repo,
+                None => inner.open_mirror()?,
+            };
+            let repo = mirror.insert(repo);
+            let mut options = FetchOptions::new();
+            options.prune(FetchPrune::On);
+            repo.remote_anonymous(&inner.url)?
+                .fetch(REFSPECS, Some(&mut options), None)?;
+            let commit = inner.resolve(repo)?;
+            f(&inner, repo, &commit)
+        })
+        .await
+        .map_err(|e| SwitchboardError::Io(std::io::Error::other(e)))?
+    }
+}
+
+/// Refuse a mirror directory owned by another user: whoever owns it
+/// controls the refs the config is read from.
+#[cfg(unix)]
+fn check_owner(dir: &Path) -> Result<(), SwitchboardError> {
+    use std::os::unix::fs::MetadataExt;
+
+    let owner = match std::fs::metadata(dir) {
+        Ok(metadata) => metadata.uid(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    // Safety: geteuid has no preconditions and cannot fail.
+    if owner != unsafe { libc::geteuid() } {
+        return Err(SwitchboardError::Io(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!("git cache dir {} is owned by another user", dir.display()),
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::unnecessary_wraps)]
+const fn check_owner(_dir: &Path) -> Result<(), SwitchboardError> {
+    Ok(())
+}
+
+impl Inner {
+    fn open_mirror(&self) -> Result<Repository, SwitchboardError> {
+        Ok(Repository::open_bare(&self.mirror_path)
+            .or_else(|_| Repository::init_bare(&self.mirror_path))?)
+    }
+
+    /// The commit `reference` names: a branch, a tag, or any revision.
+    fn resolve<'r>(&self, repo: &'r Repository) -> Result<Commit<'r>, SwitchboardError> {
+        let candidates = [
+            format!("refs/remotes/origin/{}", self.reference),
+            format!("refs/tags/{}", self.reference),
+            self.reference.clone(),
+        ];
+        for spec in &candidates {
+            if let Ok(object) = repo.revparse_single(spec) {
+                return Ok(object.peel_to_commit()?);
+            }
+        }
+        Err(SwitchboardError::ConfigParse {
+            path: self.url.clone(),
+            source: format!("ref '{}' not found", self.reference).into(),
+        })
+    }
+
+    fn label(&self, commit: &Commit<'_>) -> String {
+        format!("{}@{}:{}", self.url, short(commit), self.file.display())
+    }
+
+    fn read(&self, repo: &Repository, commit: &Commit<'_>) -> Result<String, SwitchboardError> {
+        let entry =
+            commit
+                .tree()?
+                .get_path(&self.file)
+                .map_err(|_| SwitchboardError::ConfigParse {
+                    path: self.label(commit),
+                    source: "file not found in commit".into(),
+                })?;
+        let blob = repo.find_blob(entry.id())?;
+        String::from_utf8(blob.content().to_vec()).map_err(|e| SwitchboardError::ConfigParse {
+            path: self.label(commit),
+            source: Box::new(e),
+        })
+    }
+}
+
+fn short(commit: &Commit<'_>) -> String {
+    let sha = commit.id().to_string();
+    sha[..8].to_string()
+}
+
+fn version(commit: &Commit<'_>) -> ConfigVersion {
+    let author = commit.author();
+    let author = match (author.name(), author.email()) {
+        (Some(name), Some(email)) => format!("{name} <{email}>"),
+        (Some(name), None) => name.to_string(),
+        (None, Some(email)) => email.to_string(),
+        (None, None) => String::new(),
+    };
+    ConfigVersion::Commit {
+        sha: commit.id().to_string(),
+        author,
+    }
+}
+
+#[async_trait]
+impl ConfigSource for GitSource {
+    fn name(&self) -> &'static str {
+        "git"
+    }
+
+    async fn load(&self) -> Result<(Config, ConfigVersion), SwitchboardError> {
+        let (content, label, ext, version) = self
+            .with_commit(|inner, repo, commit| {
+                let ext = inner
+                    .file
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or("")
+                    .to_string();
+                Ok((
+                    inner.read(repo, commit)?,
+                    inner.label(commit),
+                    ext,
+                    version(commit),
+                ))
+            })
+            .await?;
+
+        let config = parse_config_str(&ext, &content, &label)?;
+        let config = resolve_and_validate(config, self.profile.as_deref())?;
+        Ok((config, version))
+    }
+
+    async fn has_changed(&self, current: &ConfigVersion) -> Result<bool, SwitchboardError> {
+        let sha = self
+            .with_commit(|_, _, commit| Ok(commit.id().to_string()))
+            .await?;
+        Ok(current.commit().map(|(current, _)| current) != Some(sha.as_str()))
+    }
+}
@@ -0,0 +1,310 @@
+//! Config split across several files.
+//!
+//! A config can be assembled from fragments in two ways: a root file whose
+//! `include` list names glob patterns (relative to the root file), or a
+//! directory whose `*.yaml`/`*.yml`/`*.json`/`*.toml` files are all loaded
+//! in name order. Fragments are merged as follows:
+//!
+//! - `routes` are concatenated: the root file first, then each fragment in
+//!   the order its pattern and file name sort;
+//! - `defaults`, `actuator`, and `profiles` may each be set by at most one
+//!   file;
+//! - fragments cannot `include` further files.
+//!
+//! Validation errors are annotated with the file a route or setting came
+//! from, and the version hash covers every file so that editing any
+//! fragment triggers a reload.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use super::{parse_config_str, sha256_hex};
+use crate::config::model::{self, Config};
+use crate::config::profiles;
+use crate::config::secrets::interpolate;
+use crate::config::validation::validate;
+use crate::config::ConfigVersion;
+use crate::error::{SwitchboardError, ValidationError};
+
+const CONFIG_EXTENSIONS: &[&str] = &["yaml", "yml", "json", "toml"];
+
+/// One file of a config.
+pub struct Fragment {
+    pub path: PathBuf,
+    pub content: String,
+    pub config: Config,
+}
+
+/// Where merged routes and settings came from, for error reporting.
+#[derive(Debug, Default)]
+pub struct Origins {
+    routes: HashMap<String, Vec<String>>,
+    defaults: Option<String>,
+    actuator: Option<String>,
+    profiles: Option<String>,
+}
+
+impl Origins {
+    /// Append the originating file(s) to each error's route.
+    pub fn annotate(&self, errors: &mut [ValidationError]) {
+        for error in errors {
+            let file = if error.route == "(root)" {
+                if error.field.starts_with("defaults") {
+                    self.defaults.clone()
+                } else if error.field.starts_with("actuator") {
+                    self.actuator.clone()
+                } else if error.field.starts_with("profiles") {
+                    self.profiles.clone()
+                } else {
+                    None
+                }
+            } else {
+                self.routes.get(&error.route).map(|files| files.join(", "))
+            };
+            if let Some(file) = file {
+                error.route = format!("{} ({file})", error.route);
+            }
+        }
+    }
+}
+
+/// Load, merge, apply `profile` to, interpolate, and validate the config
+/// at `path` (a file or a directory). `parse_root` parses the root file.
+pub fn load(
+    path: &Path,
+    parse_root: &dyn Fn(&str) -> Result<Config, SwitchboardError>,
+    profile: Option<&str>,
+) -> Result<(Config, ConfigVersion), SwitchboardError> {
+    let fragments = read_fragments(path, parse_root)?;
+    let version = version(&fragments);
+    let (config, origins) = merge(fragments)?;
+    let config = profiles::apply(config, profile).map_err(|e| match e {
+        SwitchboardError::ConfigValidation { mut errors } => {
+            origins.annotate(&mut errors);
+            SwitchboardError::ConfigValidation { errors }
+        }
+        e => e,
+    })?;
+    let config = interpolate(config)?;
+
+    if let Err(mut errors) = validate(&config) {
+        origins.annotate(&mut errors);
+        config.secrets.mask_errors(&mut errors);
+        return Err(SwitchboardError::ConfigValidation { errors });
+    }
+    Ok((config, version))
+}
+
+/// The version of the config at `path`, without merging or validating it.
+pub fn current_version(
+    path: &Path,
+    parse_root: &dyn Fn(&str) -> Result<Config, SwitchboardError>,
+) -> Result<ConfigVersion, SwitchboardError> {
+    read_fragments(path, parse_root).map(|fragments| version(&fragments))
+}
+
+/// Read the root file and the fragments it includes, or every config file
+/// in a directory.
+pub fn read_fragments(
+    path: &Path,
+    parse_root: &dyn Fn(&str) -> Result<Config, SwitchboardError>,
+) -> Result<Vec<Fragment>, SwitchboardError> {
+    if path.is_dir() {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(path)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.is_file() && is_config_file(p))
+            .collect();
+        if files.is_empty() {
+            return Err(SwitchboardError::ConfigFileNotFound {
+                path: path.to_path_buf(),
+            });
+        }
+        files.sort();
+        return files.iter().map(|p| read_fragment(p)).collect();
+    }
+
+    let content = read(path)?;
+    let config = parse_root(&content)?;
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut included = Vec::new();
+    for pattern in &config.include {
+        let full = base.join(pattern);
+        let paths =
+            glob::glob(&full.to_string_lossy()).map_err(|e| SwitchboardError::ConfigParse {
+                path: path.display().to_string(),
+                source: format!("invalid include pattern '{pattern}': {e}").into(),
+            })?;
+        let mut matched: Vec<PathBuf> = paths
+            .filter_map(Result::ok)
+            .filter(|p| p.is_file() && is_config_file(p) && p != path)
+            .collect();
+        if matched.is_empty() {
+            tracing::warn!(pattern = %pattern, "include pattern matched no files");
+        }
+        matched.sort();
+        for p in matched {
+            if !included.contains(&p) {
+                included.push(p);
+            }
+        }
+    }
+
+    let mut fragments = vec![Fragment {
+        path: path.to_path_buf(),
+        content,
+        config,
+    }];
+    for p in &included {
+        fragments.push(read_fragment(p)?);
+    }
+    Ok(fragments)
+}
+
+fn is_config_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| CONFIG_EXTENSIONS.contains(&ext))
+}
+
+fn read(path: &Path) -> Result<String, SwitchboardError> {
+    std::fs::read_to_string(path).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            SwitchboardError::ConfigFileNotFound {
+                path: path.to_path_buf(),
+            }
+        } else {
+            SwitchboardError::Io(e)
+        }
+    })
+}
+
+fn read_fragment(path: &Path) -> Result<Fragment, SwitchboardError> {
+    let content = read(path)?;
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let config = parse_config_str(ext, &content, &path.display().to_string())?;
+    Ok(Fragment {
+        path: path.to_path_buf(),
+        content,
+        config,
+    })
+}
+
+/// Hash of every file in the set. A lone file keeps the plain content hash.
+#[must_use]
+pub fn version(fragments: &[Fragment]) -> ConfigVersion {
+    if let [single] = fragments {
+        return ConfigVersion::Hash(sha256_hex(single.content.as_bytes()));
+    }
+    let mut hasher = Sha256::new();
+    for fragment in fragments {
+        hasher.update(fragment.path.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(fragment.content.as_bytes());
+        hasher.update([0]);
+    }
+    ConfigVersion::Hash(format!("{:x}", hasher.finalize()))
+}
+
+/// Merge fragments into one config (see the module docs for the rules).
+pub fn merge(fragments: Vec<Fragment>) -> Result<(Config, Origins), SwitchboardError> {
+    let mut fragments = fragments.into_iter();
+    let Some(first) = fragments.next() else {
+        return Err(SwitchboardError::ConfigValidation {
+            errors: vec![root_error("routes", "no config files found".into())],
+        });
+    };
+    let rest: Vec<Fragment> = fragments.collect();
+    if rest.is_empty() {
+        return Ok((first.config, Origins::default()));
+    }
+
+    let mut merged = Config {
+        actuator: model::ActuatorConfig::default(),
+        defaults: model::Defaults::default(),
+        routes: Vec::new(),
+        include: first.config.include.clone(),
+        profiles: Default::default(),
+        profile: None,
+        secrets: Default::default(),
+    };
+    let mut origins = Origins::default();
+    let mut errors = Vec::new();
+
+    for (idx, fragment) in std::iter::once(first).chain(rest).enumerate() {
+        let file = fragment.path.display().to_string();
+        let config = fragment.config;
+
+        if idx > 0 && !config.include.is_empty() {
+            errors.push(root_error(
+                "include",
+                format!("{file}: fragments cannot include other files"),
+            ));
+        }
+        if !model::is_default_defaults(&config.defaults) {
+            match &origins.defaults {
+                Some(first) => errors.push(root_error(
+                    "defaults",
+                    format!("set in both {first} and {file}; keep defaults in one file"),
+                )),
+                None => {
+                    merged.defaults = config.defaults;
+                    origins.defaults = Some(file.clone());
+                }
+            }
+        }
+        if !model::is_default_actuator(&config.actuator) {
+            match &origins.actuator {
+                Some(first) => errors.push(root_error(
+                    "actuator",
+                    format!("set in both {first} and {file}; keep actuator settings in one file"),
+                )),
+                None => {
+                    merged.actuator = config.actuator;
+                    origins.actuator = Some(file.clone());
+                }
+            }
+        }
+        if !config.profiles.is_empty() {
+            match &origins.profiles {
+                Some(first) => errors.push(root_error(
+                    "profiles",
+                    format!("set in both {first} and {file}; keep profiles in one file"),
+                )),
+                None => {
+                    merged.profiles = config.profiles;
+                    origins.profiles = Some(file.clone());
+                }
+            }
+        }
+        for route in config.routes {
+            let id = if route.path.is_empty() {
+                format!("routes[{}]", merged.routes.len())
+            } else {
+                route.path.clone()
+            };
+            let files = origins.routes.entry(id).or_default();
+            if !files.contains(&file) {
+                files.push(file.clone());
+            }
+            merged.routes.push(route);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok((merged, origins))
+    } else {
+        Err(SwitchboardError::ConfigValidation { errors })
+    }
+}
+
+fn root_error(field: &str, message: String) -> ValidationError {
+    ValidationError {
+        route: "(root)".into(),
+        field: field.into(),
+        message,
+        suggestion: None,
+    }
+}
@@ -0,0 +1,196 @@
+//! Inline config passed through an environment variable or stdin.
+//!
+//! [`InlineSource`] implements [`ConfigSource`] over config text held in
+//! memory: the value of `SWITCHBOARD_CONFIG`, or whatever was piped to
+//! `--config -`. Serverless and container platforms can then ship a config
+//! without mounting a file. The text may be YAML, JSON, or TOML (the format
+//! is detected from its content) and may be base64-encoded as a whole,
+//! which keeps multi-line configs intact in env var editors that only
+//! accept a single line. Inline text is read once at startup, so the
+//! source never reports a change.
+
+use std::io::Read;
+
+use async_trait::async_trait;
+use base64::Engine;
+
+use super::{parse_config_str, resolve_and_validate, sha256_hex};
+use crate::config::model::Config;
+use crate::config::{ConfigSource, ConfigVersion};
+use crate::error::{SwitchboardError, ValidationError};
+
+pub struct InlineSource {
+    name: &'static str,
+    /// Where the text came from, for errors.
+    label: &'static str,
+    content: String,
+    profile: Option<String>,
+}
+
+impl InlineSource {
+    /// A source over the value of `SWITCHBOARD_CONFIG`.
+    pub fn from_env(value: &str) -> Result<Self, SwitchboardError> {
+        Self::new("env", "$SWITCHBOARD_CONFIG", value)
+    }
+
+    /// A source over everything on stdin. Blocks until stdin is closed.
+    pub fn from_stdin() -> Result<Self, SwitchboardError> {
+        let mut content = String::new();
+        std::io::stdin().read_to_string(&mut content)?;
+        Self::new("stdin", "<stdin>", &content)
+    }
+
+    fn new(
+        name: &'static str,
+        label: &'static str,
+        content: &str,
+    ) -> Result<Self, SwitchboardError> {
+        if content.trim().is_empty() {
+            return Err(SwitchboardError::ConfigParse {
+                path: label.to_string(),
+                source: "config is empty".into(),
+            });
+        }
+        Ok(Self {
+            name,
+            label,
+            content: decode_base64(content).unwrap_or_else(|| content.to_string()),
+            profile: None,
+        })
+    }
+
+    /// Merge the named profile onto every loaded config.
+    #[must_use]
+    pub fn with_profile(mut self, profile: Option<String>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Where the config came from: `$SWITCHBOARD_CONFIG` or `<stdin>`.
+    #[must_use]
+    pub fn label(&self) -> &str {
+        self.label
+    }
+
+    /// Parse the config without resolving or validating it.
+    pub fn parse(&self) -> Result<Config, SwitchboardError> {
+        let config = parse_config_str(detect_format(&self.content), &self.content, self.label)?;
+        if !config.include.is_empty() {
+            return Err(SwitchboardError::ConfigValidation {
+                errors: vec![ValidationError {
+                    route: "(root)".into(),
+                    field: "include".into(),
+                    message: format!("{}: inline configs cannot include files", self.label),
+                    suggestion: Some("mount the config and its fragments as files".into()),
+                }],
+            });
+        }
+        Ok(config)
+    }
+
+    fn version(&self) -> ConfigVersion {
+        ConfigVersion::Hash(sha256_hex(self.content.as_bytes()))
+    }
+}
+
+/// The decoded text if `content` is base64 as a whole. Config text never
+/// is: YAML and JSON need `:`, and TOML needs brackets for `routes`.
+fn decode_base64(content: &str) -> Option<String> {
+    let encoded: String = content.split_whitespace().collect();
+    if !encoded
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'='))
+    {
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .ok()?;
+    String::from_utf8(decoded).ok()
+}
+
+/// Guess the format of config text: JSON starts with `{`, TOML starts with
+/// a table header or a `key = value` line, and anything else is YAML.
+fn detect_format(content: &str) -> &'static str {
+    let first = content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .unwrap_or_default();
+    if first.starts_with('{') {
+        return "json";
+    }
+    let is_key_value = first
+        .split_once('=')
+        .is_some_and(|(key, _)| !key.contains(':') && !key.trim().contains(' '));
+    if first.starts_with('[') || is_key_value {
+        return "toml";
+    }
+    "yaml"
+}
+
+#[async_trait]
+impl ConfigSource for InlineSource {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    async fn load(&self) -> Result<(Config, ConfigVersion), SwitchboardError> {
+        let config = resolve_and_validate(self.parse()?, self.profile.as_deref())?;
+        Ok((config, self.version()))
+    }
+
+    async fn has_changed(&self, current: &ConfigVersion) -> Result<bool, SwitchboardError> {
+        Ok(*current != self.version())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = "routes:\n  - path: /a\n    targets:\n      - url: http://a:80\n";
+
+    #[test]
+    fn detects_format_from_content() {
+        assert_eq!(detect_format(YAML), "yaml");
+        assert_eq!(detect_format("# comment\n\n{\"routes\": []}"), "json");
+        assert_eq!(detect_format("[[routes]]\npath = \"/a\"\n"), "toml");
+        assert_eq!(detect_format("[defaults]\ntimeout = 100\n"), "toml");
+        assert_eq!(detect_format("include = [\"a.toml\"]\n"), "toml");
+        // YAML values containing '=' are still YAML
+        assert_eq!(detect_format("- path: /a?b=c\n"), "yaml");
+        assert_eq!(
+            detect_format("defaults: {headers: {add: {X: a=b}}}"),
+            "yaml"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn decodes_base64_content() {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(YAML);
+        let source = InlineSource::from_env(&format!("{encoded}\n")).unwrap();
+        assert_eq!(source.content, YAML);
+        assert_eq!(source.parse().unwrap().routes[0].path, "/a");
+
+        let source = InlineSource::from_env(YAML).unwrap();
+        assert_eq!(source.content, YAML);
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn rejects_includes() {
+        let source = InlineSource::from_env("include: [teams/*.yaml]\nroutes: []\n").unwrap();
+        assert!(matches!(
+            source.parse(),
+            Err(SwitchboardError::ConfigValidation { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_empty_content() {
+        let err = InlineSource::from_env("  \n").err().unwrap();
+        assert!(err.to_string().contains("$SWITCHBOARD_CONFIG"), "{err}");
+    }
+}
@@ -1,10 +1,22 @@
 //! Concrete [`ConfigSource`](super::ConfigSource) implementations.
 //!
 //! Provides file-based sources (YAML, JSON, TOML) gated by feature flags,
+//! an HTTP(S) source ([`http_source`]), a git source (`git_source`),
+//! inline configs from an env var or stdin ([`inline`]),
+//! multi-file configs assembled from fragments ([`include`]),
+//! filesystem watching for push reloads (`watch`),
 //! database backend stubs (Redis, `DynamoDB`, `PostgreSQL`, `MongoDB`, `SQLite`),
+//! the [`normalized`] relational schema for `SQLite` and `PostgreSQL`,
 //! and the [`parse_config_str`] helper for format-specific deserialization.
 
 pub mod file_source;
+#[cfg(feature = "git")]
+pub mod git_source;
+pub mod http_source;
+pub mod include;
+pub mod inline;
+#[cfg(feature = "watch")]
+pub mod watch;
 
 #[cfg(feature = "yaml")]
 pub mod yaml;
@@ -30,11 +42,23 @@ pub mod mongodb_source;
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
 
+#[cfg(feature = "sqlite")]
+pub mod sqlite_normalized;
+
+#[cfg(feature = "postgres")]
+pub mod postgres_normalized;
+
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+pub mod normalized;
+
 use sha2::{Digest, Sha256};
 
 use crate::config::model::Config;
+use crate::config::payload::PayloadKeys;
+use crate::config::profiles;
+use crate::config::secrets::interpolate;
 use crate::config::validation::validate;
-use crate::config::ConfigVersion;
+use crate::config::{ConfigVersion, StoredJson};
 use crate::error::SwitchboardError;
 
 /// Parse a config string based on file extension.
@@ -72,23 +96,45 @@ pub fn sha256_hex(data: &[u8]) -> String {
     format!("{:x}", Sha256::digest(data))
 }
 
-/// Deserialize JSON into [`Config`], validate, and compute a SHA-256 version hash.
+/// Open a stored config of `namespace` with `keys` (decrypting it and
+/// checking its signature, see [`payload`](crate::config::payload)),
+/// deserialize the JSON into [`Config`], apply `profile`, resolve secret
+/// references, validate, and compute a SHA-256 version hash of the stored
+/// form.
 ///
 /// Shared by all database config sources to avoid duplicating the
 /// parse-validate-hash pipeline.
 pub fn parse_validate_hash(
-    json: &str,
+    stored: &StoredJson,
     source_label: &str,
+    namespace: &str,
+    keys: &PayloadKeys,
+    profile: Option<&str>,
 ) -> Result<(Config, ConfigVersion), SwitchboardError> {
-    let config: Config = serde_json::from_str(json).map_err(|e| SwitchboardError::ConfigParse {
-        path: source_label.to_string(),
-        source: Box::new(e),
-    })?;
+    let json = keys.open(&stored.json, source_label, namespace, stored.version)?;
+    let config: Config =
+        serde_json::from_str(&json).map_err(|e| SwitchboardError::ConfigParse {
+            path: source_label.to_string(),
+            source: Box::new(e),
+        })?;
+    let config = resolve_and_validate(config, profile)?;
 
-    if let Err(errors) = validate(&config) {
+    let hash = sha256_hex(stored.json.as_bytes());
+    Ok((config, ConfigVersion::Hash(hash)))
+}
+
+/// Apply `profile` to a freshly parsed config, resolve secret references,
+/// and validate it, masking secrets in any validation errors.
+pub fn resolve_and_validate(
+    config: Config,
+    profile: Option<&str>,
+) -> Result<Config, SwitchboardError> {
+    let config = profiles::apply(config, profile)?;
+    let config = interpolate(config)?;
+
+    if let Err(mut errors) = validate(&config) {
+        config.secrets.mask_errors(&mut errors);
         return Err(SwitchboardError::ConfigValidation { errors });
     }
-
-    let hash = sha256_hex(json.as_bytes());
-    Ok((config, ConfigVersion::Hash(hash)))
+    Ok(config)
 }
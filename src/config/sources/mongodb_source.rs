@@ -4,23 +4,36 @@
 //! schema in the `switchboard.switchboard_config` collection is:
 //!
 //! ```json
-//! { "namespace": "default", "config_json": "{...}" }
+//! { "namespace": "default", "config_json": "{...}", "active_version": 3 }
 //! ```
+//!
+//! `switchboard config push` writes through [`ConfigStore`]: every push is
+//! kept as a document in `switchboard.switchboard_config_versions`, and the
+//! namespace's document above holds a copy of the active version along
+//! with its number, so that `config rollback` only has to copy an older
+//! version back.
 
 use async_trait::async_trait;
 use mongodb::bson::{doc, Document};
 use mongodb::{Client, Collection};
 
 use super::{parse_validate_hash, sha256_hex};
-use crate::config::{ConfigSource, ConfigVersion};
+use crate::config::payload::PayloadKeys;
+use crate::config::{
+    unix_now, ConfigSource, ConfigStore, ConfigVersion, StoredJson, StoredVersion, VersionMeta,
+};
 use crate::error::SwitchboardError;
 
 const DATABASE_NAME: &str = "switchboard";
 const COLLECTION_NAME: &str = "switchboard_config";
+const VERSIONS_COLLECTION_NAME: &str = "switchboard_config_versions";
 
 pub struct MongoDbSource {
     collection: Collection<Document>,
+    versions: Collection<Document>,
     namespace: String,
+    keys: PayloadKeys,
+    profile: Option<String>,
 }
 
 impl MongoDbSource {
@@ -41,38 +54,38 @@ impl MongoDbSource {
                 source: Box::new(e),
             })?;
 
-        let collection = client
-            .database(DATABASE_NAME)
-            .collection::<Document>(COLLECTION_NAME);
+        let database = client.database(DATABASE_NAME);
 
         Ok(Self {
-            collection,
+            collection: database.collection(COLLECTION_NAME),
+            versions: database.collection(VERSIONS_COLLECTION_NAME),
             namespace: namespace.to_owned(),
+            keys: PayloadKeys::default(),
+            profile: None,
         })
     }
 
-    async fn fetch_config_json(&self) -> Result<String, SwitchboardError> {
-        let filter = doc! { "namespace": &self.namespace };
+    /// Open stored configs with `keys`, to decrypt them and check their
+    /// signatures, and seal pushed ones with them.
+    #[must_use]
+    pub fn with_payload_keys(mut self, keys: PayloadKeys) -> Self {
+        self.keys = keys;
+        self
+    }
 
-        let document = self
-            .collection
-            .find_one(filter)
-            .await
-            .map_err(|e| SwitchboardError::Database {
-                backend: "mongodb",
-                source: Box::new(e),
-            })?
+    /// Merge the named profile onto every loaded config.
+    #[must_use]
+    pub fn with_profile(mut self, profile: Option<String>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    async fn fetch_config_json(&self) -> Result<StoredJson, SwitchboardError> {
+        self.read_json()
+            .await?
             .ok_or_else(|| SwitchboardError::Database {
                 backend: "mongodb",
                 source: format!("no document found for namespace '{}'", self.namespace).into(),
-            })?;
-
-        document
-            .get_str("config_json")
-            .map(std::borrow::ToOwned::to_owned)
-            .map_err(|e| SwitchboardError::Database {
-                backend: "mongodb",
-                source: Box::new(e),
             })
     }
 }
@@ -93,11 +106,166 @@ impl ConfigSource for MongoDbSource {
                 "mongodb://.../{DATABASE_NAME}/{COLLECTION_NAME}?namespace={}",
                 self.namespace
             ),
+            &self.namespace,
+            &self.keys,
+            self.profile.as_deref(),
         )
     }
 
     async fn has_changed(&self, current: &ConfigVersion) -> Result<bool, SwitchboardError> {
-        let json = self.fetch_config_json().await?;
-        Ok(*current != ConfigVersion::Hash(sha256_hex(json.as_bytes())))
+        let stored = self.fetch_config_json().await?;
+        Ok(*current != ConfigVersion::Hash(sha256_hex(stored.json.as_bytes())))
+    }
+}
+
+fn db_error(e: impl std::error::Error + Send + Sync + 'static) -> SwitchboardError {
+    SwitchboardError::Database {
+        backend: "mongodb",
+        source: Box::new(e),
+    }
+}
+
+/// A version number or timestamp as stored in an `Int64` field.
+fn stamp(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+impl MongoDbSource {
+    async fn insert_version(
+        &self,
+        version: u64,
+        config_json: &str,
+        author: &str,
+        comment: Option<&str>,
+    ) -> Result<(), SwitchboardError> {
+        self.versions
+            .insert_one(doc! {
+                "namespace": &self.namespace,
+                "version": stamp(version),
+                "config_json": config_json,
+                "author": author,
+                "created_at": stamp(unix_now()),
+                "comment": comment,
+            })
+            .await
+            .map_err(db_error)?;
+        Ok(())
+    }
+
+    async fn set_active(&self, version: u64, config_json: &str) -> Result<(), SwitchboardError> {
+        self.collection
+            .update_one(
+                doc! { "namespace": &self.namespace },
+                doc! { "$set": { "config_json": config_json, "active_version": stamp(version) } },
+            )
+            .upsert(true)
+            .await
+            .map_err(db_error)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ConfigStore for MongoDbSource {
+    async fn read_json(&self) -> Result<Option<StoredJson>, SwitchboardError> {
+        let filter = doc! { "namespace": &self.namespace };
+
+        let Some(document) = self.collection.find_one(filter).await.map_err(db_error)? else {
+            return Ok(None);
+        };
+
+        let json = document.get_str("config_json").map_err(db_error)?;
+        Ok(Some(StoredJson {
+            json: json.to_owned(),
+            version: document
+                .get_i64("active_version")
+                .ok()
+                .and_then(|v| u64::try_from(v).ok()),
+        }))
+    }
+
+    async fn push_json(&self, json: &str, meta: &VersionMeta) -> Result<u64, SwitchboardError> {
+        let latest = self
+            .versions
+            .find_one(doc! { "namespace": &self.namespace })
+            .sort(doc! { "version": -1 })
+            .await
+            .map_err(db_error)?;
+        let mut version = match latest {
+            Some(latest) => {
+                u64::try_from(latest.get_i64("version").map_err(db_error)?).unwrap_or_default()
+            }
+            None => 0,
+        };
+
+        // A config stored before versioning becomes version 1
+        if version == 0 {
+            if let Some(existing) = self.read_json().await? {
+                version = 1;
+                self.insert_version(
+                    version,
+                    &existing.json,
+                    "unknown",
+                    Some("stored before versioning"),
+                )
+                .await?;
+            }
+        }
+
+        version += 1;
+        let json = self.keys.seal(json, &self.namespace, version)?;
+        self.insert_version(version, &json, &meta.author, meta.comment.as_deref())
+            .await?;
+        self.set_active(version, &json).await?;
+        Ok(version)
+    }
+
+    async fn history(&self) -> Result<Vec<StoredVersion>, SwitchboardError> {
+        let active = self
+            .collection
+            .find_one(doc! { "namespace": &self.namespace })
+            .await
+            .map_err(db_error)?
+            .and_then(|doc| doc.get_i64("active_version").ok());
+
+        let mut cursor = self
+            .versions
+            .find(doc! { "namespace": &self.namespace })
+            .sort(doc! { "version": -1 })
+            .await
+            .map_err(db_error)?;
+
+        let mut versions = Vec::new();
+        while cursor.advance().await.map_err(db_error)? {
+            let doc = cursor.deserialize_current().map_err(db_error)?;
+            let version = doc.get_i64("version").map_err(db_error)?;
+            versions.push(StoredVersion {
+                version: u64::try_from(version).unwrap_or_default(),
+                author: doc.get_str("author").map_err(db_error)?.to_owned(),
+                created_at: u64::try_from(doc.get_i64("created_at").map_err(db_error)?)
+                    .unwrap_or_default(),
+                comment: doc.get_str("comment").ok().map(str::to_owned),
+                active: active == Some(version),
+            });
+        }
+        Ok(versions)
+    }
+
+    async fn activate(&self, version: u64) -> Result<(), SwitchboardError> {
+        let stored = self
+            .versions
+            .find_one(doc! { "namespace": &self.namespace, "version": stamp(version) })
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| SwitchboardError::Database {
+                backend: "mongodb",
+                source: format!(
+                    "version {version} not found for namespace '{}'",
+                    self.namespace
+                )
+                .into(),
+            })?;
+        let json = stored.get_str("config_json").map_err(db_error)?;
+        self.set_active(version, json).await
     }
 }
@@ -0,0 +1,684 @@
+//! Normalized relational schema for the `SQLite` and `PostgreSQL` sources.
+//!
+//! With `--sql-schema normalized` a config is stored across tables that
+//! can be edited and queried with plain SQL instead of as one JSON blob:
+//!
+//! | Table | Holds |
+//! |-------|-------|
+//! | `switchboard_namespaces` | one row per namespace: its `version` and `epoch`, plus `actuator_json` and `profiles_json` |
+//! | `switchboard_defaults` | one row per namespace; `NULL` columns keep the built-in default |
+//! | `switchboard_routes` | one row per route, ordered by `position`, unique per `(namespace, path)` |
+//! | `switchboard_targets` | one row per target, referencing its route by `route_id` |
+//! | `switchboard_header_rules` | one row per header rule: `direction` (`request`/`response`), `action` (`add`/`set_if_absent`/`strip`), `name`, `value`; `route_id` is `NULL` for `defaults` |
+//!
+//! Nested settings that are rarely queried (hedging, dedup, pools,
+//! outbound proxies, ordering keys, signing) are JSON columns in the
+//! config file's own format, and lists of methods or trusted proxies are
+//! comma-separated. Triggers bump the namespace's `version` on every
+//! change to its rows, which is what `has_changed` compares along with the
+//! random `epoch` the namespace row was created with.
+//!
+//! This module converts between [`Config`] and the rows; the backends
+//! ([`sqlite_normalized`](super::sqlite_normalized),
+//! [`postgres_normalized`](super::postgres_normalized)) read and write them.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::config::model::{Config, Defaults, HeaderRules, Route, Target};
+use crate::config::secrets::Secrets;
+use crate::config::ConfigVersion;
+use crate::error::SwitchboardError;
+
+/// Every row of one namespace.
+#[derive(Debug, Default)]
+pub struct Tables {
+    pub namespace: NamespaceRow,
+    pub defaults: Option<DefaultsRow>,
+    /// In route order.
+    pub routes: Vec<RouteRow>,
+    /// In target order within each route.
+    pub targets: Vec<TargetRow>,
+    pub header_rules: Vec<HeaderRuleRow>,
+}
+
+#[derive(Debug, Default)]
+pub struct NamespaceRow {
+    pub version: i64,
+    pub epoch: i64,
+    pub actuator_json: Option<String>,
+    pub profiles_json: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct DefaultsRow {
+    pub timeout_ms: Option<i64>,
+    pub forward_headers: Option<bool>,
+    pub proxy_headers: Option<bool>,
+    pub strip_hop_by_hop: Option<bool>,
+    pub forwarded_style: Option<String>,
+    pub trusted_proxies: Option<String>,
+    pub pool_json: Option<String>,
+    pub outbound_proxy_json: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct RouteRow {
+    /// The row id when read; the route's index when written.
+    pub id: i64,
+    pub path: String,
+    pub methods: Option<String>,
+    pub timeout_ms: Option<i64>,
+    pub forwarded_style: Option<String>,
+    pub hedge_json: Option<String>,
+    pub dedup_json: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct TargetRow {
+    pub route_id: i64,
+    pub url: String,
+    pub primary: bool,
+    pub timeout_ms: Option<i64>,
+    pub pool_json: Option<String>,
+    pub outbound_proxy_json: Option<String>,
+    pub ordering_key_json: Option<String>,
+    pub signing_json: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct HeaderRuleRow {
+    /// `None` for rules in `defaults`.
+    pub route_id: Option<i64>,
+    pub direction: String,
+    pub action: String,
+    pub name: String,
+    pub value: Option<String>,
+}
+
+/// `switchboard_defaults` columns in declaration order, as selected.
+pub type DefaultsColumns = (
+    Option<i64>,
+    Option<bool>,
+    Option<bool>,
+    Option<bool>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+impl From<DefaultsColumns> for DefaultsRow {
+    fn from(columns: DefaultsColumns) -> Self {
+        let (
+            timeout_ms,
+            forward_headers,
+            proxy_headers,
+            strip_hop_by_hop,
+            forwarded_style,
+            trusted_proxies,
+            pool_json,
+            outbound_proxy_json,
+        ) = columns;
+        Self {
+            timeout_ms,
+            forward_headers,
+            proxy_headers,
+            strip_hop_by_hop,
+            forwarded_style,
+            trusted_proxies,
+            pool_json,
+            outbound_proxy_json,
+        }
+    }
+}
+
+/// `switchboard_routes` columns in declaration order, as selected.
+pub type RouteColumns = (
+    i64,
+    String,
+    Option<String>,
+    Option<i64>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+impl From<RouteColumns> for RouteRow {
+    fn from(columns: RouteColumns) -> Self {
+        let (id, path, methods, timeout_ms, forwarded_style, hedge_json, dedup_json) = columns;
+        Self {
+            id,
+            path,
+            methods,
+            timeout_ms,
+            forwarded_style,
+            hedge_json,
+            dedup_json,
+        }
+    }
+}
+
+/// `switchboard_targets` columns in declaration order, as selected.
+pub type TargetColumns = (
+    i64,
+    String,
+    bool,
+    Option<i64>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+impl From<TargetColumns> for TargetRow {
+    fn from(columns: TargetColumns) -> Self {
+        let (
+            route_id,
+            url,
+            primary,
+            timeout_ms,
+            pool_json,
+            outbound_proxy_json,
+            ordering_key_json,
+            signing_json,
+        ) = columns;
+        Self {
+            route_id,
+            url,
+            primary,
+            timeout_ms,
+            pool_json,
+            outbound_proxy_json,
+            ordering_key_json,
+            signing_json,
+        }
+    }
+}
+
+/// `switchboard_header_rules` columns in declaration order, as selected.
+pub type HeaderRuleColumns = (Option<i64>, String, String, String, Option<String>);
+
+impl From<HeaderRuleColumns> for HeaderRuleRow {
+    fn from(columns: HeaderRuleColumns) -> Self {
+        let (route_id, direction, action, name, value) = columns;
+        Self {
+            route_id,
+            direction,
+            action,
+            name,
+            value,
+        }
+    }
+}
+
+/// The [`ConfigVersion`] of a namespace from its `version` and `epoch`
+/// columns. The counter starts over when a namespace is deleted and
+/// created again, but the epoch is drawn anew, so old versions don't repeat.
+#[must_use]
+pub fn version(version: i64, epoch: i64) -> ConfigVersion {
+    ConfigVersion::Hash(format!("v{version}-{epoch:016x}"))
+}
+
+/// The error for version history requests, which need the document schema.
+#[must_use]
+pub fn no_history(backend: &'static str) -> SwitchboardError {
+    SwitchboardError::Database {
+        backend,
+        source: "the normalized schema keeps no version history; use --sql-schema document".into(),
+    }
+}
+
+/// Reassemble the rows of a namespace into a config.
+pub fn assemble(tables: Tables, label: &str) -> Result<Config, SwitchboardError> {
+    let parse = Columns { label };
+    let Tables {
+        namespace,
+        defaults,
+        routes,
+        targets,
+        header_rules,
+    } = tables;
+
+    let mut config = Config {
+        actuator: parse
+            .json("actuator_json", namespace.actuator_json)?
+            .unwrap_or_default(),
+        defaults: match defaults {
+            Some(row) => parse.defaults(row)?,
+            None => Defaults::default(),
+        },
+        include: Vec::new(),
+        routes: Vec::with_capacity(routes.len()),
+        profiles: parse
+            .json::<BTreeMap<_, _>>("profiles_json", namespace.profiles_json)?
+            .unwrap_or_default(),
+        profile: None,
+        secrets: Secrets::default(),
+    };
+
+    let mut index = HashMap::with_capacity(routes.len());
+    for row in routes {
+        index.insert(row.id, config.routes.len());
+        config.routes.push(parse.route(row)?);
+    }
+    for row in targets {
+        let route = index
+            .get(&row.route_id)
+            .ok_or_else(|| parse.error("route_id", format!("no route {}", row.route_id)))?;
+        config.routes[*route].targets.push(parse.target(row)?);
+    }
+    for row in header_rules {
+        let (headers, response_headers) = match row.route_id {
+            None => (
+                &mut config.defaults.headers,
+                &mut config.defaults.response_headers,
+            ),
+            Some(id) => {
+                let route = index
+                    .get(&id)
+                    .ok_or_else(|| parse.error("route_id", format!("no route {id}")))?;
+                let route = &mut config.routes[*route];
+                (&mut route.headers, &mut route.response_headers)
+            }
+        };
+        let rules = match row.direction.as_str() {
+            "request" => headers,
+            "response" => response_headers,
+            other => return Err(parse.error("direction", format!("unknown direction '{other}'"))),
+        };
+        parse.header_rule(rules, row)?;
+    }
+    Ok(config)
+}
+
+/// Split a config into the rows of a namespace. Route ids are the routes'
+/// indexes, for the backend to replace with the ids it inserts them as.
+pub fn decompose(config: &Config) -> Result<Tables, SwitchboardError> {
+    let Config {
+        actuator,
+        defaults,
+        include: _,
+        routes,
+        profiles,
+        profile: _,
+        secrets: _,
+    } = config;
+
+    let mut tables = Tables {
+        namespace: NamespaceRow {
+            actuator_json: Some(json(actuator)?),
+            profiles_json: (!profiles.is_empty()).then(|| json(profiles)).transpose()?,
+            ..NamespaceRow::default()
+        },
+        defaults: Some(defaults_row(defaults)?),
+        ..Tables::default()
+    };
+    header_rows(&mut tables.header_rules, None, "request", &defaults.headers);
+    header_rows(
+        &mut tables.header_rules,
+        None,
+        "response",
+        &defaults.response_headers,
+    );
+
+    for (id, route) in (0..).zip(routes) {
+        let Route {
+            path,
+            methods,
+            timeout,
+            forwarded_style,
+            headers,
+            response_headers,
+            hedge,
+            dedup,
+            targets,
+        } = route;
+        tables.routes.push(RouteRow {
+            id,
+            path: path.clone(),
+            methods: Some(methods.join(",")),
+            timeout_ms: timeout.map(millis),
+            forwarded_style: forwarded_style.as_ref().map(text).transpose()?,
+            hedge_json: hedge.as_ref().map(json).transpose()?,
+            dedup_json: dedup.as_ref().map(json).transpose()?,
+        });
+        header_rows(&mut tables.header_rules, Some(id), "request", headers);
+        header_rows(
+            &mut tables.header_rules,
+            Some(id),
+            "response",
+            response_headers,
+        );
+
+        for target in targets {
+            let Target {
+                url,
+                primary,
+                timeout,
+                pool,
+                outbound_proxy,
+                ordering_key,
+                signing,
+            } = target;
+            tables.targets.push(TargetRow {
+                route_id: id,
+                url: url.clone(),
+                primary: *primary,
+                timeout_ms: timeout.map(millis),
+                pool_json: pool.as_ref().map(json).transpose()?,
+                outbound_proxy_json: outbound_proxy.as_ref().map(json).transpose()?,
+                ordering_key_json: ordering_key.as_ref().map(json).transpose()?,
+                signing_json: signing.as_ref().map(json).transpose()?,
+            });
+        }
+    }
+    Ok(tables)
+}
+
+fn defaults_row(defaults: &Defaults) -> Result<DefaultsRow, SwitchboardError> {
+    let Defaults {
+        timeout,
+        forward_headers,
+        proxy_headers,
+        strip_hop_by_hop,
+        forwarded_style,
+        trusted_proxies,
+        headers: _,
+        response_headers: _,
+        pool,
+        outbound_proxy,
+    } = defaults;
+    let builtin = Defaults::default();
+    let changed = |value: bool, default: bool| (value != default).then_some(value);
+
+    Ok(DefaultsRow {
+        timeout_ms: (*timeout != builtin.timeout).then(|| millis(*timeout)),
+        forward_headers: changed(*forward_headers, builtin.forward_headers),
+        proxy_headers: changed(*proxy_headers, builtin.proxy_headers),
+        strip_hop_by_hop: changed(*strip_hop_by_hop, builtin.strip_hop_by_hop),
+        forwarded_style: (*forwarded_style != builtin.forwarded_style)
+            .then(|| text(forwarded_style))
+            .transpose()?,
+        trusted_proxies: (!trusted_proxies.is_empty()).then(|| trusted_proxies.join(",")),
+        pool_json: (*pool != builtin.pool).then(|| json(pool)).transpose()?,
+        outbound_proxy_json: outbound_proxy.as_ref().map(json).transpose()?,
+    })
+}
+
+fn header_rows(
+    rows: &mut Vec<HeaderRuleRow>,
+    route_id: Option<i64>,
+    direction: &str,
+    rules: &HeaderRules,
+) {
+    let HeaderRules {
+        add,
+        strip,
+        set_if_absent,
+    } = rules;
+    let row = |action: &str, name: &str, value: Option<&String>| HeaderRuleRow {
+        route_id,
+        direction: direction.into(),
+        action: action.into(),
+        name: name.into(),
+        value: value.cloned(),
+    };
+    for (action, headers) in [("add", add), ("set_if_absent", set_if_absent)] {
+        let mut headers: Vec<_> = headers.iter().collect();
+        headers.sort();
+        rows.extend(headers.into_iter().map(|(n, v)| row(action, n, Some(v))));
+    }
+    rows.extend(strip.iter().map(|name| row("strip", name, None)));
+}
+
+/// A setting as a JSON column.
+fn json<T: Serialize>(value: &T) -> Result<String, SwitchboardError> {
+    serde_json::to_string(value)
+        .map_err(|e| SwitchboardError::Io(std::io::Error::other(e.to_string())))
+}
+
+/// A unit enum as the text it has in config files, e.g. `x-forwarded`.
+fn text<T: Serialize>(value: &T) -> Result<String, SwitchboardError> {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(text)) => Ok(text),
+        _ => Err(SwitchboardError::Io(std::io::Error::other(
+            "expected a unit enum",
+        ))),
+    }
+}
+
+fn millis(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+fn list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Parses column values, naming the column in errors.
+struct Columns<'a> {
+    label: &'a str,
+}
+
+impl Columns<'_> {
+    fn error(&self, column: &str, reason: impl std::fmt::Display) -> SwitchboardError {
+        SwitchboardError::ConfigParse {
+            path: self.label.to_string(),
+            source: format!("{column}: {reason}").into(),
+        }
+    }
+
+    fn json<T: DeserializeOwned>(
+        &self,
+        column: &str,
+        value: Option<String>,
+    ) -> Result<Option<T>, SwitchboardError> {
+        value
+            .map(|json| serde_json::from_str(&json).map_err(|e| self.error(column, e)))
+            .transpose()
+    }
+
+    fn text<T: DeserializeOwned>(
+        &self,
+        column: &str,
+        value: Option<String>,
+    ) -> Result<Option<T>, SwitchboardError> {
+        value
+            .map(|text| {
+                serde_json::from_value(serde_json::Value::String(text))
+                    .map_err(|e| self.error(column, e))
+            })
+            .transpose()
+    }
+
+    fn millis(&self, column: &str, value: Option<i64>) -> Result<Option<u64>, SwitchboardError> {
+        value
+            .map(|ms| u64::try_from(ms).map_err(|_| self.error(column, "must not be negative")))
+            .transpose()
+    }
+
+    fn defaults(&self, row: DefaultsRow) -> Result<Defaults, SwitchboardError> {
+        let builtin = Defaults::default();
+        Ok(Defaults {
+            timeout: self
+                .millis("timeout_ms", row.timeout_ms)?
+                .unwrap_or(builtin.timeout),
+            forward_headers: row.forward_headers.unwrap_or(builtin.forward_headers),
+            proxy_headers: row.proxy_headers.unwrap_or(builtin.proxy_headers),
+            strip_hop_by_hop: row.strip_hop_by_hop.unwrap_or(builtin.strip_hop_by_hop),
+            forwarded_style: self
+                .text("forwarded_style", row.forwarded_style)?
+                .unwrap_or(builtin.forwarded_style),
+            trusted_proxies: row.trusted_proxies.as_deref().map(list).unwrap_or_default(),
+            headers: HeaderRules::default(),
+            response_headers: HeaderRules::default(),
+            pool: self
+                .json("pool_json", row.pool_json)?
+                .unwrap_or(builtin.pool),
+            outbound_proxy: self.json("outbound_proxy_json", row.outbound_proxy_json)?,
+        })
+    }
+
+    fn route(&self, row: RouteRow) -> Result<Route, SwitchboardError> {
+        Ok(Route {
+            path: row.path,
+            methods: row
+                .methods
+                .as_deref()
+                .map_or_else(|| vec!["*".to_string()], list),
+            timeout: self.millis("timeout_ms", row.timeout_ms)?,
+            forwarded_style: self.text("forwarded_style", row.forwarded_style)?,
+            headers: HeaderRules::default(),
+            response_headers: HeaderRules::default(),
+            hedge: self.json("hedge_json", row.hedge_json)?,
+            dedup: self.json("dedup_json", row.dedup_json)?,
+            targets: Vec::new(),
+        })
+    }
+
+    fn target(&self, row: TargetRow) -> Result<Target, SwitchboardError> {
+        Ok(Target {
+            url: row.url,
+            primary: row.primary,
+            timeout: self.millis("timeout_ms", row.timeout_ms)?,
+            pool: self.json("pool_json", row.pool_json)?,
+            outbound_proxy: self.json("outbound_proxy_json", row.outbound_proxy_json)?,
+            ordering_key: self.json("ordering_key_json", row.ordering_key_json)?,
+            signing: self.json("signing_json", row.signing_json)?,
+        })
+    }
+
+    fn header_rule(
+        &self,
+        rules: &mut HeaderRules,
+        row: HeaderRuleRow,
+    ) -> Result<(), SwitchboardError> {
+        let value = || {
+            row.value
+                .clone()
+                .ok_or_else(|| self.error("value", format!("missing for header '{}'", row.name)))
+        };
+        match row.action.as_str() {
+            "add" => {
+                rules.add.insert(row.name.clone(), value()?);
+            }
+            "set_if_absent" => {
+                rules.set_if_absent.insert(row.name.clone(), value()?);
+            }
+            "strip" => rules.strip.push(row.name),
+            other => return Err(self.error("action", format!("unknown action '{other}'"))),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(json: &str) -> Config {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn configs_round_trip_through_rows() {
+        let original = config(
+            r#"{
+                "actuator": {"enabled": true},
+                "defaults": {
+                    "timeout": 2500,
+                    "proxy_headers": false,
+                    "forwarded_style": "both",
+                    "trusted_proxies": ["10.0.0.0/8", "192.168.0.0/16"],
+                    "headers": {"add": {"X-Env": "prod"}, "strip": ["Cookie"]},
+                    "pool": {"idle_timeout": 1000, "nodelay": true}
+                },
+                "routes": [
+                    {
+                        "path": "/orders/:id",
+                        "methods": ["GET", "POST"],
+                        "timeout": 800,
+                        "response_headers": {"set_if_absent": {"Cache-Control": "no-store"}},
+                        "hedge": {"url": "http://backup:80", "delay": 50},
+                        "targets": [
+                            {"url": "http://orders:80", "primary": true},
+                            {
+                                "url": "http://audit:80",
+                                "ordering_key": {"param": "id"},
+                                "signing": {"secret": "${env:AUDIT_SECRET}"}
+                            }
+                        ]
+                    },
+                    {"path": "/health", "targets": [{"url": "http://health:80"}]}
+                ],
+                "profiles": {"dev": {"defaults": {"timeout": 100}}}
+            }"#,
+        );
+
+        let tables = decompose(&original).unwrap();
+        assert_eq!(tables.routes.len(), 2);
+        assert_eq!(tables.targets.len(), 3);
+        assert_eq!(tables.header_rules.len(), 3);
+        let defaults = tables.defaults.as_ref().unwrap();
+        assert_eq!(defaults.timeout_ms, Some(2500));
+        assert_eq!(defaults.forward_headers, None);
+        assert_eq!(defaults.forwarded_style.as_deref(), Some("both"));
+        assert_eq!(tables.routes[0].methods.as_deref(), Some("GET,POST"));
+
+        let assembled = assemble(tables, "test").unwrap();
+        assert_eq!(
+            serde_json::to_value(&assembled).unwrap(),
+            serde_json::to_value(&original).unwrap()
+        );
+    }
+
+    #[test]
+    fn rows_edited_by_hand_are_checked() {
+        let row = |route_id| TargetRow {
+            route_id,
+            url: "http://a:80".into(),
+            primary: false,
+            timeout_ms: Some(-1),
+            pool_json: None,
+            outbound_proxy_json: None,
+            ordering_key_json: None,
+            signing_json: None,
+        };
+        let tables = |target| Tables {
+            routes: vec![RouteRow {
+                id: 7,
+                path: "/a".into(),
+                methods: None,
+                timeout_ms: None,
+                forwarded_style: None,
+                hedge_json: None,
+                dedup_json: None,
+            }],
+            targets: vec![target],
+            ..Tables::default()
+        };
+
+        let err = assemble(tables(row(7)), "sqlite::default").unwrap_err();
+        assert!(err.to_string().contains("timeout_ms"), "{err}");
+
+        let mut target = row(7);
+        target.timeout_ms = None;
+        target.pool_json = Some(r#"{"idle": 5}"#.into());
+        let err = assemble(tables(target), "sqlite::default").unwrap_err();
+        assert!(err.to_string().contains("pool_json"), "{err}");
+
+        let mut target = row(7);
+        target.timeout_ms = None;
+        let config = assemble(tables(target), "sqlite::default").unwrap();
+        assert_eq!(config.routes[0].methods, ["*"]);
+        assert_eq!(config.routes[0].targets[0].url, "http://a:80");
+    }
+}
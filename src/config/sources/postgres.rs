@@ -3,59 +3,270 @@
 //! Stores and retrieves Switchboard configuration from a `switchboard_config`
 //! table keyed by namespace. The table is auto-created on first connection.
 //! Change detection uses SHA-256 hashing of the raw JSON payload.
+//!
+//! `switchboard config push` writes through [`ConfigStore`]: every push is
+//! kept in `switchboard_config_versions`, and the `switchboard_config` row
+//! holds a copy of the active version along with its number, so that
+//! `config rollback` only has to copy an older version back. Databases
+//! created before versioning are migrated on connection, with the stored
+//! config becoming version 1.
+//!
+//! Changes are pushed with `LISTEN switchboard_config`: a trigger installed
+//! alongside the table sends `NOTIFY switchboard_config, '<namespace>'` on
+//! every insert or update. A dropped listener is reconnected while polling
+//! carries on.
+
+use std::time::Duration;
 
 use async_trait::async_trait;
+use sqlx::postgres::PgListener;
 use sqlx::PgPool;
+use tokio::sync::mpsc;
 
 use super::{parse_validate_hash, sha256_hex};
-use crate::config::{ConfigSource, ConfigVersion};
+use crate::config::payload::PayloadKeys;
+use crate::config::{
+    unix_now, ChangeStream, ConfigSource, ConfigStore, ConfigVersion, StoredJson, StoredVersion,
+    VersionMeta,
+};
 use crate::error::SwitchboardError;
 
+/// Channel the change trigger notifies, with the namespace as payload.
+const NOTIFY_CHANNEL: &str = "switchboard_config";
+
+/// Delay before listening again after the listener fails.
+const RELISTEN_DELAY: Duration = Duration::from_secs(5);
+
+/// How often a push is retried after losing a race with another push.
+const PUSH_ATTEMPTS: usize = 5;
+
 pub struct PostgresSource {
     pool: PgPool,
     namespace: String,
+    keys: PayloadKeys,
+    profile: Option<String>,
 }
 
 impl PostgresSource {
     pub async fn new(url: &str, namespace: &str) -> Result<Self, SwitchboardError> {
-        let pool = PgPool::connect(url)
-            .await
-            .map_err(|e| SwitchboardError::Database {
-                backend: "postgres",
-                source: Box::new(e),
-            })?;
+        let pool = PgPool::connect(url).await.map_err(db_error)?;
 
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS switchboard_config (\
-                namespace TEXT PRIMARY KEY, \
-                config_json TEXT NOT NULL\
-            )",
-        )
-        .execute(&pool)
-        .await
-        .map_err(|e| SwitchboardError::Database {
-            backend: "postgres",
-            source: Box::new(e),
-        })?;
+        migrate(&pool).await.map_err(db_error)?;
+
+        // Without the trigger, changes are still picked up by polling
+        if let Err(e) = install_notify_trigger(&pool).await {
+            tracing::warn!(error = %e, "cannot install postgres config change trigger, relying on polling");
+        }
 
         Ok(Self {
             pool,
             namespace: namespace.to_string(),
+            keys: PayloadKeys::default(),
+            profile: None,
         })
     }
 
-    async fn fetch_config_json(&self) -> Result<String, SwitchboardError> {
-        sqlx::query_scalar::<_, String>(
-            "SELECT config_json FROM switchboard_config WHERE namespace = $1",
+    /// Open stored configs with `keys`, to decrypt them and check their
+    /// signatures, and seal pushed ones with them.
+    #[must_use]
+    pub fn with_payload_keys(mut self, keys: PayloadKeys) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// Merge the named profile onto every loaded config.
+    #[must_use]
+    pub fn with_profile(mut self, profile: Option<String>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    async fn fetch_config_json(&self) -> Result<StoredJson, SwitchboardError> {
+        self.read_json()
+            .await?
+            .ok_or_else(|| SwitchboardError::Database {
+                backend: "postgres",
+                source: format!("no config row found for namespace '{}'", self.namespace).into(),
+            })
+    }
+
+    /// One push attempt, `None` when a concurrent push took the version
+    /// number first.
+    async fn try_push(
+        &self,
+        json: &str,
+        meta: &VersionMeta,
+    ) -> Result<Option<u64>, SwitchboardError> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+        // Serialize pushes to the namespace until commit, so concurrent
+        // pushes don't pick the same version number
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind(&self.namespace)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        let version: i64 = sqlx::query_scalar(
+            "SELECT COALESCE(MAX(version), 0) + 1 FROM switchboard_config_versions \
+             WHERE namespace = $1",
         )
         .bind(&self.namespace)
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await
-        .map_err(|e| SwitchboardError::Database {
-            backend: "postgres",
-            source: Box::new(e),
-        })
+        .map_err(db_error)?;
+        let json = self.keys.seal(
+            json,
+            &self.namespace,
+            u64::try_from(version).unwrap_or_default(),
+        )?;
+
+        let inserted = sqlx::query(
+            "INSERT INTO switchboard_config_versions \
+                (namespace, version, config_json, author, created_at, comment) \
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&self.namespace)
+        .bind(version)
+        .bind(&json)
+        .bind(&meta.author)
+        .bind(stamp(unix_now()))
+        .bind(&meta.comment)
+        .execute(&mut *tx)
+        .await;
+        match inserted {
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => return Ok(None),
+            inserted => inserted.map_err(db_error)?,
+        };
+
+        sqlx::query(
+            "INSERT INTO switchboard_config (namespace, config_json, active_version) \
+             VALUES ($1, $2, $3) \
+             ON CONFLICT (namespace) DO UPDATE SET \
+                config_json = EXCLUDED.config_json, \
+                active_version = EXCLUDED.active_version",
+        )
+        .bind(&self.namespace)
+        .bind(&json)
+        .bind(version)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+        tx.commit().await.map_err(db_error)?;
+        Ok(Some(u64::try_from(version).unwrap_or_default()))
+    }
+}
+
+pub(super) fn db_error(e: sqlx::Error) -> SwitchboardError {
+    SwitchboardError::Database {
+        backend: "postgres",
+        source: Box::new(e),
+    }
+}
+
+/// Create the config tables, adding version history to databases created
+/// before it existed.
+async fn migrate(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::raw_sql(
+        "CREATE TABLE IF NOT EXISTS switchboard_config (\
+            namespace TEXT PRIMARY KEY, \
+            config_json TEXT NOT NULL\
+         ); \
+         ALTER TABLE switchboard_config ADD COLUMN IF NOT EXISTS active_version BIGINT; \
+         CREATE TABLE IF NOT EXISTS switchboard_config_versions (\
+            namespace TEXT NOT NULL, \
+            version BIGINT NOT NULL, \
+            config_json TEXT NOT NULL, \
+            author TEXT NOT NULL, \
+            created_at BIGINT NOT NULL, \
+            comment TEXT, \
+            PRIMARY KEY (namespace, version)\
+         ); \
+         INSERT INTO switchboard_config_versions \
+            (namespace, version, config_json, author, created_at, comment) \
+         SELECT namespace, 1, config_json, 'unknown', EXTRACT(EPOCH FROM now())::BIGINT, \
+            'stored before versioning' \
+         FROM switchboard_config WHERE active_version IS NULL \
+         ON CONFLICT DO NOTHING; \
+         UPDATE switchboard_config SET active_version = 1 WHERE active_version IS NULL",
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await
+}
+
+/// A version number or timestamp as stored in a BIGINT column.
+fn stamp(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+async fn install_notify_trigger(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::raw_sql(
+        "CREATE OR REPLACE FUNCTION switchboard_config_notify() RETURNS trigger AS $$ \
+         BEGIN \
+            PERFORM pg_notify('switchboard_config', NEW.namespace); \
+            RETURN NEW; \
+         END; \
+         $$ LANGUAGE plpgsql; \
+         DO $$ BEGIN \
+            IF NOT EXISTS (SELECT 1 FROM pg_trigger WHERE tgname = 'switchboard_config_notify') THEN \
+                CREATE TRIGGER switchboard_config_notify \
+                AFTER INSERT OR UPDATE ON switchboard_config \
+                FOR EACH ROW EXECUTE FUNCTION switchboard_config_notify(); \
+            END IF; \
+         END $$",
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Forward notifications for `namespace` to `changes` until the listener
+/// fails or `changes` is dropped.
+async fn listen(
+    pool: &PgPool,
+    namespace: &str,
+    changes: &mpsc::Sender<()>,
+) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(NOTIFY_CHANNEL).await?;
+    tracing::debug!(
+        channel = NOTIFY_CHANNEL,
+        "listening for postgres config changes"
+    );
+
+    // A change may have been missed while (re)connecting
+    let _ = changes.try_send(());
+    while !changes.is_closed() {
+        match listener.try_recv().await? {
+            Some(notification) if notification.payload() != namespace => {}
+            // `None`: the connection was lost and is re-established on the
+            // next call, so notifications may have been missed
+            Some(_) | None => {
+                let _ = changes.try_send(());
+            }
+        }
     }
+    Ok(())
+}
+
+/// Stream notifications for `namespace` from a background listener that
+/// reconnects after failures.
+pub(super) fn watch_namespace(pool: PgPool, namespace: String) -> ChangeStream {
+    let (tx, rx) = mpsc::channel(1);
+    tokio::spawn(async move {
+        loop {
+            let result = listen(&pool, &namespace, &tx).await;
+            if tx.is_closed() {
+                return;
+            }
+            if let Err(e) = result {
+                tracing::warn!(error = %e, "postgres config listener failed, polling until it reconnects");
+            }
+            tokio::time::sleep(RELISTEN_DELAY).await;
+        }
+    });
+    rx
 }
 
 #[async_trait]
@@ -68,11 +279,113 @@ impl ConfigSource for PostgresSource {
         &self,
     ) -> Result<(crate::config::model::Config, ConfigVersion), SwitchboardError> {
         let json = self.fetch_config_json().await?;
-        parse_validate_hash(&json, &format!("postgres::{}", self.namespace))
+        parse_validate_hash(
+            &json,
+            &format!("postgres::{}", self.namespace),
+            &self.namespace,
+            &self.keys,
+            self.profile.as_deref(),
+        )
     }
 
     async fn has_changed(&self, current: &ConfigVersion) -> Result<bool, SwitchboardError> {
-        let json = self.fetch_config_json().await?;
-        Ok(*current != ConfigVersion::Hash(sha256_hex(json.as_bytes())))
+        let stored = self.fetch_config_json().await?;
+        Ok(*current != ConfigVersion::Hash(sha256_hex(stored.json.as_bytes())))
+    }
+
+    async fn watch(&self) -> Result<Option<ChangeStream>, SwitchboardError> {
+        Ok(Some(watch_namespace(
+            self.pool.clone(),
+            self.namespace.clone(),
+        )))
+    }
+}
+
+#[async_trait]
+impl ConfigStore for PostgresSource {
+    async fn read_json(&self) -> Result<Option<StoredJson>, SwitchboardError> {
+        let row: Option<(String, Option<i64>)> = sqlx::query_as(
+            "SELECT config_json, active_version FROM switchboard_config WHERE namespace = $1",
+        )
+        .bind(&self.namespace)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_error)?;
+        Ok(row.map(|(json, version)| StoredJson {
+            json,
+            version: version.and_then(|v| u64::try_from(v).ok()),
+        }))
+    }
+
+    async fn push_json(&self, json: &str, meta: &VersionMeta) -> Result<u64, SwitchboardError> {
+        for _ in 0..PUSH_ATTEMPTS {
+            if let Some(version) = self.try_push(json, meta).await? {
+                return Ok(version);
+            }
+        }
+        Err(SwitchboardError::Database {
+            backend: "postgres",
+            source: format!(
+                "push to '{}' kept conflicting with concurrent pushes",
+                self.namespace
+            )
+            .into(),
+        })
+    }
+
+    async fn history(&self) -> Result<Vec<StoredVersion>, SwitchboardError> {
+        let rows: Vec<(i64, String, i64, Option<String>, bool)> = sqlx::query_as(
+            "SELECT v.version, v.author, v.created_at, v.comment, \
+                COALESCE(c.active_version = v.version, FALSE) \
+             FROM switchboard_config_versions v \
+             LEFT JOIN switchboard_config c ON c.namespace = v.namespace \
+             WHERE v.namespace = $1 \
+             ORDER BY v.version DESC",
+        )
+        .bind(&self.namespace)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        Ok(rows
+            .into_iter()
+            .map(
+                |(version, author, created_at, comment, active)| StoredVersion {
+                    version: u64::try_from(version).unwrap_or_default(),
+                    author,
+                    created_at: u64::try_from(created_at).unwrap_or_default(),
+                    comment,
+                    active,
+                },
+            )
+            .collect())
+    }
+
+    async fn activate(&self, version: u64) -> Result<(), SwitchboardError> {
+        let result = sqlx::query(
+            "INSERT INTO switchboard_config (namespace, config_json, active_version) \
+             SELECT namespace, config_json, version FROM switchboard_config_versions \
+             WHERE namespace = $1 AND version = $2 \
+             ON CONFLICT (namespace) DO UPDATE SET \
+                config_json = EXCLUDED.config_json, \
+                active_version = EXCLUDED.active_version",
+        )
+        .bind(&self.namespace)
+        .bind(stamp(version))
+        .execute(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        if result.rows_affected() == 0 {
+            return Err(SwitchboardError::Database {
+                backend: "postgres",
+                source: format!(
+                    "version {version} not found for namespace '{}'",
+                    self.namespace
+                )
+                .into(),
+            });
+        }
+        Ok(())
     }
 }
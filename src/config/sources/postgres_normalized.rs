@@ -0,0 +1,463 @@
+//! PostgreSQL-backed [`ConfigSource`] over the normalized schema.
+//!
+//! Selected with `--sql-schema normalized`; see [`normalized`](super::normalized)
+//! for the tables. They are created on first connection, along with a
+//! trigger function that bumps the namespace's `version` whenever one of
+//! its rows changes and sends `NOTIFY switchboard_config, '<namespace>'`,
+//! so that edits made with plain SQL are pushed to running instances just
+//! like with the document schema. No version history is kept, so
+//! `config history` and `config rollback` need the document schema.
+
+use async_trait::async_trait;
+use sqlx::{PgExecutor, PgPool};
+
+use super::normalized::{
+    self, DefaultsColumns, DefaultsRow, HeaderRuleColumns, HeaderRuleRow, NamespaceRow,
+    RouteColumns, RouteRow, Tables, TargetColumns, TargetRow,
+};
+use super::postgres::{db_error, watch_namespace};
+use super::resolve_and_validate;
+use crate::config::model::Config;
+use crate::config::{
+    ChangeStream, ConfigSource, ConfigStore, ConfigVersion, StoredJson, StoredVersion, VersionMeta,
+};
+use crate::error::SwitchboardError;
+
+pub struct NormalizedPostgresSource {
+    pool: PgPool,
+    namespace: String,
+    profile: Option<String>,
+}
+
+impl NormalizedPostgresSource {
+    pub async fn new(url: &str, namespace: &str) -> Result<Self, SwitchboardError> {
+        let pool = PgPool::connect(url).await.map_err(db_error)?;
+
+        migrate(&pool).await.map_err(db_error)?;
+
+        Ok(Self {
+            pool,
+            namespace: namespace.to_string(),
+            profile: None,
+        })
+    }
+
+    /// Merge the named profile onto every loaded config.
+    #[must_use]
+    pub fn with_profile(mut self, profile: Option<String>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    fn label(&self) -> String {
+        format!("postgres::{}", self.namespace)
+    }
+
+    /// Every row of the namespace, read from one snapshot, or `None` if the
+    /// namespace has no rows.
+    async fn read_tables(&self) -> Result<Option<Tables>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+            .execute(&mut *tx)
+            .await?;
+
+        let Some((version, epoch, actuator_json, profiles_json)) = sqlx::query_as(
+            "SELECT version, epoch, actuator_json, profiles_json FROM switchboard_namespaces \
+             WHERE namespace = $1",
+        )
+        .bind(&self.namespace)
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        let defaults = sqlx::query_as::<_, DefaultsColumns>(
+            "SELECT timeout_ms, forward_headers, proxy_headers, strip_hop_by_hop, \
+                forwarded_style, trusted_proxies, pool_json, outbound_proxy_json \
+             FROM switchboard_defaults WHERE namespace = $1",
+        )
+        .bind(&self.namespace)
+        .fetch_optional(&mut *tx)
+        .await?
+        .map(DefaultsRow::from);
+
+        let routes = sqlx::query_as::<_, RouteColumns>(
+            "SELECT id, path, methods, timeout_ms, forwarded_style, hedge_json, dedup_json \
+             FROM switchboard_routes WHERE namespace = $1 ORDER BY position, id",
+        )
+        .bind(&self.namespace)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(RouteRow::from)
+        .collect();
+
+        let targets = sqlx::query_as::<_, TargetColumns>(
+            "SELECT t.route_id, t.url, t.is_primary, t.timeout_ms, t.pool_json, \
+                t.outbound_proxy_json, t.ordering_key_json, t.signing_json \
+             FROM switchboard_targets t JOIN switchboard_routes r ON r.id = t.route_id \
+             WHERE r.namespace = $1 ORDER BY t.position, t.id",
+        )
+        .bind(&self.namespace)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(TargetRow::from)
+        .collect();
+
+        let header_rules = sqlx::query_as::<_, HeaderRuleColumns>(
+            "SELECT route_id, direction, action, name, value FROM switchboard_header_rules \
+             WHERE namespace = $1 ORDER BY id",
+        )
+        .bind(&self.namespace)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(HeaderRuleRow::from)
+        .collect();
+
+        tx.commit().await?;
+        Ok(Some(Tables {
+            namespace: NamespaceRow {
+                version,
+                epoch,
+                actuator_json,
+                profiles_json,
+            },
+            defaults,
+            routes,
+            targets,
+            header_rules,
+        }))
+    }
+
+    async fn fetch_config(&self) -> Result<(Config, ConfigVersion), SwitchboardError> {
+        let tables = self.read_tables().await.map_err(db_error)?.ok_or_else(|| {
+            SwitchboardError::Database {
+                backend: "postgres",
+                source: format!("no config rows found for namespace '{}'", self.namespace).into(),
+            }
+        })?;
+        let version = normalized::version(tables.namespace.version, tables.namespace.epoch);
+        Ok((normalized::assemble(tables, &self.label())?, version))
+    }
+
+    /// Replace the namespace's rows with `tables`, returning its new version.
+    async fn write_tables(&self, tables: Tables) -> Result<i64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for table in [
+            "switchboard_header_rules",
+            "switchboard_routes",
+            "switchboard_defaults",
+        ] {
+            sqlx::query(&format!("DELETE FROM {table} WHERE namespace = $1"))
+                .bind(&self.namespace)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        sqlx::query(
+            "INSERT INTO switchboard_namespaces (namespace, actuator_json, profiles_json) \
+             VALUES ($1, $2, $3) \
+             ON CONFLICT (namespace) DO UPDATE SET \
+                actuator_json = EXCLUDED.actuator_json, \
+                profiles_json = EXCLUDED.profiles_json",
+        )
+        .bind(&self.namespace)
+        .bind(&tables.namespace.actuator_json)
+        .bind(&tables.namespace.profiles_json)
+        .execute(&mut *tx)
+        .await?;
+
+        if let Some(ref row) = tables.defaults {
+            sqlx::query(
+                "INSERT INTO switchboard_defaults (namespace, timeout_ms, forward_headers, \
+                    proxy_headers, strip_hop_by_hop, forwarded_style, trusted_proxies, \
+                    pool_json, outbound_proxy_json) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            )
+            .bind(&self.namespace)
+            .bind(row.timeout_ms)
+            .bind(row.forward_headers)
+            .bind(row.proxy_headers)
+            .bind(row.strip_hop_by_hop)
+            .bind(&row.forwarded_style)
+            .bind(&row.trusted_proxies)
+            .bind(&row.pool_json)
+            .bind(&row.outbound_proxy_json)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let mut ids = Vec::with_capacity(tables.routes.len());
+        for (position, row) in (0_i64..).zip(&tables.routes) {
+            let id: i64 = sqlx::query_scalar(
+                "INSERT INTO switchboard_routes (namespace, position, path, methods, \
+                    timeout_ms, forwarded_style, hedge_json, dedup_json) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id",
+            )
+            .bind(&self.namespace)
+            .bind(position)
+            .bind(&row.path)
+            .bind(&row.methods)
+            .bind(row.timeout_ms)
+            .bind(&row.forwarded_style)
+            .bind(&row.hedge_json)
+            .bind(&row.dedup_json)
+            .fetch_one(&mut *tx)
+            .await?;
+            ids.push(id);
+        }
+        let route_id = |index: i64| {
+            usize::try_from(index)
+                .ok()
+                .and_then(|i| ids.get(i).copied())
+        };
+
+        for (position, row) in (0_i64..).zip(&tables.targets) {
+            sqlx::query(
+                "INSERT INTO switchboard_targets (route_id, position, url, is_primary, \
+                    timeout_ms, pool_json, outbound_proxy_json, ordering_key_json, signing_json) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            )
+            .bind(route_id(row.route_id))
+            .bind(position)
+            .bind(&row.url)
+            .bind(row.primary)
+            .bind(row.timeout_ms)
+            .bind(&row.pool_json)
+            .bind(&row.outbound_proxy_json)
+            .bind(&row.ordering_key_json)
+            .bind(&row.signing_json)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for row in &tables.header_rules {
+            sqlx::query(
+                "INSERT INTO switchboard_header_rules \
+                    (namespace, route_id, direction, action, name, value) \
+                 VALUES ($1, $2, $3, $4, $5, $6)",
+            )
+            .bind(&self.namespace)
+            .bind(row.route_id.and_then(route_id))
+            .bind(&row.direction)
+            .bind(&row.action)
+            .bind(&row.name)
+            .bind(&row.value)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let version = current_version(&mut *tx, &self.namespace).await?;
+        tx.commit().await?;
+        Ok(version.map_or(0, |(version, _)| version))
+    }
+}
+
+/// The namespace's `version` and `epoch` columns.
+async fn current_version<'c>(
+    executor: impl PgExecutor<'c>,
+    namespace: &str,
+) -> Result<Option<(i64, i64)>, sqlx::Error> {
+    sqlx::query_as("SELECT version, epoch FROM switchboard_namespaces WHERE namespace = $1")
+        .bind(namespace)
+        .fetch_optional(executor)
+        .await
+}
+
+/// Create the normalized tables and the triggers that bump versions.
+async fn migrate(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    // Concurrent first connections would race on CREATE OR REPLACE FUNCTION
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('switchboard_normalized'))")
+        .execute(&mut *tx)
+        .await?;
+    sqlx::raw_sql(
+        "CREATE TABLE IF NOT EXISTS switchboard_namespaces (\
+            namespace TEXT PRIMARY KEY, \
+            version BIGINT NOT NULL DEFAULT 1, \
+            epoch BIGINT NOT NULL DEFAULT (floor(random() * 9007199254740991)::BIGINT), \
+            actuator_json TEXT, \
+            profiles_json TEXT\
+         ); \
+         CREATE TABLE IF NOT EXISTS switchboard_defaults (\
+            namespace TEXT PRIMARY KEY, \
+            timeout_ms BIGINT, \
+            forward_headers BOOLEAN, \
+            proxy_headers BOOLEAN, \
+            strip_hop_by_hop BOOLEAN, \
+            forwarded_style TEXT, \
+            trusted_proxies TEXT, \
+            pool_json TEXT, \
+            outbound_proxy_json TEXT\
+         ); \
+         CREATE TABLE IF NOT EXISTS switchboard_routes (\
+            id BIGSERIAL PRIMARY KEY, \
+            namespace TEXT NOT NULL, \
+            position BIGINT NOT NULL DEFAULT 0, \
+            path TEXT NOT NULL, \
+            methods TEXT, \
+            timeout_ms BIGINT, \
+            forwarded_style TEXT, \
+            hedge_json TEXT, \
+            dedup_json TEXT, \
+            UNIQUE (namespace, path)\
+         ); \
+         CREATE TABLE IF NOT EXISTS switchboard_targets (\
+            id BIGSERIAL PRIMARY KEY, \
+            route_id BIGINT NOT NULL REFERENCES switchboard_routes (id) ON DELETE CASCADE, \
+            position BIGINT NOT NULL DEFAULT 0, \
+            url TEXT NOT NULL, \
+            is_primary BOOLEAN NOT NULL DEFAULT FALSE, \
+            timeout_ms BIGINT, \
+            pool_json TEXT, \
+            outbound_proxy_json TEXT, \
+            ordering_key_json TEXT, \
+            signing_json TEXT\
+         ); \
+         CREATE INDEX IF NOT EXISTS switchboard_targets_route ON switchboard_targets (route_id); \
+         CREATE TABLE IF NOT EXISTS switchboard_header_rules (\
+            id BIGSERIAL PRIMARY KEY, \
+            namespace TEXT NOT NULL, \
+            route_id BIGINT REFERENCES switchboard_routes (id) ON DELETE CASCADE, \
+            direction TEXT NOT NULL CHECK (direction IN ('request', 'response')), \
+            action TEXT NOT NULL CHECK (action IN ('add', 'set_if_absent', 'strip')), \
+            name TEXT NOT NULL, \
+            value TEXT\
+         ); \
+         CREATE OR REPLACE FUNCTION switchboard_namespaces_bump() RETURNS trigger AS $$ \
+         BEGIN \
+            NEW.version := OLD.version + 1; \
+            PERFORM pg_notify('switchboard_config', NEW.namespace); \
+            RETURN NEW; \
+         END; \
+         $$ LANGUAGE plpgsql; \
+         CREATE OR REPLACE FUNCTION switchboard_bump_version() RETURNS trigger AS $$ \
+         DECLARE \
+            changed RECORD; \
+            ns TEXT; \
+         BEGIN \
+            IF TG_OP = 'DELETE' THEN changed := OLD; ELSE changed := NEW; END IF; \
+            IF TG_TABLE_NAME = 'switchboard_targets' THEN \
+                SELECT namespace INTO ns FROM switchboard_routes WHERE id = changed.route_id; \
+                IF ns IS NULL THEN RETURN NULL; END IF; \
+            ELSE \
+                ns := changed.namespace; \
+            END IF; \
+            INSERT INTO switchboard_namespaces (namespace) VALUES (ns) \
+            ON CONFLICT (namespace) DO UPDATE \
+                SET version = switchboard_namespaces.version + 1; \
+            PERFORM pg_notify('switchboard_config', ns); \
+            RETURN NULL; \
+         END; \
+         $$ LANGUAGE plpgsql; \
+         DO $$ BEGIN \
+            IF NOT EXISTS (SELECT 1 FROM pg_trigger WHERE tgname = 'switchboard_namespaces_bump') THEN \
+                CREATE TRIGGER switchboard_namespaces_bump \
+                BEFORE UPDATE OF actuator_json, profiles_json ON switchboard_namespaces \
+                FOR EACH ROW EXECUTE FUNCTION switchboard_namespaces_bump(); \
+            END IF; \
+            IF NOT EXISTS (SELECT 1 FROM pg_trigger WHERE tgname = 'switchboard_defaults_bump') THEN \
+                CREATE TRIGGER switchboard_defaults_bump \
+                AFTER INSERT OR UPDATE OR DELETE ON switchboard_defaults \
+                FOR EACH ROW EXECUTE FUNCTION switchboard_bump_version(); \
+            END IF; \
+            IF NOT EXISTS (SELECT 1 FROM pg_trigger WHERE tgname = 'switchboard_routes_bump') THEN \
+                CREATE TRIGGER switchboard_routes_bump \
+                AFTER INSERT OR UPDATE OR DELETE ON switchboard_routes \
+                FOR EACH ROW EXECUTE FUNCTION switchboard_bump_version(); \
+            END IF; \
+            IF NOT EXISTS (SELECT 1 FROM pg_trigger WHERE tgname = 'switchboard_targets_bump') THEN \
+                CREATE TRIGGER switchboard_targets_bump \
+                AFTER INSERT OR UPDATE OR DELETE ON switchboard_targets \
+                FOR EACH ROW EXECUTE FUNCTION switchboard_bump_version(); \
+            END IF; \
+            IF NOT EXISTS (SELECT 1 FROM pg_trigger WHERE tgname = 'switchboard_header_rules_bump') THEN \
+                CREATE TRIGGER switchboard_header_rules_bump \
+                AFTER INSERT OR UPDATE OR DELETE ON switchboard_header_rules \
+                FOR EACH ROW EXECUTE FUNCTION switchboard_bump_version(); \
+            END IF; \
+         END $$",
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await
+}
+
+#[async_trait]
+impl ConfigSource for NormalizedPostgresSource {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn load(&self) -> Result<(Config, ConfigVersion), SwitchboardError> {
+        let (config, version) = self.fetch_config().await?;
+        Ok((
+            resolve_and_validate(config, self.profile.as_deref())?,
+            version,
+        ))
+    }
+
+    async fn has_changed(&self, current: &ConfigVersion) -> Result<bool, SwitchboardError> {
+        let version = current_version(&self.pool, &self.namespace)
+            .await
+            .map_err(db_error)?
+            .map(|(version, epoch)| normalized::version(version, epoch));
+        Ok(version.as_ref() != Some(current))
+    }
+
+    async fn watch(&self) -> Result<Option<ChangeStream>, SwitchboardError> {
+        Ok(Some(watch_namespace(
+            self.pool.clone(),
+            self.namespace.clone(),
+        )))
+    }
+}
+
+#[async_trait]
+impl ConfigStore for NormalizedPostgresSource {
+    async fn read_json(&self) -> Result<Option<StoredJson>, SwitchboardError> {
+        match self.read_tables().await.map_err(db_error)? {
+            Some(tables) => {
+                let config = normalized::assemble(tables, &self.label())?;
+                serde_json::to_string_pretty(&config)
+                    .map(|json| {
+                        // No version history is kept
+                        Some(StoredJson {
+                            json,
+                            version: None,
+                        })
+                    })
+                    .map_err(|e| SwitchboardError::Io(std::io::Error::other(e.to_string())))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn push_json(&self, json: &str, _meta: &VersionMeta) -> Result<u64, SwitchboardError> {
+        let config: Config =
+            serde_json::from_str(json).map_err(|e| SwitchboardError::ConfigParse {
+                path: self.label(),
+                source: Box::new(e),
+            })?;
+        let version = self
+            .write_tables(normalized::decompose(&config)?)
+            .await
+            .map_err(db_error)?;
+        Ok(u64::try_from(version).unwrap_or_default())
+    }
+
+    fn keeps_history(&self) -> bool {
+        false
+    }
+
+    async fn history(&self) -> Result<Vec<StoredVersion>, SwitchboardError> {
+        Err(normalized::no_history("postgres"))
+    }
+
+    async fn activate(&self, _version: u64) -> Result<(), SwitchboardError> {
+        Err(normalized::no_history("postgres"))
+    }
+}
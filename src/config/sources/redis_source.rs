@@ -6,18 +6,81 @@
 //! asynchronously via a multiplexed Tokio connection, deserializes the
 //! JSON into a [`Config`](crate::config::model::Config), validates the result, and computes a SHA256
 //! hash for version tracking.
+//!
+//! Changes are pushed by subscribing both to a pub/sub channel named after
+//! the key (for writers that `PUBLISH` after `SET`) and to the key's
+//! keyspace notifications (when the server has `notify-keyspace-events`
+//! enabled). A dropped subscription is retried while polling carries on.
+//!
+//! `switchboard config push` writes through [`ConfigStore`]: every push is
+//! kept in the `{key}:versions` hash, numbered by the `{key}:latest`
+//! counter, and the key itself holds a copy of the active version (whose
+//! number is in `{key}:active`). Pushes and rollbacks publish to the
+//! channel, so running instances reload right away.
+
+use std::collections::HashMap;
+use std::time::Duration;
 
 use async_trait::async_trait;
+use futures_util::StreamExt;
 use redis::AsyncCommands;
-use tokio::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Mutex};
 
 use super::{parse_validate_hash, sha256_hex};
-use crate::config::{ConfigSource, ConfigVersion};
+use crate::config::payload::PayloadKeys;
+use crate::config::{
+    unix_now, ChangeStream, ConfigSource, ConfigStore, ConfigVersion, StoredJson, StoredVersion,
+    VersionMeta,
+};
 use crate::error::SwitchboardError;
 
+/// Delay before re-subscribing after the subscription drops.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// Stores a pushed version and makes it active, in one step so that
+/// concurrent pushes cannot interleave. A config stored before versioning
+/// becomes version 1 first. Returns 0 without writing anything if the new
+/// version would not be the one expected, which the config was sealed for.
+///
+/// Keys: the config key, `{key}:latest`, `{key}:versions`, `{key}:active`.
+/// Arguments: the config JSON, its serialized [`VersionEntry`], the time,
+/// the expected version.
+const PUSH_SCRIPT: &str = "\
+    local latest = tonumber(redis.call('GET', KEYS[2])) \
+    local existing = not latest and redis.call('GET', KEYS[1]) \
+    local version = (latest or 0) + 1 \
+    if existing then \
+        version = 2 \
+    end \
+    if version ~= tonumber(ARGV[4]) then \
+        return 0 \
+    end \
+    if existing then \
+        redis.call('HSET', KEYS[3], 1, cjson.encode({ \
+            config_json = existing, \
+            author = 'unknown', \
+            created_at = tonumber(ARGV[3]), \
+            comment = 'stored before versioning', \
+        })) \
+    end \
+    redis.call('SET', KEYS[2], version) \
+    redis.call('HSET', KEYS[3], version, ARGV[2]) \
+    redis.call('SET', KEYS[1], ARGV[1]) \
+    redis.call('SET', KEYS[4], version) \
+    redis.call('PUBLISH', KEYS[1], version) \
+    return version";
+
+/// How often a push is retried after losing a race with another push.
+const PUSH_ATTEMPTS: usize = 5;
+
 pub struct RedisSource {
+    client: redis::Client,
     connection: Mutex<redis::aio::MultiplexedConnection>,
     key: String,
+    namespace: String,
+    keys: PayloadKeys,
+    profile: Option<String>,
 }
 
 impl RedisSource {
@@ -36,30 +99,64 @@ impl RedisSource {
             })?;
 
         Ok(Self {
+            client,
             connection: Mutex::new(connection),
             key: format!("switchboard:{namespace}:config"),
+            namespace: namespace.to_string(),
+            keys: PayloadKeys::default(),
+            profile: None,
         })
     }
 
-    #[allow(clippy::significant_drop_tightening)]
-    async fn read_content(&self) -> Result<String, SwitchboardError> {
-        let mut conn = self.connection.lock().await;
+    /// Open stored configs with `keys`, to decrypt them and check their
+    /// signatures, and seal pushed ones with them.
+    #[must_use]
+    pub fn with_payload_keys(mut self, keys: PayloadKeys) -> Self {
+        self.keys = keys;
+        self
+    }
 
-        let value: Option<String> =
-            conn.get(&self.key)
-                .await
-                .map_err(|e| SwitchboardError::Database {
-                    backend: "redis",
-                    source: Box::new(e),
-                })?;
-
-        value.ok_or_else(|| SwitchboardError::ConfigParse {
-            path: self.key.clone(),
-            source: format!("key '{}' not found in Redis", self.key).into(),
-        })
+    /// Merge the named profile onto every loaded config.
+    #[must_use]
+    pub fn with_profile(mut self, profile: Option<String>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    async fn read_content(&self) -> Result<StoredJson, SwitchboardError> {
+        self.read_json()
+            .await?
+            .ok_or_else(|| SwitchboardError::ConfigParse {
+                path: self.key.clone(),
+                source: format!("key '{}' not found in Redis", self.key).into(),
+            })
     }
 }
 
+/// Forward messages on the key's channel and keyspace events to `changes`
+/// until the subscription drops.
+async fn subscribe(
+    client: &redis::Client,
+    key: &str,
+    changes: &mpsc::Sender<()>,
+) -> redis::RedisResult<()> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.subscribe(key).await?;
+    pubsub.psubscribe(format!("__keyspace@*__:{key}")).await?;
+    tracing::debug!(key = %key, "subscribed to redis config changes");
+
+    // A change may have been missed while (re)connecting
+    let _ = changes.try_send(());
+    let mut messages = pubsub.into_on_message();
+    while messages.next().await.is_some() {
+        if changes.is_closed() {
+            break;
+        }
+        let _ = changes.try_send(());
+    }
+    Ok(())
+}
+
 #[async_trait]
 impl ConfigSource for RedisSource {
     fn name(&self) -> &'static str {
@@ -70,11 +167,198 @@ impl ConfigSource for RedisSource {
         &self,
     ) -> Result<(crate::config::model::Config, ConfigVersion), SwitchboardError> {
         let content = self.read_content().await?;
-        parse_validate_hash(&content, &self.key)
+        parse_validate_hash(
+            &content,
+            &self.key,
+            &self.namespace,
+            &self.keys,
+            self.profile.as_deref(),
+        )
     }
 
     async fn has_changed(&self, current: &ConfigVersion) -> Result<bool, SwitchboardError> {
         let content = self.read_content().await?;
-        Ok(*current != ConfigVersion::Hash(sha256_hex(content.as_bytes())))
+        Ok(*current != ConfigVersion::Hash(sha256_hex(content.json.as_bytes())))
+    }
+
+    async fn watch(&self) -> Result<Option<ChangeStream>, SwitchboardError> {
+        let (tx, rx) = mpsc::channel(1);
+        let client = self.client.clone();
+        let key = self.key.clone();
+        tokio::spawn(async move {
+            loop {
+                let result = subscribe(&client, &key, &tx).await;
+                if tx.is_closed() {
+                    return;
+                }
+                match result {
+                    Ok(()) => tracing::warn!(
+                        "redis config subscription closed, polling until it reconnects"
+                    ),
+                    Err(e) => {
+                        tracing::warn!(error = %e, "redis config subscription failed, polling until it reconnects")
+                    }
+                }
+                tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+            }
+        });
+        Ok(Some(rx))
+    }
+}
+
+/// A pushed version, as stored in the `{key}:versions` hash.
+#[derive(Serialize, Deserialize)]
+struct VersionEntry {
+    config_json: String,
+    author: String,
+    created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+}
+
+fn db_error(e: impl std::error::Error + Send + Sync + 'static) -> SwitchboardError {
+    SwitchboardError::Database {
+        backend: "redis",
+        source: Box::new(e),
+    }
+}
+
+impl RedisSource {
+    fn versions_key(&self) -> String {
+        format!("{}:versions", self.key)
+    }
+
+    /// Make `json` (version `version`) the active config and notify
+    /// subscribers, atomically.
+    async fn set_active(
+        &self,
+        conn: &mut redis::aio::MultiplexedConnection,
+        version: u64,
+        json: &str,
+    ) -> Result<(), SwitchboardError> {
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .set(&self.key, json)
+            .ignore()
+            .set(format!("{}:active", self.key), version)
+            .ignore()
+            .publish(&self.key, version)
+            .ignore();
+        pipe.query_async::<()>(conn).await.map_err(db_error)
+    }
+}
+
+#[async_trait]
+impl ConfigStore for RedisSource {
+    #[allow(clippy::significant_drop_tightening)]
+    async fn read_json(&self) -> Result<Option<StoredJson>, SwitchboardError> {
+        let mut conn = self.connection.lock().await;
+        let (json, version): (Option<String>, Option<u64>) = conn
+            .mget(&[self.key.clone(), format!("{}:active", self.key)])
+            .await
+            .map_err(db_error)?;
+        Ok(json.map(|json| StoredJson { json, version }))
+    }
+
+    #[allow(clippy::significant_drop_tightening)]
+    async fn push_json(&self, json: &str, meta: &VersionMeta) -> Result<u64, SwitchboardError> {
+        let latest_key = format!("{}:latest", self.key);
+        let mut conn = self.connection.lock().await;
+        for _ in 0..PUSH_ATTEMPTS {
+            let (latest, existing): (Option<u64>, bool) = redis::pipe()
+                .get(&latest_key)
+                .exists(&self.key)
+                .query_async(&mut *conn)
+                .await
+                .map_err(db_error)?;
+            // A config stored before versioning becomes version 1
+            let version = match latest {
+                Some(latest) => latest + 1,
+                None if existing => 2,
+                None => 1,
+            };
+
+            let json = self.keys.seal(json, &self.namespace, version)?;
+            let entry = VersionEntry {
+                config_json: json.clone(),
+                author: meta.author.clone(),
+                created_at: unix_now(),
+                comment: meta.comment.clone(),
+            };
+            let entry = serde_json::to_string(&entry).map_err(db_error)?;
+
+            let pushed: u64 = redis::Script::new(PUSH_SCRIPT)
+                .key(&self.key)
+                .key(&latest_key)
+                .key(self.versions_key())
+                .key(format!("{}:active", self.key))
+                .arg(json)
+                .arg(entry)
+                .arg(unix_now())
+                .arg(version)
+                .invoke_async(&mut *conn)
+                .await
+                .map_err(db_error)?;
+            if pushed != 0 {
+                return Ok(pushed);
+            }
+        }
+        Err(SwitchboardError::Database {
+            backend: "redis",
+            source: format!(
+                "push to '{}' kept conflicting with concurrent pushes",
+                self.key
+            )
+            .into(),
+        })
+    }
+
+    #[allow(clippy::significant_drop_tightening)]
+    async fn history(&self) -> Result<Vec<StoredVersion>, SwitchboardError> {
+        let mut conn = self.connection.lock().await;
+        let entries: HashMap<u64, String> =
+            conn.hgetall(self.versions_key()).await.map_err(db_error)?;
+        let active: Option<u64> = conn
+            .get(format!("{}:active", self.key))
+            .await
+            .map_err(db_error)?;
+
+        let mut versions = entries
+            .into_iter()
+            .map(|(version, entry)| {
+                let entry: VersionEntry = serde_json::from_str(&entry).map_err(db_error)?;
+                Ok(StoredVersion {
+                    version,
+                    author: entry.author,
+                    created_at: entry.created_at,
+                    comment: entry.comment,
+                    active: active == Some(version),
+                })
+            })
+            .collect::<Result<Vec<_>, SwitchboardError>>()?;
+        versions.sort_by_key(|v| std::cmp::Reverse(v.version));
+        Ok(versions)
+    }
+
+    #[allow(clippy::significant_drop_tightening)]
+    async fn activate(&self, version: u64) -> Result<(), SwitchboardError> {
+        let mut conn = self.connection.lock().await;
+        let entry: Option<String> = conn
+            .hget(self.versions_key(), version)
+            .await
+            .map_err(db_error)?;
+        let Some(entry) = entry else {
+            return Err(SwitchboardError::Database {
+                backend: "redis",
+                source: format!(
+                    "version {version} not found under '{}'",
+                    self.versions_key()
+                )
+                .into(),
+            });
+        };
+        let entry: VersionEntry = serde_json::from_str(&entry).map_err(db_error)?;
+        self.set_active(&mut conn, version, &entry.config_json)
+            .await
     }
 }
@@ -4,6 +4,13 @@
 //! database, keyed by namespace. The table `switchboard_config` is
 //! auto-created on first connection. Change detection uses SHA-256
 //! hashing of the raw `config_json` column value.
+//!
+//! `switchboard config push` writes through [`ConfigStore`]: every push is
+//! kept in `switchboard_config_versions`, and the `switchboard_config` row
+//! holds a copy of the active version along with its number, so that
+//! `config rollback` only has to copy an older version back. Databases
+//! created before versioning are migrated on connection, with the stored
+//! config becoming version 1.
 
 use std::path::Path;
 
@@ -12,12 +19,20 @@ use sqlx::sqlite::SqliteConnectOptions;
 use sqlx::SqlitePool;
 
 use super::{parse_validate_hash, sha256_hex};
-use crate::config::{ConfigSource, ConfigVersion};
+use crate::config::payload::PayloadKeys;
+use crate::config::{
+    unix_now, ConfigSource, ConfigStore, ConfigVersion, StoredJson, StoredVersion, VersionMeta,
+};
 use crate::error::SwitchboardError;
 
+/// How often a push is retried after losing a race with another push.
+const PUSH_ATTEMPTS: usize = 5;
+
 pub struct SqliteSource {
     pool: SqlitePool,
     namespace: String,
+    keys: PayloadKeys,
+    profile: Option<String>,
 }
 
 impl SqliteSource {
@@ -26,50 +41,105 @@ impl SqliteSource {
             .filename(path)
             .create_if_missing(true);
 
-        let pool =
-            SqlitePool::connect_with(options)
-                .await
-                .map_err(|e| SwitchboardError::Database {
-                    backend: "sqlite",
-                    source: Box::new(e),
-                })?;
+        let pool = SqlitePool::connect_with(options).await.map_err(db_error)?;
 
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS switchboard_config (\
-                namespace TEXT PRIMARY KEY, \
-                config_json TEXT NOT NULL\
-            )",
-        )
-        .execute(&pool)
-        .await
-        .map_err(|e| SwitchboardError::Database {
-            backend: "sqlite",
-            source: Box::new(e),
-        })?;
+        migrate(&pool).await.map_err(db_error)?;
 
         Ok(Self {
             pool,
             namespace: namespace.to_string(),
+            keys: PayloadKeys::default(),
+            profile: None,
         })
     }
 
-    async fn fetch_config_json(&self) -> Result<String, SwitchboardError> {
-        let row: Option<(String,)> =
-            sqlx::query_as("SELECT config_json FROM switchboard_config WHERE namespace = ?")
-                .bind(&self.namespace)
-                .fetch_optional(&self.pool)
-                .await
-                .map_err(|e| SwitchboardError::Database {
-                    backend: "sqlite",
-                    source: Box::new(e),
-                })?;
-
-        row.map(|(json,)| json)
+    /// Open stored configs with `keys`, to decrypt them and check their
+    /// signatures, and seal pushed ones with them.
+    #[must_use]
+    pub fn with_payload_keys(mut self, keys: PayloadKeys) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// Merge the named profile onto every loaded config.
+    #[must_use]
+    pub fn with_profile(mut self, profile: Option<String>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    async fn fetch_config_json(&self) -> Result<StoredJson, SwitchboardError> {
+        self.read_json()
+            .await?
             .ok_or_else(|| SwitchboardError::Database {
                 backend: "sqlite",
                 source: format!("no config row found for namespace '{}'", self.namespace).into(),
             })
     }
+
+    /// One push attempt, `None` when a concurrent push took the version
+    /// number first.
+    async fn try_push(
+        &self,
+        json: &str,
+        meta: &VersionMeta,
+    ) -> Result<Option<u64>, SwitchboardError> {
+        // IMMEDIATE takes the write lock before reading the latest version,
+        // so concurrent pushes queue up instead of picking the same number
+        let mut tx = self
+            .pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(db_error)?;
+        let version: i64 = sqlx::query_scalar(
+            "SELECT COALESCE(MAX(version), 0) + 1 FROM switchboard_config_versions \
+             WHERE namespace = ?",
+        )
+        .bind(&self.namespace)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(db_error)?;
+        let json = self.keys.seal(
+            json,
+            &self.namespace,
+            u64::try_from(version).unwrap_or_default(),
+        )?;
+
+        let inserted = sqlx::query(
+            "INSERT INTO switchboard_config_versions \
+                (namespace, version, config_json, author, created_at, comment) \
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&self.namespace)
+        .bind(version)
+        .bind(&json)
+        .bind(&meta.author)
+        .bind(stamp(unix_now()))
+        .bind(&meta.comment)
+        .execute(&mut *tx)
+        .await;
+        match inserted {
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => return Ok(None),
+            inserted => inserted.map_err(db_error)?,
+        };
+
+        sqlx::query(
+            "INSERT INTO switchboard_config (namespace, config_json, active_version) \
+             VALUES (?, ?, ?) \
+             ON CONFLICT (namespace) DO UPDATE SET \
+                config_json = excluded.config_json, \
+                active_version = excluded.active_version",
+        )
+        .bind(&self.namespace)
+        .bind(&json)
+        .bind(version)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+        tx.commit().await.map_err(db_error)?;
+        Ok(Some(u64::try_from(version).unwrap_or_default()))
+    }
 }
 
 #[async_trait]
@@ -82,11 +152,175 @@ impl ConfigSource for SqliteSource {
         &self,
     ) -> Result<(crate::config::model::Config, ConfigVersion), SwitchboardError> {
         let json = self.fetch_config_json().await?;
-        parse_validate_hash(&json, &format!("sqlite::{}", self.namespace))
+        parse_validate_hash(
+            &json,
+            &format!("sqlite::{}", self.namespace),
+            &self.namespace,
+            &self.keys,
+            self.profile.as_deref(),
+        )
     }
 
     async fn has_changed(&self, current: &ConfigVersion) -> Result<bool, SwitchboardError> {
-        let json = self.fetch_config_json().await?;
-        Ok(*current != ConfigVersion::Hash(sha256_hex(json.as_bytes())))
+        let stored = self.fetch_config_json().await?;
+        Ok(*current != ConfigVersion::Hash(sha256_hex(stored.json.as_bytes())))
+    }
+}
+
+pub(super) fn db_error(e: sqlx::Error) -> SwitchboardError {
+    SwitchboardError::Database {
+        backend: "sqlite",
+        source: Box::new(e),
+    }
+}
+
+/// Create the config tables, adding version history to databases created
+/// before it existed.
+async fn migrate(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS switchboard_config (\
+            namespace TEXT PRIMARY KEY, \
+            config_json TEXT NOT NULL, \
+            active_version INTEGER\
+        )",
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let versioned: bool = sqlx::query_scalar(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('switchboard_config') \
+         WHERE name = 'active_version'",
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if !versioned {
+        sqlx::query("ALTER TABLE switchboard_config ADD COLUMN active_version INTEGER")
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS switchboard_config_versions (\
+            namespace TEXT NOT NULL, \
+            version INTEGER NOT NULL, \
+            config_json TEXT NOT NULL, \
+            author TEXT NOT NULL, \
+            created_at INTEGER NOT NULL, \
+            comment TEXT, \
+            PRIMARY KEY (namespace, version)\
+        )",
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // Configs stored before versioning become version 1
+    sqlx::query(
+        "INSERT OR IGNORE INTO switchboard_config_versions \
+            (namespace, version, config_json, author, created_at, comment) \
+         SELECT namespace, 1, config_json, 'unknown', ?, 'stored before versioning' \
+         FROM switchboard_config WHERE active_version IS NULL",
+    )
+    .bind(stamp(unix_now()))
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("UPDATE switchboard_config SET active_version = 1 WHERE active_version IS NULL")
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await
+}
+
+/// A version number or timestamp as stored in an INTEGER column.
+fn stamp(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+#[async_trait]
+impl ConfigStore for SqliteSource {
+    async fn read_json(&self) -> Result<Option<StoredJson>, SwitchboardError> {
+        let row: Option<(String, Option<i64>)> = sqlx::query_as(
+            "SELECT config_json, active_version FROM switchboard_config WHERE namespace = ?",
+        )
+        .bind(&self.namespace)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_error)?;
+        Ok(row.map(|(json, version)| StoredJson {
+            json,
+            version: version.and_then(|v| u64::try_from(v).ok()),
+        }))
+    }
+
+    async fn push_json(&self, json: &str, meta: &VersionMeta) -> Result<u64, SwitchboardError> {
+        for _ in 0..PUSH_ATTEMPTS {
+            if let Some(version) = self.try_push(json, meta).await? {
+                return Ok(version);
+            }
+        }
+        Err(SwitchboardError::Database {
+            backend: "sqlite",
+            source: format!(
+                "push to '{}' kept conflicting with concurrent pushes",
+                self.namespace
+            )
+            .into(),
+        })
+    }
+
+    async fn history(&self) -> Result<Vec<StoredVersion>, SwitchboardError> {
+        let rows: Vec<(i64, String, i64, Option<String>, bool)> = sqlx::query_as(
+            "SELECT v.version, v.author, v.created_at, v.comment, \
+                COALESCE(c.active_version = v.version, FALSE) \
+             FROM switchboard_config_versions v \
+             LEFT JOIN switchboard_config c ON c.namespace = v.namespace \
+             WHERE v.namespace = ? \
+             ORDER BY v.version DESC",
+        )
+        .bind(&self.namespace)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        Ok(rows
+            .into_iter()
+            .map(
+                |(version, author, created_at, comment, active)| StoredVersion {
+                    version: u64::try_from(version).unwrap_or_default(),
+                    author,
+                    created_at: u64::try_from(created_at).unwrap_or_default(),
+                    comment,
+                    active,
+                },
+            )
+            .collect())
+    }
+
+    async fn activate(&self, version: u64) -> Result<(), SwitchboardError> {
+        let result = sqlx::query(
+            "INSERT INTO switchboard_config (namespace, config_json, active_version) \
+             SELECT namespace, config_json, version FROM switchboard_config_versions \
+             WHERE namespace = ? AND version = ? \
+             ON CONFLICT (namespace) DO UPDATE SET \
+                config_json = excluded.config_json, \
+                active_version = excluded.active_version",
+        )
+        .bind(&self.namespace)
+        .bind(stamp(version))
+        .execute(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        if result.rows_affected() == 0 {
+            return Err(SwitchboardError::Database {
+                backend: "sqlite",
+                source: format!(
+                    "version {version} not found for namespace '{}'",
+                    self.namespace
+                )
+                .into(),
+            });
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,437 @@
+//! SQLite-backed [`ConfigSource`] over the normalized schema.
+//!
+//! Selected with `--sql-schema normalized`; see [`normalized`](super::normalized)
+//! for the tables. They are created on first connection, along with
+//! triggers that bump the namespace's `version` whenever one of its rows
+//! changes, so that edits made with plain SQL are picked up by polling.
+//! `switchboard config push` replaces a namespace's rows in one
+//! transaction. No version history is kept, so `config history` and
+//! `config rollback` need the document schema.
+
+use std::path::Path;
+
+use async_trait::async_trait;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{SqliteExecutor, SqlitePool};
+
+use super::normalized::{
+    self, DefaultsColumns, DefaultsRow, HeaderRuleColumns, HeaderRuleRow, NamespaceRow,
+    RouteColumns, RouteRow, Tables, TargetColumns, TargetRow,
+};
+use super::resolve_and_validate;
+use super::sqlite::db_error;
+use crate::config::model::Config;
+use crate::config::{
+    ConfigSource, ConfigStore, ConfigVersion, StoredJson, StoredVersion, VersionMeta,
+};
+use crate::error::SwitchboardError;
+
+pub struct NormalizedSqliteSource {
+    pool: SqlitePool,
+    namespace: String,
+    profile: Option<String>,
+}
+
+impl NormalizedSqliteSource {
+    pub async fn new(path: &Path, namespace: &str) -> Result<Self, SwitchboardError> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .foreign_keys(true);
+
+        let pool = SqlitePool::connect_with(options).await.map_err(db_error)?;
+
+        migrate(&pool).await.map_err(db_error)?;
+
+        Ok(Self {
+            pool,
+            namespace: namespace.to_string(),
+            profile: None,
+        })
+    }
+
+    /// Merge the named profile onto every loaded config.
+    #[must_use]
+    pub fn with_profile(mut self, profile: Option<String>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    fn label(&self) -> String {
+        format!("sqlite::{}", self.namespace)
+    }
+
+    /// Every row of the namespace, read in one transaction, or `None` if
+    /// the namespace has no rows.
+    async fn read_tables(&self) -> Result<Option<Tables>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let Some((version, epoch, actuator_json, profiles_json)) = sqlx::query_as(
+            "SELECT version, epoch, actuator_json, profiles_json FROM switchboard_namespaces \
+             WHERE namespace = ?",
+        )
+        .bind(&self.namespace)
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        let defaults = sqlx::query_as::<_, DefaultsColumns>(
+            "SELECT timeout_ms, forward_headers, proxy_headers, strip_hop_by_hop, \
+                forwarded_style, trusted_proxies, pool_json, outbound_proxy_json \
+             FROM switchboard_defaults WHERE namespace = ?",
+        )
+        .bind(&self.namespace)
+        .fetch_optional(&mut *tx)
+        .await?
+        .map(DefaultsRow::from);
+
+        let routes = sqlx::query_as::<_, RouteColumns>(
+            "SELECT id, path, methods, timeout_ms, forwarded_style, hedge_json, dedup_json \
+             FROM switchboard_routes WHERE namespace = ? ORDER BY position, id",
+        )
+        .bind(&self.namespace)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(RouteRow::from)
+        .collect();
+
+        let targets = sqlx::query_as::<_, TargetColumns>(
+            "SELECT t.route_id, t.url, t.is_primary, t.timeout_ms, t.pool_json, \
+                t.outbound_proxy_json, t.ordering_key_json, t.signing_json \
+             FROM switchboard_targets t JOIN switchboard_routes r ON r.id = t.route_id \
+             WHERE r.namespace = ? ORDER BY t.position, t.id",
+        )
+        .bind(&self.namespace)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(TargetRow::from)
+        .collect();
+
+        let header_rules = sqlx::query_as::<_, HeaderRuleColumns>(
+            "SELECT route_id, direction, action, name, value FROM switchboard_header_rules \
+             WHERE namespace = ? ORDER BY id",
+        )
+        .bind(&self.namespace)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(HeaderRuleRow::from)
+        .collect();
+
+        tx.commit().await?;
+        Ok(Some(Tables {
+            namespace: NamespaceRow {
+                version,
+                epoch,
+                actuator_json,
+                profiles_json,
+            },
+            defaults,
+            routes,
+            targets,
+            header_rules,
+        }))
+    }
+
+    async fn fetch_config(&self) -> Result<(Config, ConfigVersion), SwitchboardError> {
+        let tables = self.read_tables().await.map_err(db_error)?.ok_or_else(|| {
+            SwitchboardError::Database {
+                backend: "sqlite",
+                source: format!("no config rows found for namespace '{}'", self.namespace).into(),
+            }
+        })?;
+        let version = normalized::version(tables.namespace.version, tables.namespace.epoch);
+        Ok((normalized::assemble(tables, &self.label())?, version))
+    }
+
+    /// Replace the namespace's rows with `tables`, returning its new version.
+    async fn write_tables(&self, tables: Tables) -> Result<i64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for table in [
+            "switchboard_header_rules",
+            "switchboard_routes",
+            "switchboard_defaults",
+        ] {
+            sqlx::query(&format!("DELETE FROM {table} WHERE namespace = ?"))
+                .bind(&self.namespace)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        sqlx::query(
+            "INSERT INTO switchboard_namespaces (namespace, actuator_json, profiles_json) \
+             VALUES (?, ?, ?) \
+             ON CONFLICT (namespace) DO UPDATE SET \
+                actuator_json = excluded.actuator_json, \
+                profiles_json = excluded.profiles_json",
+        )
+        .bind(&self.namespace)
+        .bind(&tables.namespace.actuator_json)
+        .bind(&tables.namespace.profiles_json)
+        .execute(&mut *tx)
+        .await?;
+
+        if let Some(ref row) = tables.defaults {
+            sqlx::query(
+                "INSERT INTO switchboard_defaults (namespace, timeout_ms, forward_headers, \
+                    proxy_headers, strip_hop_by_hop, forwarded_style, trusted_proxies, \
+                    pool_json, outbound_proxy_json) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&self.namespace)
+            .bind(row.timeout_ms)
+            .bind(row.forward_headers)
+            .bind(row.proxy_headers)
+            .bind(row.strip_hop_by_hop)
+            .bind(&row.forwarded_style)
+            .bind(&row.trusted_proxies)
+            .bind(&row.pool_json)
+            .bind(&row.outbound_proxy_json)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let mut ids = Vec::with_capacity(tables.routes.len());
+        for (position, row) in (0_i64..).zip(&tables.routes) {
+            let id: i64 = sqlx::query_scalar(
+                "INSERT INTO switchboard_routes (namespace, position, path, methods, \
+                    timeout_ms, forwarded_style, hedge_json, dedup_json) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?) RETURNING id",
+            )
+            .bind(&self.namespace)
+            .bind(position)
+            .bind(&row.path)
+            .bind(&row.methods)
+            .bind(row.timeout_ms)
+            .bind(&row.forwarded_style)
+            .bind(&row.hedge_json)
+            .bind(&row.dedup_json)
+            .fetch_one(&mut *tx)
+            .await?;
+            ids.push(id);
+        }
+        let route_id = |index: i64| {
+            usize::try_from(index)
+                .ok()
+                .and_then(|i| ids.get(i).copied())
+        };
+
+        for (position, row) in (0_i64..).zip(&tables.targets) {
+            sqlx::query(
+                "INSERT INTO switchboard_targets (route_id, position, url, is_primary, \
+                    timeout_ms, pool_json, outbound_proxy_json, ordering_key_json, signing_json) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(route_id(row.route_id))
+            .bind(position)
+            .bind(&row.url)
+            .bind(row.primary)
+            .bind(row.timeout_ms)
+            .bind(&row.pool_json)
+            .bind(&row.outbound_proxy_json)
+            .bind(&row.ordering_key_json)
+            .bind(&row.signing_json)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for row in &tables.header_rules {
+            sqlx::query(
+                "INSERT INTO switchboard_header_rules \
+                    (namespace, route_id, direction, action, name, value) \
+                 VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&self.namespace)
+            .bind(row.route_id.and_then(route_id))
+            .bind(&row.direction)
+            .bind(&row.action)
+            .bind(&row.name)
+            .bind(&row.value)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let version = current_version(&mut *tx, &self.namespace).await?;
+        tx.commit().await?;
+        Ok(version.map_or(0, |(version, _)| version))
+    }
+}
+
+/// The namespace's `version` and `epoch` columns.
+async fn current_version<'c>(
+    executor: impl SqliteExecutor<'c>,
+    namespace: &str,
+) -> Result<Option<(i64, i64)>, sqlx::Error> {
+    sqlx::query_as("SELECT version, epoch FROM switchboard_namespaces WHERE namespace = ?")
+        .bind(namespace)
+        .fetch_optional(executor)
+        .await
+}
+
+/// Create the normalized tables and the triggers that bump versions.
+async fn migrate(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::raw_sql(
+        "CREATE TABLE IF NOT EXISTS switchboard_namespaces (\
+            namespace TEXT PRIMARY KEY, \
+            version INTEGER NOT NULL DEFAULT 1, \
+            epoch INTEGER NOT NULL DEFAULT (random()), \
+            actuator_json TEXT, \
+            profiles_json TEXT\
+         ); \
+         CREATE TABLE IF NOT EXISTS switchboard_defaults (\
+            namespace TEXT PRIMARY KEY, \
+            timeout_ms INTEGER, \
+            forward_headers BOOLEAN, \
+            proxy_headers BOOLEAN, \
+            strip_hop_by_hop BOOLEAN, \
+            forwarded_style TEXT, \
+            trusted_proxies TEXT, \
+            pool_json TEXT, \
+            outbound_proxy_json TEXT\
+         ); \
+         CREATE TABLE IF NOT EXISTS switchboard_routes (\
+            id INTEGER PRIMARY KEY, \
+            namespace TEXT NOT NULL, \
+            position INTEGER NOT NULL DEFAULT 0, \
+            path TEXT NOT NULL, \
+            methods TEXT, \
+            timeout_ms INTEGER, \
+            forwarded_style TEXT, \
+            hedge_json TEXT, \
+            dedup_json TEXT, \
+            UNIQUE (namespace, path)\
+         ); \
+         CREATE TABLE IF NOT EXISTS switchboard_targets (\
+            id INTEGER PRIMARY KEY, \
+            route_id INTEGER NOT NULL REFERENCES switchboard_routes (id) ON DELETE CASCADE, \
+            position INTEGER NOT NULL DEFAULT 0, \
+            url TEXT NOT NULL, \
+            is_primary BOOLEAN NOT NULL DEFAULT FALSE, \
+            timeout_ms INTEGER, \
+            pool_json TEXT, \
+            outbound_proxy_json TEXT, \
+            ordering_key_json TEXT, \
+            signing_json TEXT\
+         ); \
+         CREATE INDEX IF NOT EXISTS switchboard_targets_route ON switchboard_targets (route_id); \
+         CREATE TABLE IF NOT EXISTS switchboard_header_rules (\
+            id INTEGER PRIMARY KEY, \
+            namespace TEXT NOT NULL, \
+            route_id INTEGER REFERENCES switchboard_routes (id) ON DELETE CASCADE, \
+            direction TEXT NOT NULL CHECK (direction IN ('request', 'response')), \
+            action TEXT NOT NULL CHECK (action IN ('add', 'set_if_absent', 'strip')), \
+            name TEXT NOT NULL, \
+            value TEXT\
+         ); \
+         CREATE TRIGGER IF NOT EXISTS switchboard_namespaces_bump \
+         AFTER UPDATE OF actuator_json, profiles_json ON switchboard_namespaces BEGIN \
+            UPDATE switchboard_namespaces SET version = version + 1 \
+            WHERE namespace = NEW.namespace; \
+         END;",
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    for (event, row) in [("INSERT", "NEW"), ("UPDATE", "NEW"), ("DELETE", "OLD")] {
+        let lower = event.to_lowercase();
+        // Tables keyed by namespace create it when it has no row yet
+        for table in [
+            "switchboard_defaults",
+            "switchboard_routes",
+            "switchboard_header_rules",
+        ] {
+            sqlx::raw_sql(&format!(
+                "CREATE TRIGGER IF NOT EXISTS {table}_{lower} AFTER {event} ON {table} BEGIN \
+                    INSERT INTO switchboard_namespaces (namespace) VALUES ({row}.namespace) \
+                    ON CONFLICT (namespace) DO UPDATE SET version = version + 1; \
+                 END;"
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+        sqlx::raw_sql(&format!(
+            "CREATE TRIGGER IF NOT EXISTS switchboard_targets_{lower} \
+             AFTER {event} ON switchboard_targets BEGIN \
+                UPDATE switchboard_namespaces SET version = version + 1 WHERE namespace = \
+                    (SELECT namespace FROM switchboard_routes WHERE id = {row}.route_id); \
+             END;"
+        ))
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+}
+
+#[async_trait]
+impl ConfigSource for NormalizedSqliteSource {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn load(&self) -> Result<(Config, ConfigVersion), SwitchboardError> {
+        let (config, version) = self.fetch_config().await?;
+        Ok((
+            resolve_and_validate(config, self.profile.as_deref())?,
+            version,
+        ))
+    }
+
+    async fn has_changed(&self, current: &ConfigVersion) -> Result<bool, SwitchboardError> {
+        let version = current_version(&self.pool, &self.namespace)
+            .await
+            .map_err(db_error)?
+            .map(|(version, epoch)| normalized::version(version, epoch));
+        Ok(version.as_ref() != Some(current))
+    }
+}
+
+#[async_trait]
+impl ConfigStore for NormalizedSqliteSource {
+    async fn read_json(&self) -> Result<Option<StoredJson>, SwitchboardError> {
+        match self.read_tables().await.map_err(db_error)? {
+            Some(tables) => {
+                let config = normalized::assemble(tables, &self.label())?;
+                serde_json::to_string_pretty(&config)
+                    .map(|json| {
+                        // No version history is kept
+                        Some(StoredJson {
+                            json,
+                            version: None,
+                        })
+                    })
+                    .map_err(|e| SwitchboardError::Io(std::io::Error::other(e.to_string())))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn push_json(&self, json: &str, _meta: &VersionMeta) -> Result<u64, SwitchboardError> {
+        let config: Config =
+            serde_json::from_str(json).map_err(|e| SwitchboardError::ConfigParse {
+                path: self.label(),
+                source: Box::new(e),
+            })?;
+        let version = self
+            .write_tables(normalized::decompose(&config)?)
+            .await
+            .map_err(db_error)?;
+        Ok(u64::try_from(version).unwrap_or_default())
+    }
+
+    fn keeps_history(&self) -> bool {
+        false
+    }
+
+    async fn history(&self) -> Result<Vec<StoredVersion>, SwitchboardError> {
+        Err(normalized::no_history("sqlite"))
+    }
+
+    async fn activate(&self, _version: u64) -> Result<(), SwitchboardError> {
+        Err(normalized::no_history("sqlite"))
+    }
+}
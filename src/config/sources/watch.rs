@@ -0,0 +1,131 @@
+//! Filesystem watching for file-based config sources.
+//!
+//! [`watch_dirs`] watches directories (not files) so that the changes
+//! editors and Kubernetes make by swapping files are seen: an editor's
+//! atomic save renames a temporary file over the config, and a `ConfigMap`
+//! update re-points the `..data` symlink next to it. Bursts of events are
+//! debounced into a single notification; the receiver re-checks the
+//! config's hash, so unrelated events in a watched directory only cost a
+//! re-read.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use notify::event::{AccessKind, AccessMode};
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::config::ChangeStream;
+use crate::error::SwitchboardError;
+
+/// How long a burst of events must be quiet before notifying.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Watch `dirs` (non-recursively), sending a notification after each
+/// debounced burst of changes. Watching stops when the stream is dropped.
+pub fn watch_dirs(dirs: Vec<PathBuf>) -> Result<ChangeStream, SwitchboardError> {
+    let (event_tx, mut event_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if is_change(&event.kind) => {
+                let _ = event_tx.send(());
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!(error = %e, "config watch error"),
+        })
+        .map_err(|e| watch_error(&dirs, e))?;
+
+    for dir in &dirs {
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|e| watch_error(std::slice::from_ref(dir), e))?;
+    }
+    tracing::debug!(dirs = ?dirs, "watching config files");
+
+    let (change_tx, change_rx) = tokio::sync::mpsc::channel(1);
+    tokio::spawn(async move {
+        // The watcher lives as long as this task
+        let _watcher = watcher;
+        while event_rx.recv().await.is_some() {
+            // Wait for the burst to settle
+            while let Ok(Some(())) = tokio::time::timeout(DEBOUNCE, event_rx.recv()).await {}
+            if change_tx.is_closed() {
+                return;
+            }
+            // A notification already pending covers this burst too
+            let _ = change_tx.try_send(());
+        }
+    });
+    Ok(change_rx)
+}
+
+/// Whether an event may have changed file contents. Reads, including our
+/// own re-reads, are ignored.
+const fn is_change(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+        EventKind::Access(_) => false,
+        _ => true,
+    }
+}
+
+fn watch_error(dirs: &[PathBuf], source: notify::Error) -> SwitchboardError {
+    SwitchboardError::Watch {
+        path: dirs.first().cloned().unwrap_or_default(),
+        source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn next_change(stream: &mut ChangeStream) -> bool {
+        tokio::time::timeout(Duration::from_secs(2), stream.recv())
+            .await
+            .is_ok()
+    }
+
+    #[tokio::test]
+    async fn atomic_rename_is_reported_once() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let config = dir.join("switchboard.yaml");
+        std::fs::write(&config, "routes: []\n").unwrap();
+
+        let mut stream = watch_dirs(vec![dir.to_path_buf()]).unwrap();
+        let swap = dir.join(".switchboard.yaml.swp");
+        std::fs::write(&swap, "routes: [] # edited\n").unwrap();
+        std::fs::rename(&swap, &config).unwrap();
+
+        assert!(next_change(&mut stream).await);
+        // The burst was coalesced
+        assert!(tokio::time::timeout(DEBOUNCE * 3, stream.recv())
+            .await
+            .is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlink_swap_is_reported() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        for data in ["v1", "v2"] {
+            std::fs::create_dir(dir.join(data)).unwrap();
+            std::fs::write(dir.join(data).join("switchboard.yaml"), data).unwrap();
+        }
+        std::os::unix::fs::symlink("v1", dir.join("..data")).unwrap();
+        std::os::unix::fs::symlink("..data/switchboard.yaml", dir.join("switchboard.yaml"))
+            .unwrap();
+
+        let mut stream = watch_dirs(vec![dir.to_path_buf()]).unwrap();
+        // How the kubelet updates a ConfigMap volume
+        std::os::unix::fs::symlink("v2", dir.join("..data_tmp")).unwrap();
+        std::fs::rename(dir.join("..data_tmp"), dir.join("..data")).unwrap();
+
+        assert!(next_change(&mut stream).await);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("switchboard.yaml")).unwrap(),
+            "v2"
+        );
+    }
+}
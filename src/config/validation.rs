@@ -2,14 +2,17 @@
 //!
 //! The [`validate`] function checks a parsed [`Config`]
 //! for structural errors such as empty routes, invalid paths, duplicate
-//! entries, bad HTTP methods, multiple primaries, and malformed target URLs.
+//! entries, bad HTTP methods, multiple primaries, malformed target URLs,
+//! invalid hedge or dedup settings, misplaced ordering keys, incomplete
+//! signing settings, and invalid connection pool or outbound proxy settings.
 //! Returns a list of [`ValidationError`]
 //! values with per-field suggestions.
 
 use url::Url;
 
-use super::model::Config;
+use super::model::{Config, Dedup, DedupKey, OrderingKey, OutboundProxy, PoolSettings, Signing};
 use crate::error::ValidationError;
+use crate::proxy::client_ip::Cidr;
 
 pub const VALID_METHODS: &[&str] = &[
     "GET", "POST", "PUT", "DELETE", "PATCH", "HEAD", "OPTIONS", "*",
@@ -56,6 +59,101 @@ pub fn validate_method(method: &str) -> Result<(), String> {
     }
 }
 
+/// Validate connection pool settings. Returns `Ok(())` or a human-readable error.
+pub fn validate_pool(pool: &PoolSettings) -> Result<(), String> {
+    if pool.max_connections == Some(0) {
+        return Err("max_connections must be at least 1".into());
+    }
+    if pool.tcp_keepalive == Some(0) {
+        return Err("tcp_keepalive must be greater than 0 (omit it to disable)".into());
+    }
+    Ok(())
+}
+
+/// Validate an outbound proxy setting. Returns `Ok(())` or a human-readable error.
+pub fn validate_outbound_proxy(proxy: &OutboundProxy) -> Result<(), String> {
+    let Some(url) = &proxy.url else {
+        return Ok(());
+    };
+    match Url::parse(url) {
+        Ok(parsed) if parsed.scheme() == "http" && parsed.host_str().is_some() => Ok(()),
+        Ok(parsed) if parsed.scheme() != "http" => Err(format!(
+            "unsupported proxy scheme '{}' (expected http)",
+            parsed.scheme()
+        )),
+        _ => Err(format!("'{url}' is not a valid proxy URL")),
+    }
+}
+
+/// Validate a route's dedup settings. Returns `Ok(())` or a human-readable error.
+pub fn validate_dedup(dedup: &Dedup) -> Result<(), String> {
+    if dedup.ttl == 0 {
+        return Err("ttl must be greater than 0".into());
+    }
+    if dedup.key == DedupKey::Header && dedup.header.parse::<axum::http::HeaderName>().is_err() {
+        return Err(format!("'{}' is not a valid header name", dedup.header));
+    }
+    Ok(())
+}
+
+/// Validate a target's signing settings. Returns `Ok(())` or a human-readable error.
+pub fn validate_signing(signing: &Signing) -> Result<(), String> {
+    if signing.secret.is_empty() {
+        return Err("secret cannot be empty".into());
+    }
+    if signing.secret.starts_with("env:") || signing.secret.starts_with("file:") {
+        return Err(format!(
+            "secret '{}' looks like a reference; use ${{{}}} instead",
+            signing.secret, signing.secret
+        ));
+    }
+    for name in [&signing.header, &signing.timestamp_header] {
+        if name.parse::<axum::http::HeaderName>().is_err() {
+            return Err(format!("'{name}' is not a valid header name"));
+        }
+    }
+    if signing.template.is_empty() {
+        return Err("template cannot be empty".into());
+    }
+    Ok(())
+}
+
+/// Validate a target's ordering key. Returns `Ok(())` or a human-readable error.
+pub fn validate_ordering_key(
+    key: &OrderingKey,
+    route_path: &str,
+    is_primary: bool,
+) -> Result<(), String> {
+    if is_primary {
+        return Err("ordering_key only applies to secondary targets".into());
+    }
+    match key {
+        OrderingKey::Param(name) => {
+            let declared = route_path
+                .split('/')
+                .any(|segment| segment.strip_prefix(':') == Some(name.as_str()));
+            if declared {
+                Ok(())
+            } else {
+                Err(format!("route path has no ':{name}' parameter"))
+            }
+        }
+        OrderingKey::Header(name) => name
+            .parse::<axum::http::HeaderName>()
+            .map(|_| ())
+            .map_err(|_| format!("'{name}' is not a valid header name")),
+        OrderingKey::Json(path) => {
+            if path.is_empty() || path.split('.').any(str::is_empty) {
+                Err(format!(
+                    "'{path}' is not a valid JSON path (e.g. 'order.id')"
+                ))
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
 pub fn validate(config: &Config) -> Result<(), Vec<ValidationError>> {
     let mut errors = Vec::new();
 
@@ -99,6 +197,40 @@ pub fn validate(config: &Config) -> Result<(), Vec<ValidationError>> {
         (None, None) => {}
     }
 
+    for cidr in &config.defaults.trusted_proxies {
+        if let Err(msg) = cidr.parse::<Cidr>() {
+            errors.push(ValidationError {
+                route: "(root)".into(),
+                field: "defaults.trusted_proxies".into(),
+                message: msg,
+                suggestion: Some("use CIDR notation, e.g. '10.0.0.0/8'".into()),
+            });
+        }
+    }
+
+    if let Err(msg) = validate_pool(&config.defaults.pool) {
+        errors.push(ValidationError {
+            route: "(root)".into(),
+            field: "defaults.pool".into(),
+            message: msg,
+            suggestion: None,
+        });
+    }
+
+    if let Some(Err(msg)) = config
+        .defaults
+        .outbound_proxy
+        .as_ref()
+        .map(validate_outbound_proxy)
+    {
+        errors.push(ValidationError {
+            route: "(root)".into(),
+            field: "defaults.outbound_proxy".into(),
+            message: msg,
+            suggestion: Some("use http://[user:pass@]host:port".into()),
+        });
+    }
+
     if config.routes.is_empty() {
         errors.push(ValidationError {
             route: "(root)".into(),
@@ -170,6 +302,62 @@ pub fn validate(config: &Config) -> Result<(), Vec<ValidationError>> {
             });
         }
 
+        if let Some(hedge) = &route.hedge {
+            if let Err(msg) = validate_target_url(&hedge.url) {
+                errors.push(ValidationError {
+                    route: route_id.clone(),
+                    field: "hedge.url".into(),
+                    message: msg,
+                    suggestion: None,
+                });
+            }
+            for method in &hedge.methods {
+                if let Err(msg) = validate_method(method) {
+                    errors.push(ValidationError {
+                        route: route_id.clone(),
+                        field: "hedge.methods".into(),
+                        message: msg,
+                        suggestion: None,
+                    });
+                }
+            }
+        }
+
+        if let Some(dedup) = &route.dedup {
+            if let Err(msg) = validate_dedup(dedup) {
+                errors.push(ValidationError {
+                    route: route_id.clone(),
+                    field: "dedup".into(),
+                    message: msg,
+                    suggestion: None,
+                });
+            }
+        }
+
+        let primary_idx = route.targets.iter().position(|t| t.primary).unwrap_or(0);
+        for (idx, target) in route.targets.iter().enumerate() {
+            if let Some(key) = &target.ordering_key {
+                if let Err(msg) = validate_ordering_key(key, &route.path, idx == primary_idx) {
+                    errors.push(ValidationError {
+                        route: route_id.clone(),
+                        field: "targets.ordering_key".into(),
+                        message: msg,
+                        suggestion: None,
+                    });
+                }
+            }
+            if let Some(signing) = &target.signing {
+                if let Err(msg) = validate_signing(signing) {
+                    errors.push(ValidationError {
+                        route: route_id.clone(),
+                        field: "targets.signing".into(),
+                        message: msg,
+                        suggestion: None,
+                    });
+                }
+            }
+        }
+
         for target in &route.targets {
             if let Err(msg) = validate_target_url(&target.url) {
                 errors.push(ValidationError {
@@ -179,6 +367,22 @@ pub fn validate(config: &Config) -> Result<(), Vec<ValidationError>> {
                     suggestion: None,
                 });
             }
+            if let Some(Err(msg)) = target.pool.as_ref().map(validate_pool) {
+                errors.push(ValidationError {
+                    route: route_id.clone(),
+                    field: "targets.pool".into(),
+                    message: msg,
+                    suggestion: None,
+                });
+            }
+            if let Some(Err(msg)) = target.outbound_proxy.as_ref().map(validate_outbound_proxy) {
+                errors.push(ValidationError {
+                    route: route_id.clone(),
+                    field: "targets.outbound_proxy".into(),
+                    message: msg,
+                    suggestion: Some("use http://[user:pass@]host:port".into()),
+                });
+            }
         }
     }
 
@@ -248,7 +452,10 @@ pub fn format_validation_report(path: &str, config: &Config) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::model::{Config, Defaults, Route, Target};
+    use crate::config::model::{
+        Config, DedupMode, Defaults, Hedge, OrderingKey, Route, SignatureEncoding,
+        SigningAlgorithm, Target,
+    };
 
     fn minimal_config() -> Config {
         Config {
@@ -258,13 +465,25 @@ mod tests {
                 path: "/test".into(),
                 methods: vec!["*".into()],
                 timeout: None,
+                forwarded_style: None,
                 headers: Default::default(),
+                response_headers: Default::default(),
+                hedge: None,
+                dedup: None,
                 targets: vec![Target {
                     url: "http://localhost:8080/test".into(),
                     primary: false,
                     timeout: None,
+                    pool: None,
+                    outbound_proxy: None,
+                    ordering_key: None,
+                    signing: None,
                 }],
             }],
+            include: Vec::new(),
+            profiles: Default::default(),
+            profile: None,
+            secrets: Default::default(),
         }
     }
 
@@ -279,6 +498,10 @@ mod tests {
             actuator: Default::default(),
             defaults: Defaults::default(),
             routes: vec![],
+            include: Vec::new(),
+            profiles: Default::default(),
+            profile: None,
+            secrets: Default::default(),
         };
         let errors = validate(&config).unwrap_err();
         assert_eq!(errors.len(), 1);
@@ -294,9 +517,17 @@ mod tests {
                 path: "/test".into(),
                 methods: vec!["*".into()],
                 timeout: None,
+                forwarded_style: None,
                 headers: Default::default(),
+                response_headers: Default::default(),
+                hedge: None,
+                dedup: None,
                 targets: vec![],
             }],
+            include: Vec::new(),
+            profiles: Default::default(),
+            profile: None,
+            secrets: Default::default(),
         };
         let errors = validate(&config).unwrap_err();
         assert!(errors
@@ -313,20 +544,36 @@ mod tests {
                 path: "/test".into(),
                 methods: vec!["*".into()],
                 timeout: None,
+                forwarded_style: None,
                 headers: Default::default(),
+                response_headers: Default::default(),
+                hedge: None,
+                dedup: None,
                 targets: vec![
                     Target {
                         url: "http://a:80".into(),
                         primary: true,
                         timeout: None,
+                        pool: None,
+                        outbound_proxy: None,
+                        ordering_key: None,
+                        signing: None,
                     },
                     Target {
                         url: "http://b:80".into(),
                         primary: true,
                         timeout: None,
+                        pool: None,
+                        outbound_proxy: None,
+                        ordering_key: None,
+                        signing: None,
                     },
                 ],
             }],
+            include: Vec::new(),
+            profiles: Default::default(),
+            profile: None,
+            secrets: Default::default(),
         };
         let errors = validate(&config).unwrap_err();
         assert!(errors.iter().any(|e| e.message.contains("primary")));
@@ -341,13 +588,25 @@ mod tests {
                 path: "/test".into(),
                 methods: vec!["*".into()],
                 timeout: None,
+                forwarded_style: None,
                 headers: Default::default(),
+                response_headers: Default::default(),
+                hedge: None,
+                dedup: None,
                 targets: vec![Target {
                     url: "not a url".into(),
                     primary: false,
                     timeout: None,
+                    pool: None,
+                    outbound_proxy: None,
+                    ordering_key: None,
+                    signing: None,
                 }],
             }],
+            include: Vec::new(),
+            profiles: Default::default(),
+            profile: None,
+            secrets: Default::default(),
         };
         let errors = validate(&config).unwrap_err();
         assert!(errors.iter().any(|e| e.message.contains("not a valid URL")));
@@ -362,13 +621,25 @@ mod tests {
                 path: "test".into(),
                 methods: vec!["*".into()],
                 timeout: None,
+                forwarded_style: None,
                 headers: Default::default(),
+                response_headers: Default::default(),
+                hedge: None,
+                dedup: None,
                 targets: vec![Target {
                     url: "http://localhost:8080".into(),
                     primary: false,
                     timeout: None,
+                    pool: None,
+                    outbound_proxy: None,
+                    ordering_key: None,
+                    signing: None,
                 }],
             }],
+            include: Vec::new(),
+            profiles: Default::default(),
+            profile: None,
+            secrets: Default::default(),
         };
         let errors = validate(&config).unwrap_err();
         assert!(errors
@@ -385,17 +656,143 @@ mod tests {
                 path: "/test".into(),
                 methods: vec!["INVALID".into()],
                 timeout: None,
+                forwarded_style: None,
                 headers: Default::default(),
+                response_headers: Default::default(),
+                hedge: None,
+                dedup: None,
                 targets: vec![Target {
                     url: "http://localhost:8080".into(),
                     primary: false,
                     timeout: None,
+                    pool: None,
+                    outbound_proxy: None,
+                    ordering_key: None,
+                    signing: None,
                 }],
             }],
+            include: Vec::new(),
+            profiles: Default::default(),
+            profile: None,
+            secrets: Default::default(),
         };
         let errors = validate(&config).unwrap_err();
         assert!(errors
             .iter()
             .any(|e| e.message.contains("not a valid HTTP method")));
     }
+
+    #[test]
+    fn invalid_trusted_proxy_fails() {
+        let mut config = minimal_config();
+        config.defaults.trusted_proxies = vec!["10.0.0.0/8".into(), "10.0.0.0/40".into()];
+        let errors = validate(&config).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, "defaults.trusted_proxies");
+    }
+
+    #[test]
+    fn zero_max_connections_fails() {
+        let mut config = minimal_config();
+        config.routes[0].targets[0].pool = Some(PoolSettings {
+            max_connections: Some(0),
+            ..PoolSettings::default()
+        });
+        let errors = validate(&config).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, "targets.pool");
+    }
+
+    #[test]
+    fn non_http_outbound_proxy_fails() {
+        let mut config = minimal_config();
+        config.defaults.outbound_proxy = Some(OutboundProxy {
+            url: Some("socks5://proxy:1080".into()),
+            no_proxy: Vec::new(),
+            enabled: true,
+        });
+        let errors = validate(&config).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, "defaults.outbound_proxy");
+    }
+
+    #[test]
+    fn invalid_hedge_url_fails() {
+        let mut config = minimal_config();
+        config.routes[0].hedge = Some(Hedge {
+            url: "ftp://replica/test".into(),
+            delay: 50,
+            methods: vec!["GET".into()],
+            timeout: None,
+        });
+        let errors = validate(&config).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, "hedge.url");
+    }
+
+    #[test]
+    fn invalid_dedup_settings_fail() {
+        let mut config = minimal_config();
+        config.routes[0].dedup = Some(Dedup {
+            key: DedupKey::Header,
+            header: "Idempotency Key".into(),
+            ttl: 0,
+            mode: DedupMode::Replay,
+        });
+        let errors = validate(&config).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, "dedup");
+        assert!(errors[0].message.contains("ttl"));
+
+        config.routes[0].dedup.as_mut().unwrap().ttl = 1000;
+        let errors = validate(&config).unwrap_err();
+        assert!(errors[0].message.contains("header name"));
+    }
+
+    #[test]
+    fn ordering_key_rules() {
+        let mut config = minimal_config();
+        config.routes[0].path = "/orders/:id".into();
+        config.routes[0].targets.push(Target {
+            url: "http://localhost:9090/audit".into(),
+            primary: false,
+            timeout: None,
+            pool: None,
+            outbound_proxy: None,
+            ordering_key: Some(OrderingKey::Param("id".into())),
+            signing: None,
+        });
+        assert!(validate(&config).is_ok());
+
+        config.routes[0].targets[1].ordering_key = Some(OrderingKey::Param("order".into()));
+        config.routes[0].targets[0].ordering_key = Some(OrderingKey::Header("X-Id".into()));
+        let errors = validate(&config).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].message.contains("secondary"));
+        assert!(errors[1].message.contains(":order"));
+    }
+
+    #[test]
+    fn incomplete_signing_fails() {
+        let mut config = minimal_config();
+        config.routes[0].targets[0].signing = Some(Signing {
+            algorithm: SigningAlgorithm::HmacSha256,
+            secret: String::new(),
+            header: "X-Signature".into(),
+            timestamp_header: "X-Signature-Timestamp".into(),
+            template: "{timestamp}.{body}".into(),
+            encoding: SignatureEncoding::Hex,
+            prefix: String::new(),
+        });
+        let errors = validate(&config).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, "targets.signing");
+
+        config.routes[0].targets[0].signing.as_mut().unwrap().secret = "env:HOOK_SECRET".into();
+        let errors = validate(&config).unwrap_err();
+        assert!(errors[0].message.contains("${env:HOOK_SECRET}"));
+
+        config.routes[0].targets[0].signing.as_mut().unwrap().secret = "whsec".into();
+        assert!(validate(&config).is_ok());
+    }
 }
@@ -63,6 +63,12 @@ pub enum SwitchboardError {
     #[error("Config validation failed:\n{}", format_errors(.errors))]
     ConfigValidation { errors: Vec<ValidationError> },
 
+    #[error("Config from {path} rejected: {reason}")]
+    ConfigPayload { path: String, reason: String },
+
+    #[error("Cannot resolve secret reference '{reference}': {reason}")]
+    SecretReference { reference: String, reason: String },
+
     #[error("Unsupported config format: '{0}'")]
     UnsupportedFormat(String),
 
@@ -87,6 +93,18 @@ pub enum SwitchboardError {
     #[error("{0}")]
     Io(#[from] std::io::Error),
 
+    #[cfg(feature = "watch")]
+    #[error("Cannot watch {} for changes: {source}", path.display())]
+    Watch {
+        path: PathBuf,
+        #[source]
+        source: notify::Error,
+    },
+
+    #[cfg(feature = "git")]
+    #[error("Git error: {0}")]
+    Git(#[from] git2::Error),
+
     #[error("Health check failed with status {0}")]
     HealthCheckFailed(hyper::StatusCode),
 
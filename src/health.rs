@@ -2,7 +2,8 @@
 //!
 //! Returns a [`HealthResponse`] JSON payload containing the server
 //! version, uptime, config source metadata, loaded route/target counts,
-//! and cumulative request statistics.
+//! and cumulative request statistics. The status is `degraded` while the
+//! last-known-good config snapshot is served.
 
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -30,6 +31,16 @@ pub struct ConfigHealth {
     pub namespace: String,
     pub routes: usize,
     pub targets: usize,
+    /// Commit SHA, for configs read from git.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// Commit author, for configs read from git.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// Set when the last-known-good snapshot is served because no config
+    /// source could be loaded.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -40,33 +51,34 @@ pub struct StatsResponse {
 
 pub async fn health_handler(State(state): State<Arc<AppState>>) -> Json<HealthResponse> {
     // Clone Arc<Config> (cheap refcount bump) to release the lock quickly
-    let (config, source_name, version_str, loaded_ago) = {
+    let (config, source_name, version, loaded_ago, stale) = {
         let loaded = state.config.read().await;
-        let config = Arc::clone(&loaded.config);
-        let version_str = match &loaded.version {
-            crate::config::ConfigVersion::Hash(h) => h.get(..8).unwrap_or(h).to_string(),
-        };
         (
-            config,
+            Arc::clone(&loaded.config),
             loaded.source_name.clone(),
-            version_str,
+            loaded.version.clone(),
             loaded.loaded_at.elapsed().as_secs(),
+            loaded.stale,
         )
     };
+    let commit = version.commit();
 
     let total_targets = config.total_targets();
 
     Json(HealthResponse {
-        status: "healthy".to_string(),
+        status: if stale { "degraded" } else { "healthy" }.to_string(),
         version: env!("CARGO_PKG_VERSION").to_string(),
         uptime_seconds: state.start_time.elapsed().as_secs(),
         config: ConfigHealth {
             source: source_name,
-            version: version_str,
+            version: version.short().to_string(),
             loaded_ago_seconds: loaded_ago,
             namespace: state.namespace.clone(),
             routes: config.routes.len(),
             targets: total_targets,
+            commit: commit.map(|(sha, _)| sha.to_string()),
+            author: commit.map(|(_, author)| author.to_string()),
+            stale,
         },
         stats: StatsResponse {
             requests_forwarded: state.stats.forwarded.load(Ordering::Relaxed),
@@ -17,6 +17,8 @@
 //! - [`middleware`] -- Placeholder for Tower middleware layers.
 //! - [`proxy`] -- Core HTTP forwarding: route matching, header construction, and
 //!   concurrent fan-out to multiple targets.
+//! - [`rollback`] -- Automatic rollback of config reloads that raise the
+//!   failure rate.
 //! - [`server`] -- Axum server setup, shared application state, HTTP client, and
 //!   graceful shutdown.
 //!
@@ -32,8 +34,10 @@
 //! | `postgres` | PostgreSQL config backend |
 //! | `mongodb` | MongoDB config backend |
 //! | `sqlite` | SQLite config backend |
+//! | `git` | Git repository config source |
 //! | `actuator` | Spring Boot-style actuator endpoints |
 //! | `sentry-integration` | Sentry error tracking |
+//! | `schema` | `switchboard schema` JSON Schema export |
 //! | `file-backends` | All file format backends |
 //! | `db-backends` | All database backends |
 //! | `full` | All features |
@@ -51,6 +55,7 @@ pub mod health;
 pub mod logging;
 pub mod middleware;
 pub mod proxy;
+pub mod rollback;
 pub mod server;
 
 #[cfg(feature = "sentry-integration")]
@@ -0,0 +1,327 @@
+//! Real client IP resolution behind trusted proxies.
+//!
+//! The TCP peer is only the client when nothing sits in front of
+//! Switchboard. When the peer matches a `defaults.trusted_proxies` CIDR,
+//! [`resolve`] walks the incoming `X-Forwarded-For` (or, when absent,
+//! `Forwarded`) chain from right to left and picks the right-most address
+//! that is not itself a trusted proxy. When the peer is untrusted, the
+//! incoming chain is client-controlled and is ignored entirely.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use axum::http::HeaderMap;
+
+/// An IPv4 or IPv6 network in CIDR notation. A bare address is treated
+/// as a single-host network (`/32` or `/128`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    #[must_use]
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = addr
+            .trim()
+            .parse()
+            .map_err(|_| format!("'{s}' is not a valid IP address or CIDR range"))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format!("'{s}' has an invalid prefix length (0-{max})"))?,
+            None => max,
+        };
+        Ok(Self {
+            network: network.to_canonical(),
+            prefix,
+        })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// The resolved identity of the caller for one request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientAddr {
+    /// The TCP peer address of the connection.
+    pub peer: IpAddr,
+    /// The real client address (the peer itself unless it is a trusted proxy).
+    pub ip: IpAddr,
+    /// Whether the peer is a trusted proxy, i.e. whether the incoming
+    /// forwarding chain may be kept and extended.
+    pub trusted_peer: bool,
+}
+
+impl ClientAddr {
+    /// A client connected directly, with no trusted proxy in between.
+    #[must_use]
+    pub fn direct(peer: IpAddr) -> Self {
+        let peer = peer.to_canonical();
+        Self {
+            peer,
+            ip: peer,
+            trusted_peer: false,
+        }
+    }
+}
+
+/// Resolve the real client address for a request received from `peer`.
+#[must_use]
+pub fn resolve(peer: IpAddr, headers: &HeaderMap, trusted: &[Cidr]) -> ClientAddr {
+    let peer = peer.to_canonical();
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|cidr| cidr.contains(ip));
+
+    if !is_trusted(peer) {
+        return ClientAddr::direct(peer);
+    }
+
+    let chain = forwarded_for_chain(headers);
+    let mut client = peer;
+    for entry in chain.iter().rev() {
+        let Some(ip) = entry.as_deref().and_then(parse_node) else {
+            // Garbage or obfuscated entry: nothing to its left can be trusted
+            break;
+        };
+        client = ip;
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+
+    ClientAddr {
+        peer,
+        ip: client,
+        trusted_peer: true,
+    }
+}
+
+/// Collect the client chain from `X-Forwarded-For`, falling back to the
+/// `for` parameters of `Forwarded`. Entries are left-most first.
+fn forwarded_for_chain(headers: &HeaderMap) -> Vec<Option<String>> {
+    let xff: Vec<Option<String>> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|entry| Some(entry.trim().to_string()))
+        .collect();
+    if !xff.is_empty() {
+        return xff;
+    }
+
+    headers
+        .get_all("forwarded")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| split_unquoted(v, ','))
+        .map(|element| {
+            split_unquoted(element, ';').into_iter().find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| unquote(value.trim()))
+            })
+        })
+        .collect()
+}
+
+/// Split a `Forwarded` header on `sep`, ignoring separators inside quoted
+/// strings (RFC 7230 §3.2.6).
+fn split_unquoted(value: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == sep && !quoted => {
+                parts.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+/// The value of a token or quoted string, with quoted pairs unescaped.
+fn unquote(value: &str) -> String {
+    let Some(inner) = value
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    else {
+        return value.to_string();
+    };
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        out.push(if c == '\\' {
+            chars.next().unwrap_or(c)
+        } else {
+            c
+        });
+    }
+    out
+}
+
+/// Parse a chain entry: a bare IP, `ip:port`, `[v6]`, or `[v6]:port`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip.to_canonical());
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip().to_canonical());
+    }
+    node.strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .and_then(|v6| v6.parse::<IpAddr>().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidrs(list: &[&str]) -> Vec<Cidr> {
+        list.iter().map(|c| c.parse().unwrap()).collect()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn cidr_contains() {
+        let net: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(net.contains(ip("10.1.2.3")));
+        assert!(!net.contains(ip("11.0.0.1")));
+        assert!(net.contains(ip("::ffff:10.0.0.1")));
+
+        let v6: Cidr = "fd00::/8".parse().unwrap();
+        assert!(v6.contains(ip("fd12::1")));
+        assert!(!v6.contains(ip("10.0.0.1")));
+
+        let host: Cidr = "192.168.1.10".parse().unwrap();
+        assert!(host.contains(ip("192.168.1.10")));
+        assert!(!host.contains(ip("192.168.1.11")));
+    }
+
+    #[test]
+    fn cidr_rejects_invalid() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("not-an-ip".parse::<Cidr>().is_err());
+        assert!("::/129".parse::<Cidr>().is_err());
+        assert!("0.0.0.0/0".parse::<Cidr>().is_ok());
+    }
+
+    #[test]
+    fn untrusted_peer_ignores_chain() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "6.6.6.6".parse().unwrap());
+
+        let client = resolve(ip("203.0.113.7"), &headers, &cidrs(&["10.0.0.0/8"]));
+        assert_eq!(client.ip, ip("203.0.113.7"));
+        assert!(!client.trusted_peer);
+    }
+
+    #[test]
+    fn trusted_peer_uses_rightmost_untrusted_entry() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            "6.6.6.6, 198.51.100.4, 10.0.0.2".parse().unwrap(),
+        );
+
+        let client = resolve(ip("10.0.0.1"), &headers, &cidrs(&["10.0.0.0/8"]));
+        assert_eq!(client.ip, ip("198.51.100.4"));
+        assert_eq!(client.peer, ip("10.0.0.1"));
+        assert!(client.trusted_peer);
+    }
+
+    #[test]
+    fn trusted_peer_falls_back_to_forwarded() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "forwarded",
+            "for=\"[2001:db8::7]:4711\";proto=https, for=10.0.0.9"
+                .parse()
+                .unwrap(),
+        );
+
+        let client = resolve(ip("10.0.0.1"), &headers, &cidrs(&["10.0.0.0/8"]));
+        assert_eq!(client.ip, ip("2001:db8::7"));
+    }
+
+    #[test]
+    fn forwarded_separators_inside_quotes_are_kept() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "forwarded",
+            "for=198.51.100.4;by=\"a,b;c\", for=\"10.0.0.9\";proto=https"
+                .parse()
+                .unwrap(),
+        );
+
+        let client = resolve(ip("10.0.0.1"), &headers, &cidrs(&["10.0.0.0/8"]));
+        assert_eq!(client.ip, ip("198.51.100.4"));
+    }
+
+    #[test]
+    fn garbage_entry_stops_the_walk() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            "1.2.3.4, junk, 10.0.0.2".parse().unwrap(),
+        );
+
+        let client = resolve(ip("10.0.0.1"), &headers, &cidrs(&["10.0.0.0/8"]));
+        assert_eq!(client.ip, ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn trusted_peer_without_chain_is_the_client() {
+        let client = resolve(ip("10.0.0.1"), &HeaderMap::new(), &cidrs(&["10.0.0.0/8"]));
+        assert_eq!(client.ip, ip("10.0.0.1"));
+        assert!(client.trusted_peer);
+    }
+}
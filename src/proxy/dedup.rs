@@ -0,0 +1,565 @@
+//! Deduplication of retried requests.
+//!
+//! A route with `dedup` enabled derives a key for each request, either from
+//! its `Idempotency-Key` header or from a hash of method, path, query, and
+//! body. The first request with a key claims it with an in-flight marker,
+//! which its primary's response then replaces for `ttl` ms. A duplicate
+//! inside the window replays the stored response (or gets `409 Conflict`
+//! while the first request is still in flight) or, in `skip-secondaries`
+//! mode, is forwarded to the primary only. Only the claiming request
+//! writes to the store; if it fails or is abandoned, its [`Reservation`]
+//! releases the key so that a retry is forwarded again.
+//!
+//! Stores implement [`DedupStore`]. [`MemoryDedupStore`] is a bounded
+//! in-process map; with the `redis` feature, [`RedisDedupStore`] shares the
+//! window across instances.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri};
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+
+use crate::config::model::{Dedup, DedupKey};
+use crate::error::SwitchboardError;
+
+/// A primary response kept for replay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// What a store holds for a key when a request claims it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Claim {
+    /// The key was free and is now reserved for the caller.
+    Acquired,
+    /// Another request with the key has not completed yet.
+    InFlight,
+    /// A request with the key completed with this primary response.
+    Done(StoredResponse),
+}
+
+#[async_trait]
+pub trait DedupStore: Send + Sync {
+    /// Look up `key`, reserving it with an in-flight marker owned by
+    /// `token` for `ttl` when nothing is stored under it.
+    async fn claim(&self, key: &str, token: &str, ttl: Duration)
+        -> Result<Claim, SwitchboardError>;
+
+    /// Store the primary's response under `key` for `ttl`, replacing the
+    /// in-flight marker.
+    async fn complete(
+        &self,
+        key: &str,
+        response: &StoredResponse,
+        ttl: Duration,
+    ) -> Result<(), SwitchboardError>;
+
+    /// Drop the in-flight marker owned by `token`, if it is still there.
+    async fn release(&self, key: &str, token: &str) -> Result<(), SwitchboardError>;
+}
+
+/// A key claimed by the current request. Dropping it without
+/// [`Reservation::complete`] releases the key.
+pub struct Reservation {
+    store: Arc<dyn DedupStore>,
+    key: String,
+    token: String,
+    completed: bool,
+}
+
+impl Reservation {
+    /// Claim `key` for `ttl`, returning the reservation when the key was
+    /// free or what the store holds otherwise.
+    pub async fn claim(
+        store: &Arc<dyn DedupStore>,
+        key: &str,
+        ttl: Duration,
+    ) -> Result<Result<Self, Claim>, SwitchboardError> {
+        let token = uuid::Uuid::new_v4().to_string();
+        Ok(match store.claim(key, &token, ttl).await? {
+            Claim::Acquired => Ok(Self {
+                store: Arc::clone(store),
+                key: key.to_string(),
+                token,
+                completed: false,
+            }),
+            other => Err(other),
+        })
+    }
+
+    /// Store the primary's response for duplicates to replay.
+    pub async fn complete(
+        mut self,
+        response: &StoredResponse,
+        ttl: Duration,
+    ) -> Result<(), SwitchboardError> {
+        self.completed = true;
+        self.store.complete(&self.key, response, ttl).await
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if self.completed {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let store = Arc::clone(&self.store);
+        let key = std::mem::take(&mut self.key);
+        let token = std::mem::take(&mut self.token);
+        runtime.spawn(async move {
+            if let Err(e) = store.release(&key, &token).await {
+                tracing::warn!(error = %e, "dedup release failed, key stays reserved until it expires");
+            }
+        });
+    }
+}
+
+/// Derive the dedup key for a request, or `None` when the request is not
+/// eligible (e.g. no `Idempotency-Key` header in `header` mode).
+///
+/// Keys are scoped to the matched route and hashed, so they are safe to
+/// use as store keys regardless of what clients send.
+#[must_use]
+pub fn request_key(
+    dedup: &Dedup,
+    route_path: &str,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+    body: &Bytes,
+) -> Option<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(route_path.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(method.as_str().as_bytes());
+    hasher.update(b"\n");
+
+    match dedup.key {
+        DedupKey::Header => {
+            let value = headers.get(dedup.header.as_str())?;
+            hasher.update(value.as_bytes());
+        }
+        DedupKey::Hash => {
+            let path_and_query = uri.path_and_query().map_or("/", |pq| pq.as_str());
+            hasher.update(path_and_query.as_bytes());
+            hasher.update(b"\n");
+            hasher.update(body);
+        }
+    }
+
+    let digest = hasher.finalize();
+    Some(digest.iter().fold(String::with_capacity(64), |mut hex, b| {
+        use std::fmt::Write;
+        let _ = write!(hex, "{b:02x}");
+        hex
+    }))
+}
+
+/// Bounded in-memory store. Expired entries are dropped lazily; when full,
+/// the oldest entry is evicted.
+pub struct MemoryDedupStore {
+    max_entries: usize,
+    inner: Mutex<MemoryInner>,
+}
+
+enum MemoryEntry {
+    InFlight(String),
+    Done(StoredResponse),
+}
+
+#[derive(Default)]
+struct MemoryInner {
+    entries: HashMap<String, (Instant, MemoryEntry)>,
+    order: VecDeque<String>,
+}
+
+impl MemoryInner {
+    fn insert(&mut self, key: &str, entry: (Instant, MemoryEntry), max_entries: usize) {
+        let Self { entries, order } = self;
+        if entries.insert(key.to_string(), entry).is_none() {
+            order.push_back(key.to_string());
+        }
+
+        // `order` may hold keys already purged; skip those
+        while entries.len() > max_entries {
+            let Some(oldest) = order.pop_front() else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        if order.len() > max_entries.saturating_mul(2) {
+            order.retain(|k| entries.contains_key(k));
+        }
+    }
+}
+
+impl MemoryDedupStore {
+    #[must_use]
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries: max_entries.max(1),
+            inner: Mutex::new(MemoryInner::default()),
+        }
+    }
+
+    /// Number of live (possibly expired, not yet purged) entries.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MemoryInner> {
+        self.inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl Default for MemoryDedupStore {
+    fn default() -> Self {
+        Self::new(10_000)
+    }
+}
+
+#[async_trait]
+impl DedupStore for MemoryDedupStore {
+    async fn claim(
+        &self,
+        key: &str,
+        token: &str,
+        ttl: Duration,
+    ) -> Result<Claim, SwitchboardError> {
+        let now = Instant::now();
+        let mut inner = self.lock();
+        match inner.entries.get(key) {
+            Some((expires, MemoryEntry::InFlight(_))) if *expires > now => Ok(Claim::InFlight),
+            Some((expires, MemoryEntry::Done(response))) if *expires > now => {
+                Ok(Claim::Done(response.clone()))
+            }
+            _ => {
+                let entry = (now + ttl, MemoryEntry::InFlight(token.to_string()));
+                inner.insert(key, entry, self.max_entries);
+                Ok(Claim::Acquired)
+            }
+        }
+    }
+
+    async fn complete(
+        &self,
+        key: &str,
+        response: &StoredResponse,
+        ttl: Duration,
+    ) -> Result<(), SwitchboardError> {
+        let entry = (Instant::now() + ttl, MemoryEntry::Done(response.clone()));
+        self.lock().insert(key, entry, self.max_entries);
+        Ok(())
+    }
+
+    async fn release(&self, key: &str, token: &str) -> Result<(), SwitchboardError> {
+        let mut inner = self.lock();
+        if matches!(inner.entries.get(key), Some((_, MemoryEntry::InFlight(owner))) if owner == token)
+        {
+            inner.entries.remove(key);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use redis_store::RedisDedupStore;
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use bytes::Bytes;
+    use redis::AsyncCommands;
+    use serde::{Deserialize, Serialize};
+
+    use super::{Claim, DedupStore, StoredResponse};
+    use crate::error::SwitchboardError;
+
+    /// Prefix of the in-flight marker, followed by the owner's token.
+    /// Stored responses are JSON objects, so they never start with it.
+    const IN_FLIGHT: &str = "in-flight:";
+
+    fn db_err(e: impl std::error::Error + Send + Sync + 'static) -> SwitchboardError {
+        SwitchboardError::Database {
+            backend: "redis",
+            source: Box::new(e),
+        }
+    }
+
+    /// Wire format: headers as (name, value) pairs, body base64-encoded.
+    #[derive(Serialize, Deserialize)]
+    struct Entry {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: String,
+    }
+
+    /// Redis-backed store, sharing the dedup window across instances.
+    /// Entries live under `switchboard:{namespace}:dedup:{key}`; a key is
+    /// claimed with `SET NX` and released with a compare-and-delete script.
+    pub struct RedisDedupStore {
+        connection: redis::aio::MultiplexedConnection,
+        prefix: String,
+    }
+
+    impl RedisDedupStore {
+        pub async fn new(url: &str, namespace: &str) -> Result<Self, SwitchboardError> {
+            let client = redis::Client::open(url).map_err(db_err)?;
+            let connection = client
+                .get_multiplexed_async_connection()
+                .await
+                .map_err(db_err)?;
+            Ok(Self {
+                connection,
+                prefix: format!("switchboard:{namespace}:dedup:"),
+            })
+        }
+    }
+
+    impl RedisDedupStore {
+        fn decode(value: &str) -> Result<StoredResponse, SwitchboardError> {
+            let entry: Entry = serde_json::from_str(value).map_err(db_err)?;
+            let mut headers = HeaderMap::new();
+            for (name, value) in entry.headers {
+                if let (Ok(name), Ok(value)) =
+                    (name.parse::<HeaderName>(), HeaderValue::from_str(&value))
+                {
+                    headers.append(name, value);
+                }
+            }
+            Ok(StoredResponse {
+                status: StatusCode::from_u16(entry.status).unwrap_or(StatusCode::OK),
+                headers,
+                body: Bytes::from(STANDARD.decode(entry.body).map_err(db_err)?),
+            })
+        }
+    }
+
+    fn millis(ttl: Duration) -> u64 {
+        u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX).max(1)
+    }
+
+    #[async_trait]
+    impl DedupStore for RedisDedupStore {
+        async fn claim(
+            &self,
+            key: &str,
+            token: &str,
+            ttl: Duration,
+        ) -> Result<Claim, SwitchboardError> {
+            let key = format!("{}{key}", self.prefix);
+            let marker = format!("{IN_FLIGHT}{token}");
+            let mut conn = self.connection.clone();
+            // The key may expire between SET NX and GET: try again once
+            for _ in 0..2 {
+                let acquired: bool = redis::cmd("SET")
+                    .arg(&key)
+                    .arg(&marker)
+                    .arg("NX")
+                    .arg("PX")
+                    .arg(millis(ttl))
+                    .query_async::<Option<String>>(&mut conn)
+                    .await
+                    .map_err(db_err)?
+                    .is_some();
+                if acquired {
+                    return Ok(Claim::Acquired);
+                }
+                let value: Option<String> = conn.get(&key).await.map_err(db_err)?;
+                match value {
+                    Some(value) if value.starts_with(IN_FLIGHT) => return Ok(Claim::InFlight),
+                    Some(value) => return Ok(Claim::Done(Self::decode(&value)?)),
+                    None => {}
+                }
+            }
+            Ok(Claim::InFlight)
+        }
+
+        async fn complete(
+            &self,
+            key: &str,
+            response: &StoredResponse,
+            ttl: Duration,
+        ) -> Result<(), SwitchboardError> {
+            let entry = Entry {
+                status: response.status.as_u16(),
+                headers: response
+                    .headers
+                    .iter()
+                    .filter_map(|(name, value)| {
+                        Some((name.to_string(), value.to_str().ok()?.to_string()))
+                    })
+                    .collect(),
+                body: STANDARD.encode(&response.body),
+            };
+            let value = serde_json::to_string(&entry).map_err(db_err)?;
+
+            let mut conn = self.connection.clone();
+            conn.pset_ex::<_, _, ()>(format!("{}{key}", self.prefix), value, millis(ttl))
+                .await
+                .map_err(db_err)
+        }
+
+        async fn release(&self, key: &str, token: &str) -> Result<(), SwitchboardError> {
+            let mut conn = self.connection.clone();
+            redis::Script::new(
+                "if redis.call('GET', KEYS[1]) == ARGV[1] then \
+                    return redis.call('DEL', KEYS[1]) \
+                 end \
+                 return 0",
+            )
+            .key(format!("{}{key}", self.prefix))
+            .arg(format!("{IN_FLIGHT}{token}"))
+            .invoke_async::<()>(&mut conn)
+            .await
+            .map_err(db_err)
+        }
+    }
+}
+
+/// Mark a replayed response, following the common `Idempotent-Replayed`
+/// convention.
+pub fn mark_replayed(headers: &mut HeaderMap) {
+    headers.insert(
+        HeaderName::from_static("idempotent-replayed"),
+        HeaderValue::from_static("true"),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::model::DedupMode;
+
+    fn dedup(key: DedupKey) -> Dedup {
+        Dedup {
+            key,
+            header: "Idempotency-Key".into(),
+            ttl: 60_000,
+            mode: DedupMode::Replay,
+        }
+    }
+
+    fn response(body: &'static str) -> StoredResponse {
+        StoredResponse {
+            status: StatusCode::CREATED,
+            headers: HeaderMap::new(),
+            body: Bytes::from_static(body.as_bytes()),
+        }
+    }
+
+    #[test]
+    fn header_key_requires_header_and_is_route_scoped() {
+        let uri: Uri = "/orders".parse().unwrap();
+        let body = Bytes::new();
+        let mut headers = HeaderMap::new();
+        let d = dedup(DedupKey::Header);
+
+        assert!(request_key(&d, "/orders", &Method::POST, &uri, &headers, &body).is_none());
+
+        headers.insert("idempotency-key", "abc".parse().unwrap());
+        let a = request_key(&d, "/orders", &Method::POST, &uri, &headers, &body).unwrap();
+        let b = request_key(&d, "/payments", &Method::POST, &uri, &headers, &body).unwrap();
+        assert_eq!(a.len(), 64);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn hash_key_covers_query_and_body() {
+        let d = dedup(DedupKey::Hash);
+        let headers = HeaderMap::new();
+        let key = |uri: &str, body: &'static str| {
+            request_key(
+                &d,
+                "/events",
+                &Method::POST,
+                &uri.parse().unwrap(),
+                &headers,
+                &Bytes::from_static(body.as_bytes()),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(key("/events?a=1", "x"), key("/events?a=1", "x"));
+        assert_ne!(key("/events?a=1", "x"), key("/events?a=2", "x"));
+        assert_ne!(key("/events?a=1", "x"), key("/events?a=1", "y"));
+    }
+
+    #[tokio::test]
+    async fn memory_store_expires_entries() {
+        let store = MemoryDedupStore::new(10);
+        let ttl = Duration::from_millis(20);
+        assert_eq!(store.claim("k", "t1", ttl).await.unwrap(), Claim::Acquired);
+        store.complete("k", &response("one"), ttl).await.unwrap();
+        assert_eq!(
+            store.claim("k", "t2", ttl).await.unwrap(),
+            Claim::Done(response("one"))
+        );
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(store.claim("k", "t3", ttl).await.unwrap(), Claim::Acquired);
+    }
+
+    #[tokio::test]
+    async fn memory_store_evicts_oldest_when_full() {
+        let store = MemoryDedupStore::new(2);
+        let ttl = Duration::from_secs(60);
+        store.complete("a", &response("a"), ttl).await.unwrap();
+        store.complete("b", &response("b"), ttl).await.unwrap();
+        store.complete("c", &response("c"), ttl).await.unwrap();
+
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.claim("a", "t", ttl).await.unwrap(), Claim::Acquired);
+        assert!(matches!(
+            store.claim("c", "t", ttl).await.unwrap(),
+            Claim::Done(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn claimed_key_is_in_flight_until_completed_or_released() {
+        let store: Arc<dyn DedupStore> = Arc::new(MemoryDedupStore::new(10));
+        let ttl = Duration::from_secs(60);
+
+        let first = Reservation::claim(&store, "k", ttl).await.unwrap().unwrap();
+        let duplicate = Reservation::claim(&store, "k", ttl).await.unwrap();
+        assert_eq!(duplicate.err(), Some(Claim::InFlight));
+
+        // Another owner's token cannot release the key
+        store.release("k", "someone-else").await.unwrap();
+        assert_eq!(store.claim("k", "t", ttl).await.unwrap(), Claim::InFlight);
+
+        // Dropping the reservation releases the key for a retry
+        drop(first);
+        tokio::task::yield_now().await;
+        let retry = Reservation::claim(&store, "k", ttl).await.unwrap().unwrap();
+        retry.complete(&response("done"), ttl).await.unwrap();
+        assert_eq!(
+            store.claim("k", "t", ttl).await.unwrap(),
+            Claim::Done(response("done"))
+        );
+    }
+}
@@ -3,7 +3,12 @@
 //! Spawns requests to all targets in parallel. The primary target's
 //! response is returned as soon as it arrives. Secondary targets run
 //! as detached tasks — their results are logged but never block the
-//! caller.
+//! caller, except that secondaries with an `ordering_key` are queued per
+//! key (see [`super::ordering`]). When the route has a [`Hedge`], a slow
+//! primary is raced against a hedge request and the first successful
+//! answer wins. Targets with a [`Signing`] config get an HMAC signature
+//! header over the final request, computed when it is sent (see
+//! [`super::signing`]).
 //!
 //! **Shutdown behavior:** Secondary tasks are fire-and-forget. During
 //! graceful shutdown they may be cancelled by the Tokio runtime before
@@ -11,6 +16,7 @@
 //! and are not required for correctness.
 
 use std::collections::HashMap;
+use std::future::Future;
 use std::time::{Duration, Instant};
 
 use axum::http::{HeaderMap, Method};
@@ -18,16 +24,23 @@ use bytes::Bytes;
 use http_body_util::BodyExt;
 use http_body_util::Full;
 use hyper::StatusCode;
+use tokio::task::{JoinError, JoinHandle};
 
-use crate::config::model::{Defaults, Route, Target};
+use crate::config::model::{Defaults, Hedge, Route, Signing, Target};
 use crate::error::SwitchboardError;
-use crate::server::HttpClient;
+use crate::server::{HttpClients, PooledClient};
 
+use super::client_ip::ClientAddr;
 use super::headers::build_forwarded_headers;
+use super::ordering::{self, OrderedQueues};
+use super::routing::method_matches;
+use super::signing;
 
 #[derive(Debug)]
 pub struct FanOutResult {
     pub primary_response: Option<(StatusCode, HeaderMap, Bytes)>,
+    /// Whether a hedge request was sent for the primary.
+    pub hedged: bool,
 }
 
 #[derive(Debug)]
@@ -38,8 +51,11 @@ pub struct TargetResult {
     pub error: Option<String>,
 }
 
+type Outcome = (TargetResult, Option<(StatusCode, HeaderMap, Bytes)>);
+
 pub struct FanOutRequest<'a> {
-    pub client: &'a HttpClient,
+    pub clients: &'a HttpClients,
+    pub queues: &'a OrderedQueues,
     pub targets: &'a [Target],
     pub method: &'a Method,
     pub original_headers: &'a HeaderMap,
@@ -47,133 +63,91 @@ pub struct FanOutRequest<'a> {
     pub params: &'a HashMap<String, String>,
     pub defaults: &'a Defaults,
     pub route: &'a Route,
-    pub client_ip: &'a str,
+    pub client_addr: &'a ClientAddr,
     pub correlation_id: &'a str,
 }
 
-#[allow(clippy::too_many_lines, clippy::cast_possible_truncation)]
-pub async fn fan_out(req: FanOutRequest<'_>) -> Result<FanOutResult, SwitchboardError> {
-    let primary_idx = req.targets.iter().position(|t| t.primary).unwrap_or(0);
+/// A fully-built outbound request to one target.
+struct TargetRequest {
+    pool: PooledClient,
+    method: Method,
+    url: String,
+    headers: HeaderMap,
+    body: Bytes,
+    timeout: Duration,
+    /// Signed in [`send`], so a queued request gets a fresh timestamp.
+    signing: Option<(Signing, url::Url)>,
+}
 
-    let mut primary_handle = None;
-
-    for (idx, target) in req.targets.iter().enumerate() {
-        let resolved_url = substitute_params(&target.url, req.params);
-        let timeout_ms = target
-            .timeout
-            .or(req.route.timeout)
-            .unwrap_or(req.defaults.timeout);
+impl FanOutRequest<'_> {
+    /// Build the outbound request for a target URL template. Returns `None`
+    /// (after logging) when the resolved URL is invalid.
+    fn prepare(
+        &self,
+        url_template: &str,
+        timeout: Option<u64>,
+        pool: PooledClient,
+        signing: Option<&Signing>,
+    ) -> Option<TargetRequest> {
+        let resolved_url = substitute_params(url_template, self.params);
+        let timeout_ms = timeout
+            .or(self.route.timeout)
+            .unwrap_or(self.defaults.timeout);
 
         let parsed_url = match url::Url::parse(&resolved_url) {
             Ok(u) => u,
             Err(e) => {
                 tracing::error!(target = %resolved_url, error = %e, "invalid target URL");
-                continue;
+                return None;
             }
         };
 
-        let forwarded_headers = build_forwarded_headers(
-            req.original_headers,
-            req.client_ip,
+        let headers = build_forwarded_headers(
+            self.original_headers,
+            self.client_addr,
             &parsed_url,
-            req.route,
-            req.defaults,
-            req.correlation_id,
+            self.route,
+            self.defaults,
+            self.correlation_id,
         );
 
-        let method = req.method.clone();
-        let body = req.body.clone();
-        let client = req.client.clone();
-        let timeout = Duration::from_millis(timeout_ms);
-        let correlation_id = req.correlation_id.to_string();
-
-        let task = async move {
-            let start = Instant::now();
-
-            let mut req_builder = hyper::Request::builder()
-                .method(method)
-                .uri(resolved_url.clone());
+        Some(TargetRequest {
+            pool,
+            method: self.method.clone(),
+            url: resolved_url,
+            headers,
+            body: self.body.clone(),
+            timeout: Duration::from_millis(timeout_ms),
+            signing: signing.map(|signing| (signing.clone(), parsed_url)),
+        })
+    }
+}
 
-            for (key, value) in &forwarded_headers {
-                req_builder = req_builder.header(key, value);
-            }
+pub async fn fan_out(req: FanOutRequest<'_>) -> Result<FanOutResult, SwitchboardError> {
+    let primary_idx = req.targets.iter().position(|t| t.primary).unwrap_or(0);
 
-            let req = match req_builder.body(Full::new(body)) {
-                Ok(r) => r,
-                Err(e) => {
-                    return (
-                        TargetResult {
-                            url: resolved_url,
-                            status: None,
-                            latency_ms: start.elapsed().as_millis() as u64,
-                            error: Some(e.to_string()),
-                        },
-                        None,
-                    );
-                }
-            };
+    let mut primary_handle = None;
+    let json_body = std::cell::OnceCell::new();
 
-            let result = tokio::time::timeout(timeout, client.request(req)).await;
-            let latency_ms = start.elapsed().as_millis() as u64;
-
-            match result {
-                Ok(Ok(response)) => {
-                    let status = response.status();
-                    let headers = response.headers().clone();
-                    let body_result = response.into_body().collect().await;
-
-                    match body_result {
-                        Ok(collected) => {
-                            let body_bytes = collected.to_bytes();
-                            (
-                                TargetResult {
-                                    url: resolved_url,
-                                    status: Some(status.as_u16()),
-                                    latency_ms,
-                                    error: None,
-                                },
-                                Some((status, headers, body_bytes)),
-                            )
-                        }
-                        Err(e) => (
-                            TargetResult {
-                                url: resolved_url,
-                                status: Some(status.as_u16()),
-                                latency_ms,
-                                error: Some(format!("body read error: {e}")),
-                            },
-                            None,
-                        ),
-                    }
-                }
-                Ok(Err(e)) => (
-                    TargetResult {
-                        url: resolved_url,
-                        status: None,
-                        latency_ms,
-                        error: Some(e.to_string()),
-                    },
-                    None,
-                ),
-                Err(_) => (
-                    TargetResult {
-                        url: resolved_url,
-                        status: None,
-                        latency_ms,
-                        error: Some("request timed out".into()),
-                    },
-                    None,
-                ),
-            }
+    for (idx, target) in req.targets.iter().enumerate() {
+        let pool = req.clients.get(
+            target.pool_settings(req.defaults),
+            target.outbound_proxy(req.defaults),
+        );
+        let Some(outbound) =
+            req.prepare(&target.url, target.timeout, pool, target.signing.as_ref())
+        else {
+            continue;
         };
+        let task = send(outbound);
 
         if idx == primary_idx {
             // Primary: store handle so we can await it directly
             primary_handle = Some(tokio::spawn(task));
         } else {
             // Secondary: fire-and-forget with self-contained logging
-            let cid = correlation_id.clone();
-            tokio::spawn(async move {
+            let cid = req.correlation_id.to_string();
+            let secondary = async move {
                 let (target_result, _) = task.await;
                 if let Some(err) = &target_result.error {
                     tracing::warn!(
@@ -192,13 +166,64 @@ pub async fn fan_out(req: FanOutRequest<'_>) -> Result<FanOutResult, Switchboard
                         "secondary target responded"
                     );
                 }
+            };
+
+            let ordering_key = target.ordering_key.as_ref().map(|key| {
+                ordering::extract_key(key, req.params, req.original_headers, || {
+                    json_body
+                        .get_or_init(|| ordering::parse_json_body(req.body))
+                        .clone()
+                })
             });
+            match ordering_key {
+                Some(Some(key)) => {
+                    if !req.queues.enqueue(&target.url, key, secondary) {
+                        tracing::warn!(
+                            correlation_id = %req.correlation_id,
+                            target = %target.url,
+                            "ordered queue is full, dropping secondary request"
+                        );
+                    }
+                }
+                Some(None) => {
+                    tracing::warn!(
+                        correlation_id = %req.correlation_id,
+                        target = %target.url,
+                        "ordering key missing from request, delivering unordered"
+                    );
+                    tokio::spawn(secondary);
+                }
+                None => {
+                    tokio::spawn(secondary);
+                }
+            }
         }
     }
 
-    // Await only the primary target
+    let hedge = req
+        .route
+        .hedge
+        .as_ref()
+        .filter(|hedge| method_matches(&hedge.methods, req.method.as_str()))
+        .and_then(|hedge| {
+            let pool = req
+                .clients
+                .get(&req.defaults.pool, req.defaults.outbound_proxy.as_ref());
+            Some((hedge, req.prepare(&hedge.url, hedge.timeout, pool, None)?))
+        });
+
+    // Await only the primary target (raced against the hedge, if any)
+    let mut hedged = false;
     let primary_response = if let Some(handle) = primary_handle {
-        match handle.await {
+        let result = match hedge {
+            Some((hedge, outbound)) => {
+                let (result, sent) = race_hedge(handle, hedge, send(outbound)).await;
+                hedged = sent;
+                result
+            }
+            None => handle.await,
+        };
+        match result {
             Ok((target_result, response_data)) => {
                 if let Some(err) = &target_result.error {
                     tracing::warn!(
@@ -226,7 +251,167 @@ pub async fn fan_out(req: FanOutRequest<'_>) -> Result<FanOutResult, Switchboard
         None
     };
 
-    Ok(FanOutResult { primary_response })
+    Ok(FanOutResult {
+        primary_response,
+        hedged,
+    })
+}
+
+/// Send one request to a target and collect its response.
+#[allow(clippy::cast_possible_truncation)]
+async fn send(req: TargetRequest) -> Outcome {
+    let TargetRequest {
+        pool,
+        method,
+        url,
+        mut headers,
+        body,
+        timeout,
+        signing,
+    } = req;
+    let start = Instant::now();
+
+    if let Some((signing, parsed_url)) = signing {
+        if let Err(e) = signing::sign(&signing, &mut headers, &method, &parsed_url, &body) {
+            return (
+                TargetResult {
+                    url,
+                    status: None,
+                    latency_ms: 0,
+                    error: Some(format!("request signing failed: {e}")),
+                },
+                None,
+            );
+        }
+    }
+
+    let mut req_builder = hyper::Request::builder().method(method).uri(url.clone());
+
+    for (key, value) in &headers {
+        req_builder = req_builder.header(key, value);
+    }
+
+    let mut req = match req_builder.body(Full::new(body)) {
+        Ok(r) => r,
+        Err(e) => {
+            return (
+                TargetResult {
+                    url,
+                    status: None,
+                    latency_ms: start.elapsed().as_millis() as u64,
+                    error: Some(e.to_string()),
+                },
+                None,
+            );
+        }
+    };
+
+    if let Some(auth) = pool.proxy_authorization(req.uri()) {
+        req.headers_mut()
+            .insert(hyper::header::PROXY_AUTHORIZATION, auth);
+    }
+
+    // Waiting for a free slot under `max_connections` counts
+    // against the timeout; the slot is held until the body is read.
+    let result = tokio::time::timeout(timeout, async {
+        let permit = pool.acquire(req.uri()).await;
+        pool.client().request(req).await.map(|resp| (resp, permit))
+    })
+    .await;
+    let latency_ms = start.elapsed().as_millis() as u64;
+
+    match result {
+        Ok(Ok((response, _permit))) => {
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body_result = response.into_body().collect().await;
+
+            match body_result {
+                Ok(collected) => {
+                    let body_bytes = collected.to_bytes();
+                    (
+                        TargetResult {
+                            url,
+                            status: Some(status.as_u16()),
+                            latency_ms,
+                            error: None,
+                        },
+                        Some((status, headers, body_bytes)),
+                    )
+                }
+                Err(e) => (
+                    TargetResult {
+                        url,
+                        status: Some(status.as_u16()),
+                        latency_ms,
+                        error: Some(format!("body read error: {e}")),
+                    },
+                    None,
+                ),
+            }
+        }
+        Ok(Err(e)) => (
+            TargetResult {
+                url,
+                status: None,
+                latency_ms,
+                error: Some(e.to_string()),
+            },
+            None,
+        ),
+        Err(_) => (
+            TargetResult {
+                url,
+                status: None,
+                latency_ms,
+                error: Some("request timed out".into()),
+            },
+            None,
+        ),
+    }
+}
+
+/// Race the primary against a hedge request sent after `hedge.delay` ms.
+///
+/// The first request to answer successfully wins and the other one is
+/// aborted. If the first to finish failed, the other is awaited; when both
+/// fail, the primary's outcome is returned. The flag reports whether the
+/// hedge request was sent at all.
+async fn race_hedge(
+    mut primary: JoinHandle<Outcome>,
+    hedge: &Hedge,
+    hedge_request: impl Future<Output = Outcome> + Send + 'static,
+) -> (Result<Outcome, JoinError>, bool) {
+    tokio::select! {
+        result = &mut primary => return (result, false),
+        () = tokio::time::sleep(Duration::from_millis(hedge.delay)) => {}
+    }
+
+    tracing::debug!(hedge = %hedge.url, delay_ms = hedge.delay, "primary is slow, sending hedge request");
+    let mut hedge_handle = tokio::spawn(hedge_request);
+
+    let (primary_first, first) = tokio::select! {
+        result = &mut primary => (true, result),
+        result = &mut hedge_handle => (false, result),
+    };
+    let other = if primary_first { hedge_handle } else { primary };
+
+    if matches!(&first, Ok((_, Some(_)))) {
+        other.abort();
+        if !primary_first {
+            tracing::info!(hedge = %hedge.url, "hedge request answered first");
+        }
+        return (first, true);
+    }
+
+    let second = other.await;
+    let second_succeeded = matches!(&second, Ok((_, Some(_))));
+    let result = if second_succeeded || !primary_first {
+        second
+    } else {
+        first
+    };
+    (result, true)
 }
 
 /// Substitute `:param` placeholders in URL templates.
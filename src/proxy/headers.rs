@@ -1,16 +1,26 @@
 //! Header construction, forwarding, and hop-by-hop stripping.
 //!
 //! [`build_forwarded_headers`] clones the original client headers (when
-//! forwarding is enabled), strips hop-by-hop headers, rewrites `Host`,
-//! adds proxy metadata (`X-Forwarded-For`, `X-Real-IP`, `Via`,
-//! `X-Correlation-Id`), and applies per-route and per-defaults custom
-//! header rules.
-
+//! forwarding is enabled), strips hop-by-hop headers (including any named
+//! in the `Connection` header, per RFC 7230 §6.1), rewrites `Host`, adds
+//! proxy metadata (`X-Forwarded-For`, `X-Real-IP`, RFC 7239 `Forwarded`,
+//! `Via`, `X-Correlation-Id`), and applies per-route and per-defaults
+//! custom header rules. [`apply_response_header_rules`] applies the
+//! `response_headers` rules to every response returned to the client,
+//! including the ones Switchboard generates itself.
+
+use std::net::IpAddr;
 use std::sync::LazyLock;
 
 use axum::http::{HeaderMap, HeaderName, HeaderValue};
 
-use crate::config::model::{Defaults, Route};
+use super::client_ip::ClientAddr;
+use crate::config::model::{Defaults, HeaderRules, Route};
+
+/// Node identifier used for the `by` parameter of the `Forwarded` header.
+///
+/// RFC 7239 §6.3 obfuscated identifier — avoids leaking the listen address.
+const FORWARDED_BY: &str = "_switchboard";
 
 static HOP_BY_HOP: LazyLock<Vec<HeaderName>> = LazyLock::new(|| {
     [
@@ -28,6 +38,34 @@ static HOP_BY_HOP: LazyLock<Vec<HeaderName>> = LazyLock::new(|| {
     .collect()
 });
 
+/// Client-identifying headers that must not be trusted from an untrusted peer.
+const FORWARDING_CHAIN: &[&str] = &[
+    "x-forwarded-for",
+    "x-forwarded-proto",
+    "x-forwarded-host",
+    "x-real-ip",
+    "forwarded",
+];
+
+/// Remove hop-by-hop headers, including any extra header names listed as
+/// tokens in the `Connection` header (RFC 7230 §6.1).
+fn remove_hop_by_hop(headers: &mut HeaderMap) {
+    let listed: Vec<HeaderName> = headers
+        .get_all(hyper::header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|token| token.trim().parse::<HeaderName>().ok())
+        .collect();
+
+    for name in &listed {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP.iter() {
+        headers.remove(name);
+    }
+}
+
 /// Strip hop-by-hop headers and `content-length` from an upstream response.
 ///
 /// The body has already been fully collected by the fan-out engine, so
@@ -35,15 +73,13 @@ static HOP_BY_HOP: LazyLock<Vec<HeaderName>> = LazyLock::new(|| {
 /// accurate. Axum will set the correct `content-length` based on the actual
 /// body bytes.
 pub fn strip_response_hop_by_hop(headers: &mut HeaderMap) {
-    for name in HOP_BY_HOP.iter() {
-        headers.remove(name);
-    }
+    remove_hop_by_hop(headers);
     headers.remove(hyper::header::CONTENT_LENGTH);
 }
 
 pub fn build_forwarded_headers(
     original: &HeaderMap,
-    client_ip: &str,
+    client: &ClientAddr,
     target_url: &url::Url,
     route: &Route,
     defaults: &Defaults,
@@ -57,9 +93,7 @@ pub fn build_forwarded_headers(
 
     // Strip hop-by-hop
     if defaults.strip_hop_by_hop {
-        for header_name in HOP_BY_HOP.iter() {
-            headers.remove(header_name);
-        }
+        remove_hop_by_hop(&mut headers);
     }
 
     // Rewrite Host
@@ -72,44 +106,68 @@ pub fn build_forwarded_headers(
         }
     }
 
-    // X-Forwarded-For: append to chain
-    if defaults.proxy_headers {
-        let xff = headers
-            .get("x-forwarded-for")
-            .and_then(|v| v.to_str().ok())
-            .map_or_else(
-                || client_ip.to_string(),
-                |existing| format!("{existing}, {client_ip}"),
-            );
-        if let Ok(val) = HeaderValue::from_str(&xff) {
-            headers.insert("x-forwarded-for", val);
-        }
-
-        // X-Real-IP (first IP in chain)
-        let real_ip = xff.split(',').next().unwrap_or(client_ip).trim();
-        if let Ok(val) = HeaderValue::from_str(real_ip) {
-            headers.insert("x-real-ip", val);
+    // An untrusted peer controls the incoming chain: never pass it on,
+    // whether or not a fresh one is started below
+    if !client.trusted_peer {
+        for name in FORWARDING_CHAIN {
+            headers.remove(*name);
         }
+    }
 
-        // X-Forwarded-Proto
+    if defaults.proxy_headers {
+        let peer_ip = client.peer.to_string();
+        let style = route.forwarded_style.unwrap_or(defaults.forwarded_style);
         let proto = if target_url.scheme() == "https" {
             "https"
         } else {
             "http"
         };
-        if let Ok(val) = HeaderValue::from_str(proto) {
-            headers.insert("x-forwarded-proto", val);
+
+        if style.x_forwarded() {
+            // X-Forwarded-For: append the peer to the chain
+            let xff = headers
+                .get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok())
+                .map_or_else(
+                    || peer_ip.clone(),
+                    |existing| format!("{existing}, {peer_ip}"),
+                );
+            if let Ok(val) = HeaderValue::from_str(&xff) {
+                headers.insert("x-forwarded-for", val);
+            }
+
+            // X-Real-IP (resolved client, see `client_ip::resolve`)
+            if let Ok(val) = HeaderValue::from_str(&client.ip.to_string()) {
+                headers.insert("x-real-ip", val);
+            }
+
+            // X-Forwarded-Proto
+            headers.insert("x-forwarded-proto", HeaderValue::from_static(proto));
+
+            // X-Forwarded-Host (original Host the client targeted)
+            if let Some(original_host) = original.get("host") {
+                headers.insert("x-forwarded-host", original_host.clone());
+            }
         }
 
-        // X-Forwarded-Host (original Host the client targeted)
-        if let Some(original_host) = original.get("host") {
-            headers.insert("x-forwarded-host", original_host.clone());
+        // Forwarded (RFC 7239): append an element to the existing list
+        if style.forwarded() {
+            let original_host = original.get("host").and_then(|v| v.to_str().ok());
+            let element = forwarded_element(&peer_ip, proto, original_host);
+            let forwarded = headers
+                .get("forwarded")
+                .and_then(|v| v.to_str().ok())
+                .map_or_else(
+                    || element.clone(),
+                    |existing| format!("{existing}, {element}"),
+                );
+            if let Ok(val) = HeaderValue::from_str(&forwarded) {
+                headers.insert("forwarded", val);
+            }
         }
 
         // Via
-        if let Ok(val) = HeaderValue::from_str("1.1 switchboard") {
-            headers.insert("via", val);
-        }
+        headers.insert("via", HeaderValue::from_static("1.1 switchboard"));
 
         // Correlation ID
         if let Ok(val) = HeaderValue::from_str(correlation_id) {
@@ -117,62 +175,141 @@ pub fn build_forwarded_headers(
         }
     }
 
-    // Apply defaults.headers.add
-    for (key, value) in &defaults.headers.add {
-        match (key.parse::<HeaderName>(), HeaderValue::from_str(value)) {
-            (Ok(name), Ok(val)) => {
-                headers.insert(name, val);
-            }
-            _ => {
-                tracing::warn!(header = %key, "invalid header name or value in defaults.headers.add, skipping");
+    apply_rules(&mut headers, &defaults.headers, &route.headers, "headers");
+
+    headers
+}
+
+/// Apply `defaults.response_headers` and `route.response_headers` to the
+/// response returned to the client.
+pub fn apply_response_header_rules(headers: &mut HeaderMap, route: &Route, defaults: &Defaults) {
+    apply_rules(
+        headers,
+        &defaults.response_headers,
+        &route.response_headers,
+        "response_headers",
+    );
+}
+
+/// Apply only the `defaults.response_headers` rules, for responses to
+/// requests that matched no route.
+pub fn apply_default_response_header_rules(headers: &mut HeaderMap, defaults: &Defaults) {
+    apply_rules(
+        headers,
+        &defaults.response_headers,
+        &HeaderRules::default(),
+        "response_headers",
+    );
+}
+
+/// Apply a defaults/route pair of header rules, in order: `add` (route
+/// overrides defaults), `set_if_absent` (route wins, existing headers are
+/// kept), then `strip` (always wins).
+fn apply_rules(headers: &mut HeaderMap, defaults: &HeaderRules, route: &HeaderRules, field: &str) {
+    for (scope, rules) in [("defaults", defaults), ("route", route)] {
+        for (key, value) in &rules.add {
+            match (key.parse::<HeaderName>(), HeaderValue::from_str(value)) {
+                (Ok(name), Ok(val)) => {
+                    headers.insert(name, val);
+                }
+                _ => {
+                    tracing::warn!(header = %key, "invalid header name or value in {scope}.{field}.add, skipping");
+                }
             }
         }
     }
 
-    // Apply route.headers.add (overrides defaults)
-    for (key, value) in &route.headers.add {
-        match (key.parse::<HeaderName>(), HeaderValue::from_str(value)) {
-            (Ok(name), Ok(val)) => {
-                headers.insert(name, val);
-            }
-            _ => {
-                tracing::warn!(header = %key, "invalid header name or value in route.headers.add, skipping");
+    for (scope, rules) in [("route", route), ("defaults", defaults)] {
+        for (key, value) in &rules.set_if_absent {
+            match (key.parse::<HeaderName>(), HeaderValue::from_str(value)) {
+                (Ok(name), Ok(val)) => {
+                    headers.entry(name).or_insert(val);
+                }
+                _ => {
+                    tracing::warn!(header = %key, "invalid header name or value in {scope}.{field}.set_if_absent, skipping");
+                }
             }
         }
     }
 
-    // Apply defaults.headers.strip
-    for key in &defaults.headers.strip {
-        if let Ok(name) = key.parse::<HeaderName>() {
-            headers.remove(&name);
+    for rules in [defaults, route] {
+        for key in &rules.strip {
+            if let Ok(name) = key.parse::<HeaderName>() {
+                headers.remove(&name);
+            }
         }
     }
+}
 
-    // Apply route.headers.strip
-    for key in &route.headers.strip {
-        if let Ok(name) = key.parse::<HeaderName>() {
-            headers.remove(&name);
-        }
+/// Build a single RFC 7239 `Forwarded` element for this hop.
+fn forwarded_element(peer_ip: &str, proto: &str, host: Option<&str>) -> String {
+    let mut element = format!(
+        "for={};by={};proto={proto}",
+        forwarded_node(peer_ip),
+        forwarded_node(FORWARDED_BY)
+    );
+    if let Some(host) = host {
+        element.push_str(";host=");
+        element.push_str(&forwarded_value(host));
     }
+    element
+}
 
-    headers
+/// Format a `for`/`by` node. IPv6 addresses must be bracketed and quoted
+/// (RFC 7239 §6), e.g. `for="[2001:db8::1]"`.
+fn forwarded_node(node: &str) -> String {
+    match node.parse::<IpAddr>() {
+        Ok(IpAddr::V6(v6)) => format!("\"[{v6}]\""),
+        _ => forwarded_value(node),
+    }
+}
+
+/// Emit a parameter value as a `token` when possible, otherwise as a
+/// `quoted-string` (RFC 7230 §3.2.6).
+fn forwarded_value(value: &str) -> String {
+    let is_tchar = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if !value.is_empty() && value.chars().all(is_tchar) {
+        return value.to_string();
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::model::{Defaults, HeaderRules, Route, Target};
+    use crate::config::model::{Defaults, ForwardedStyle, HeaderRules, Route, Target};
+
+    fn direct(ip: &str) -> ClientAddr {
+        ClientAddr::direct(ip.parse().unwrap())
+    }
 
     fn default_route() -> Route {
         Route {
             path: "/test".into(),
             methods: vec!["*".into()],
             timeout: None,
+            forwarded_style: None,
             headers: HeaderRules::default(),
+            response_headers: HeaderRules::default(),
+            hedge: None,
+            dedup: None,
             targets: vec![Target {
                 url: "http://target:8080/test".into(),
                 primary: false,
                 timeout: None,
+                pool: None,
+                outbound_proxy: None,
+                ordering_key: None,
+                signing: None,
             }],
         }
     }
@@ -186,7 +323,7 @@ mod tests {
         let target = url::Url::parse("http://target:8080").unwrap();
         let result = build_forwarded_headers(
             &original,
-            "10.0.0.1",
+            &direct("10.0.0.1"),
             &target,
             &default_route(),
             &Defaults::default(),
@@ -203,7 +340,7 @@ mod tests {
         let target = url::Url::parse("http://backend:9090/path").unwrap();
         let result = build_forwarded_headers(
             &original,
-            "10.0.0.1",
+            &direct("10.0.0.1"),
             &target,
             &default_route(),
             &Defaults::default(),
@@ -219,9 +356,14 @@ mod tests {
         original.insert("x-forwarded-for", "1.2.3.4".parse().unwrap());
 
         let target = url::Url::parse("http://target:8080").unwrap();
+        let client = ClientAddr {
+            peer: "10.0.0.1".parse().unwrap(),
+            ip: "1.2.3.4".parse().unwrap(),
+            trusted_peer: true,
+        };
         let result = build_forwarded_headers(
             &original,
-            "10.0.0.1",
+            &client,
             &target,
             &default_route(),
             &Defaults::default(),
@@ -229,6 +371,62 @@ mod tests {
         );
 
         assert_eq!(result.get("x-forwarded-for").unwrap(), "1.2.3.4, 10.0.0.1");
+        assert_eq!(result.get("x-real-ip").unwrap(), "1.2.3.4");
+    }
+
+    #[test]
+    fn drops_chain_from_untrusted_peer() {
+        let mut original = HeaderMap::new();
+        original.insert("x-forwarded-for", "6.6.6.6".parse().unwrap());
+        original.insert("x-real-ip", "6.6.6.6".parse().unwrap());
+        original.insert("forwarded", "for=6.6.6.6".parse().unwrap());
+
+        let target = url::Url::parse("http://target:8080").unwrap();
+        let defaults = Defaults {
+            forwarded_style: ForwardedStyle::Both,
+            ..Defaults::default()
+        };
+        let result = build_forwarded_headers(
+            &original,
+            &direct("203.0.113.7"),
+            &target,
+            &default_route(),
+            &defaults,
+            "test-id",
+        );
+
+        assert_eq!(result.get("x-forwarded-for").unwrap(), "203.0.113.7");
+        assert_eq!(result.get("x-real-ip").unwrap(), "203.0.113.7");
+        assert_eq!(
+            result.get("forwarded").unwrap(),
+            "for=203.0.113.7;by=_switchboard;proto=http"
+        );
+    }
+
+    #[test]
+    fn drops_chain_from_untrusted_peer_without_proxy_headers() {
+        let mut original = HeaderMap::new();
+        original.insert("x-forwarded-for", "6.6.6.6".parse().unwrap());
+        original.insert("x-real-ip", "6.6.6.6".parse().unwrap());
+        original.insert("forwarded", "for=6.6.6.6".parse().unwrap());
+
+        let target = url::Url::parse("http://target:8080").unwrap();
+        let defaults = Defaults {
+            proxy_headers: false,
+            ..Defaults::default()
+        };
+        let result = build_forwarded_headers(
+            &original,
+            &direct("203.0.113.7"),
+            &target,
+            &default_route(),
+            &defaults,
+            "test-id",
+        );
+
+        assert!(result.get("x-forwarded-for").is_none());
+        assert!(result.get("x-real-ip").is_none());
+        assert!(result.get("forwarded").is_none());
     }
 
     #[test]
@@ -237,7 +435,7 @@ mod tests {
         let target = url::Url::parse("http://target:8080").unwrap();
         let result = build_forwarded_headers(
             &original,
-            "10.0.0.1",
+            &direct("10.0.0.1"),
             &target,
             &default_route(),
             &Defaults::default(),
@@ -256,7 +454,7 @@ mod tests {
 
         let result = build_forwarded_headers(
             &original,
-            "10.0.0.1",
+            &direct("10.0.0.1"),
             &target,
             &route,
             &Defaults::default(),
@@ -265,4 +463,186 @@ mod tests {
 
         assert_eq!(result.get("x-custom").unwrap(), "value");
     }
+
+    #[test]
+    fn strips_headers_listed_in_connection() {
+        let mut original = HeaderMap::new();
+        original.insert("connection", "close, X-Internal-Token".parse().unwrap());
+        original.insert("x-internal-token", "secret".parse().unwrap());
+        original.insert("content-type", "application/json".parse().unwrap());
+
+        let target = url::Url::parse("http://target:8080").unwrap();
+        let result = build_forwarded_headers(
+            &original,
+            &direct("10.0.0.1"),
+            &target,
+            &default_route(),
+            &Defaults::default(),
+            "test-id",
+        );
+
+        assert!(result.get("x-internal-token").is_none());
+        assert!(result.get("connection").is_none());
+        assert!(result.get("content-type").is_some());
+    }
+
+    #[test]
+    fn response_strips_headers_listed_in_connection() {
+        let mut headers = HeaderMap::new();
+        headers.insert("connection", "x-upstream-debug".parse().unwrap());
+        headers.insert("x-upstream-debug", "1".parse().unwrap());
+        headers.insert("server", "nginx".parse().unwrap());
+
+        strip_response_hop_by_hop(&mut headers);
+
+        assert!(headers.get("x-upstream-debug").is_none());
+        assert!(headers.get("server").is_some());
+    }
+
+    #[test]
+    fn forwarded_style_emits_rfc7239_only() {
+        let mut original = HeaderMap::new();
+        original.insert("host", "api.example.com".parse().unwrap());
+
+        let target = url::Url::parse("https://target:8443").unwrap();
+        let defaults = Defaults {
+            forwarded_style: ForwardedStyle::Forwarded,
+            ..Defaults::default()
+        };
+        let result = build_forwarded_headers(
+            &original,
+            &direct("10.0.0.1"),
+            &target,
+            &default_route(),
+            &defaults,
+            "test-id",
+        );
+
+        assert_eq!(
+            result.get("forwarded").unwrap(),
+            "for=10.0.0.1;by=_switchboard;proto=https;host=api.example.com"
+        );
+        assert!(result.get("x-forwarded-for").is_none());
+        assert!(result.get("x-real-ip").is_none());
+    }
+
+    #[test]
+    fn forwarded_quotes_ipv6_and_appends() {
+        let mut original = HeaderMap::new();
+        original.insert("forwarded", "for=192.0.2.43".parse().unwrap());
+        original.insert("host", "example.com:8080".parse().unwrap());
+
+        let target = url::Url::parse("http://target:8080").unwrap();
+        let mut route = default_route();
+        route.forwarded_style = Some(ForwardedStyle::Both);
+        let client = ClientAddr {
+            peer: "2001:db8::1".parse().unwrap(),
+            ip: "192.0.2.43".parse().unwrap(),
+            trusted_peer: true,
+        };
+        let result = build_forwarded_headers(
+            &original,
+            &client,
+            &target,
+            &route,
+            &Defaults::default(),
+            "test-id",
+        );
+
+        assert_eq!(
+            result.get("forwarded").unwrap(),
+            "for=192.0.2.43, for=\"[2001:db8::1]\";by=_switchboard;proto=http;host=\"example.com:8080\""
+        );
+        assert!(result.get("x-forwarded-for").is_some());
+    }
+
+    #[test]
+    fn set_if_absent_keeps_existing_headers() {
+        let mut original = HeaderMap::new();
+        original.insert("x-tenant", "acme".parse().unwrap());
+        let target = url::Url::parse("http://target:8080").unwrap();
+        let mut route = default_route();
+        route
+            .headers
+            .set_if_absent
+            .insert("x-tenant".into(), "default".into());
+        let mut defaults = Defaults::default();
+        defaults
+            .headers
+            .set_if_absent
+            .insert("x-region".into(), "eu".into());
+        route
+            .headers
+            .set_if_absent
+            .insert("x-region".into(), "us".into());
+
+        let result = build_forwarded_headers(
+            &original,
+            &direct("10.0.0.1"),
+            &target,
+            &route,
+            &defaults,
+            "test-id",
+        );
+
+        assert_eq!(result.get("x-tenant").unwrap(), "acme");
+        assert_eq!(result.get("x-region").unwrap(), "us");
+    }
+
+    #[test]
+    fn response_rules_add_set_and_strip() {
+        let mut headers = HeaderMap::new();
+        headers.insert("server", "nginx".parse().unwrap());
+        headers.insert("x-powered-by", "php".parse().unwrap());
+        headers.insert(
+            "content-security-policy",
+            "default-src 'none'".parse().unwrap(),
+        );
+
+        let mut defaults = Defaults::default();
+        defaults.response_headers.strip = vec!["Server".into(), "X-Powered-By".into()];
+        defaults.response_headers.set_if_absent.insert(
+            "content-security-policy".into(),
+            "default-src 'self'".into(),
+        );
+        defaults.response_headers.set_if_absent.insert(
+            "strict-transport-security".into(),
+            "max-age=63072000".into(),
+        );
+        let mut route = default_route();
+        route
+            .response_headers
+            .add
+            .insert("x-switchboard-route".into(), "/test".into());
+
+        apply_response_header_rules(&mut headers, &route, &defaults);
+
+        assert!(headers.get("server").is_none());
+        assert!(headers.get("x-powered-by").is_none());
+        assert_eq!(
+            headers.get("content-security-policy").unwrap(),
+            "default-src 'none'"
+        );
+        assert_eq!(
+            headers.get("strict-transport-security").unwrap(),
+            "max-age=63072000"
+        );
+        assert_eq!(headers.get("x-switchboard-route").unwrap(), "/test");
+    }
+
+    #[test]
+    fn response_strip_wins_over_add() {
+        let mut headers = HeaderMap::new();
+        let mut defaults = Defaults::default();
+        defaults
+            .response_headers
+            .add
+            .insert("x-debug".into(), "1".into());
+        let mut route = default_route();
+        route.response_headers.strip = vec!["x-debug".into()];
+
+        apply_response_header_rules(&mut headers, &route, &defaults);
+
+        assert!(headers.get("x-debug").is_none());
+    }
 }
@@ -3,22 +3,32 @@
 //! The [`forward_handler`] function is the Axum fallback that receives
 //! every non-`/health` request, matches it against configured routes,
 //! and delegates to the fan-out engine. Submodules handle route matching
-//! ([`routing`]), header construction ([`headers`]), and concurrent
-//! target dispatch ([`fanout`]).
+//! ([`routing`]), client address resolution ([`client_ip`]), header
+//! construction ([`headers`]), concurrent target dispatch ([`fanout`]),
+//! ordered secondary delivery ([`ordering`]), duplicate request handling
+//! ([`dedup`]), request signing ([`signing`]), and outbound proxy
+//! connections ([`outbound`]).
 
+pub mod client_ip;
+pub mod dedup;
 pub mod fanout;
 pub mod headers;
+pub mod ordering;
+pub mod outbound;
 pub mod routing;
+pub mod signing;
 
 use std::net::SocketAddr;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::body::Bytes;
 use axum::extract::{ConnectInfo, State};
-use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, Uri};
 use axum::response::{IntoResponse, Response};
 
+use crate::config::model::{DedupMode, Defaults, Route};
 use crate::server::AppState;
 
 pub async fn forward_handler(
@@ -51,7 +61,7 @@ async fn forward_inner(
         .map_or_else(|| uuid::Uuid::new_v4().to_string(), String::from);
 
     // Clone the Arc<Config> (cheap refcount bump) to release the RwLock before .await
-    let (config, route_idx, params) = {
+    let (config, trusted_proxies, route_idx, params) = {
         let config_guard = state.config.read().await;
         let config = Arc::clone(&config_guard.config);
 
@@ -63,60 +73,139 @@ async fn forward_inner(
                 path = %path,
                 "no route matched"
             );
-            return StatusCode::NOT_FOUND.into_response();
+            let mut response = StatusCode::NOT_FOUND.into_response();
+            headers::apply_default_response_header_rules(response.headers_mut(), &config.defaults);
+            return response;
         };
 
-        (config, route_idx, params)
+        let trusted_proxies = Arc::clone(config_guard.trusted_proxies());
+        (config, trusted_proxies, route_idx, params)
     };
 
     let route = &config.routes[route_idx];
     let defaults = &config.defaults;
 
+    let client_addr = client_ip::resolve(addr.ip(), &req_headers, &trusted_proxies);
+
     tracing::info!(
         correlation_id = %correlation_id,
         method = %method,
         path = %path,
+        client_ip = %client_addr.ip,
         targets = route.targets.len(),
         "request received"
     );
 
-    let client_ip = addr.ip().to_string();
+    // A duplicate inside the dedup window is replayed or sent to the primary
+    // only; the first request with a key reserves it until it completes
+    let mut targets = route.targets.as_slice();
+    let mut reservation = None;
+    if let Some(dedup) = &route.dedup {
+        let key = dedup::request_key(dedup, &route.path, &method, &uri, &req_headers, &body);
+        let ttl = Duration::from_millis(dedup.ttl);
+        let claim = match key {
+            Some(key) => match dedup::Reservation::claim(&state.dedup_store, &key, ttl).await {
+                Ok(claim) => Some(claim),
+                Err(e) => {
+                    tracing::warn!(correlation_id = %correlation_id, error = %e, "dedup lookup failed");
+                    None
+                }
+            },
+            None => None,
+        };
+        match claim {
+            // Not deduplicated: no key, or the store is unavailable
+            None => {}
+            Some(Ok(claimed)) => reservation = Some((claimed, ttl)),
+            Some(Err(duplicate)) => {
+                state.stats.deduplicated.fetch_add(1, Ordering::Relaxed);
+                tracing::info!(
+                    correlation_id = %correlation_id,
+                    mode = ?dedup.mode,
+                    in_flight = duplicate == dedup::Claim::InFlight,
+                    "duplicate request"
+                );
+                match (dedup.mode, duplicate) {
+                    (DedupMode::Replay, dedup::Claim::Done(stored)) => {
+                        let mut resp_headers = stored.headers;
+                        dedup::mark_replayed(&mut resp_headers);
+                        return build_response(
+                            stored.status,
+                            resp_headers,
+                            stored.body,
+                            route,
+                            defaults,
+                            &correlation_id,
+                        );
+                    }
+                    (DedupMode::Replay, _) => {
+                        let mut response =
+                            error_response(StatusCode::CONFLICT, route, defaults, &correlation_id);
+                        response
+                            .headers_mut()
+                            .insert("retry-after", HeaderValue::from_static("1"));
+                        return response;
+                    }
+                    (DedupMode::SkipSecondaries, _) => {
+                        let primary_idx = targets.iter().position(|t| t.primary).unwrap_or(0);
+                        targets = &targets[primary_idx..=primary_idx];
+                    }
+                }
+            }
+        }
+    }
+
     let request = fanout::FanOutRequest {
-        client: &state.http_client,
-        targets: &route.targets,
+        clients: &state.http_clients,
+        queues: &state.ordered_queues,
+        targets,
         method: &method,
         original_headers: &req_headers,
         body: &body,
         params: &params,
         defaults,
         route,
-        client_ip: &client_ip,
+        client_addr: &client_addr,
         correlation_id: &correlation_id,
     };
 
     match fanout::fan_out(request).await {
         Ok(fan_out_result) => {
+            if fan_out_result.hedged {
+                state.stats.hedged.fetch_add(1, Ordering::Relaxed);
+            }
             if let Some((status, mut resp_headers, body_bytes)) = fan_out_result.primary_response {
                 state.stats.forwarded.fetch_add(1, Ordering::Relaxed);
                 headers::strip_response_hop_by_hop(&mut resp_headers);
-                let mut builder = Response::builder().status(status);
-                for (key, value) in &resp_headers {
-                    builder = builder.header(key, value);
+                // A server error is not kept: dropping the reservation lets
+                // a retry through again
+                if let Some((reservation, ttl)) = reservation {
+                    if !status.is_server_error() {
+                        let stored = dedup::StoredResponse {
+                            status,
+                            headers: resp_headers.clone(),
+                            body: body_bytes.clone(),
+                        };
+                        if let Err(e) = reservation.complete(&stored, ttl).await {
+                            tracing::warn!(
+                                correlation_id = %correlation_id,
+                                error = %e,
+                                "dedup store failed"
+                            );
+                        }
+                    }
                 }
-                builder
-                    .header("x-correlation-id", &correlation_id)
-                    .body(axum::body::Body::from(body_bytes))
-                    .unwrap_or_else(|e| {
-                        tracing::error!(
-                            correlation_id = %correlation_id,
-                            error = %e,
-                            "failed to build response"
-                        );
-                        StatusCode::BAD_GATEWAY.into_response()
-                    })
+                build_response(
+                    status,
+                    resp_headers,
+                    body_bytes,
+                    route,
+                    defaults,
+                    &correlation_id,
+                )
             } else {
                 state.stats.failed.fetch_add(1, Ordering::Relaxed);
-                StatusCode::BAD_GATEWAY.into_response()
+                error_response(StatusCode::BAD_GATEWAY, route, defaults, &correlation_id)
             }
         }
         Err(e) => {
@@ -126,7 +215,44 @@ async fn forward_inner(
                 "fan-out failed"
             );
             state.stats.failed.fetch_add(1, Ordering::Relaxed);
-            StatusCode::BAD_GATEWAY.into_response()
+            error_response(StatusCode::BAD_GATEWAY, route, defaults, &correlation_id)
         }
     }
 }
+
+/// Build the client-facing response from a primary (or replayed) response.
+fn build_response(
+    status: StatusCode,
+    mut resp_headers: HeaderMap,
+    body: Bytes,
+    route: &Route,
+    defaults: &Defaults,
+    correlation_id: &str,
+) -> Response {
+    if let Ok(val) = HeaderValue::from_str(correlation_id) {
+        resp_headers.insert("x-correlation-id", val);
+    }
+    headers::apply_response_header_rules(&mut resp_headers, route, defaults);
+    let mut response = Response::new(axum::body::Body::from(body));
+    *response.status_mut() = status;
+    *response.headers_mut() = resp_headers;
+    response
+}
+
+/// An empty response generated by Switchboard itself for a matched route,
+/// with the same correlation id and response header rules as a proxied one.
+fn error_response(
+    status: StatusCode,
+    route: &Route,
+    defaults: &Defaults,
+    correlation_id: &str,
+) -> Response {
+    build_response(
+        status,
+        HeaderMap::new(),
+        Bytes::new(),
+        route,
+        defaults,
+        correlation_id,
+    )
+}
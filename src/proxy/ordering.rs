@@ -0,0 +1,348 @@
+//! Ordered per-key delivery to secondary targets.
+//!
+//! Secondaries are normally fire-and-forget, so two requests for the same
+//! entity can reach a downstream out of order. A target with an
+//! `ordering_key` instead sends its requests through [`OrderedQueues`]:
+//! requests sharing a key are delivered one at a time, in arrival order,
+//! while different keys still run concurrently.
+//!
+//! Each (target, key) pair gets a worker task that drains its queue and
+//! exits once the queue is empty, so idle keys cost nothing. A target holds
+//! at most `capacity` jobs across all of its keys; requests beyond that
+//! are dropped, so a slow secondary cannot grow memory without limit.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use axum::http::HeaderMap;
+use bytes::Bytes;
+use tokio::sync::mpsc;
+
+use crate::config::model::OrderingKey;
+
+/// Jobs a target may hold, queued or in flight, unless configured.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+type Job = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A job counted in its target's depth until dropped: after running, when
+/// it panics, or when a dead worker's queue is discarded.
+struct Queued {
+    job: Job,
+    _depth: DepthGuard,
+}
+
+struct DepthGuard {
+    queues: OrderedQueues,
+    target: String,
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        if let Some(queues) = self.queues.lock().get_mut(&self.target) {
+            queues.depth = queues.depth.saturating_sub(1);
+        }
+    }
+}
+
+#[derive(Default)]
+struct TargetQueues {
+    /// Jobs queued or in flight for this target.
+    depth: u64,
+    /// Unbounded per key; `capacity` bounds the jobs of all keys together.
+    workers: HashMap<String, mpsc::UnboundedSender<Queued>>,
+}
+
+/// Serialized per-key queues for secondary targets, shared via `AppState`.
+#[derive(Clone)]
+pub struct OrderedQueues {
+    targets: Arc<Mutex<HashMap<String, TargetQueues>>>,
+    capacity: u64,
+}
+
+impl Default for OrderedQueues {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl OrderedQueues {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues holding at most `capacity` jobs per target.
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            targets: Arc::default(),
+            capacity: u64::try_from(capacity).unwrap_or(u64::MAX),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, TargetQueues>> {
+        self.targets.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queue `job` for delivery to `target` after every earlier job with
+    /// the same `key`. Returns `false`, dropping `job`, when `target`
+    /// already holds `capacity` jobs.
+    #[must_use]
+    pub fn enqueue<F>(&self, target: &str, key: String, job: F) -> bool
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut targets = self.lock();
+        let queues = targets.entry(target.to_string()).or_default();
+        if queues.depth >= self.capacity {
+            return false;
+        }
+        queues.depth += 1;
+
+        let job = Queued {
+            job: Box::pin(job),
+            _depth: DepthGuard {
+                queues: self.clone(),
+                target: target.to_string(),
+            },
+        };
+        let job = match queues.workers.get(&key) {
+            Some(sender) => match sender.send(job) {
+                Ok(()) => return true,
+                // The worker is gone; start a new one with this job
+                Err(mpsc::error::SendError(job)) => job,
+            },
+            None => job,
+        };
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let _ = sender.send(job);
+        queues.workers.insert(key.clone(), sender);
+        drop(targets);
+
+        tokio::spawn(self.clone().run_worker(target.to_string(), key, receiver));
+        true
+    }
+
+    async fn run_worker(
+        self,
+        target: String,
+        key: String,
+        mut receiver: mpsc::UnboundedReceiver<Queued>,
+    ) {
+        loop {
+            let queued = match receiver.try_recv() {
+                Ok(queued) => queued,
+                Err(_) => {
+                    // Jobs are only sent while holding the lock, so an empty
+                    // queue here means the worker can retire safely.
+                    let mut targets = self.lock();
+                    match receiver.try_recv() {
+                        Ok(queued) => queued,
+                        Err(_) => {
+                            if let Some(queues) = targets.get_mut(&target) {
+                                queues.workers.remove(&key);
+                            }
+                            return;
+                        }
+                    }
+                }
+            };
+
+            // The depth guard is released once the job is done
+            queued.job.await;
+        }
+    }
+
+    /// Jobs queued or in flight, per target URL.
+    #[must_use]
+    pub fn depths(&self) -> Vec<(String, u64)> {
+        let mut depths: Vec<(String, u64)> = self
+            .lock()
+            .iter()
+            .map(|(target, queues)| (target.clone(), queues.depth))
+            .collect();
+        depths.sort();
+        depths
+    }
+}
+
+/// Extract the ordering key for a request, or `None` when the request does
+/// not carry it.
+#[must_use]
+pub fn extract_key(
+    key: &OrderingKey,
+    params: &HashMap<String, String>,
+    headers: &HeaderMap,
+    json_body: impl FnOnce() -> Option<serde_json::Value>,
+) -> Option<String> {
+    match key {
+        OrderingKey::Param(name) => params.get(name).cloned(),
+        OrderingKey::Header(name) => headers
+            .get(name.as_str())
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string),
+        OrderingKey::Json(path) => {
+            let body = json_body()?;
+            let pointer: String = path
+                .split('.')
+                .map(|segment| format!("/{}", segment.replace('~', "~0").replace('/', "~1")))
+                .collect();
+            match body.pointer(&pointer)? {
+                serde_json::Value::String(s) => Some(s.clone()),
+                serde_json::Value::Null => None,
+                other => Some(other.to_string()),
+            }
+        }
+    }
+}
+
+/// Parse a request body as JSON for `json` ordering keys.
+#[must_use]
+pub fn parse_json_body(body: &Bytes) -> Option<serde_json::Value> {
+    serde_json::from_slice(body).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn extracts_param_header_and_json_keys() {
+        let mut params = HashMap::new();
+        params.insert("id".to_string(), "42".to_string());
+        let mut headers = HeaderMap::new();
+        headers.insert("x-order-id", "o-7".parse().unwrap());
+        let body = Bytes::from_static(br#"{"order": {"id": 99, "ref": "abc"}}"#);
+
+        let json = || parse_json_body(&body);
+        assert_eq!(
+            extract_key(&OrderingKey::Param("id".into()), &params, &headers, json),
+            Some("42".into())
+        );
+        assert_eq!(
+            extract_key(
+                &OrderingKey::Header("X-Order-Id".into()),
+                &params,
+                &headers,
+                json
+            ),
+            Some("o-7".into())
+        );
+        assert_eq!(
+            extract_key(
+                &OrderingKey::Json("order.id".into()),
+                &params,
+                &headers,
+                json
+            ),
+            Some("99".into())
+        );
+        assert_eq!(
+            extract_key(
+                &OrderingKey::Json("order.ref".into()),
+                &params,
+                &headers,
+                json
+            ),
+            Some("abc".into())
+        );
+        let body = Bytes::from_static(br#"{"a/b": {"c~d": "escaped"}}"#);
+        assert_eq!(
+            extract_key(
+                &OrderingKey::Json("a/b.c~d".into()),
+                &params,
+                &headers,
+                || parse_json_body(&body)
+            ),
+            Some("escaped".into())
+        );
+        assert_eq!(
+            extract_key(
+                &OrderingKey::Json("missing".into()),
+                &params,
+                &headers,
+                json
+            ),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn same_key_runs_in_order_and_reports_depth() {
+        let queues = OrderedQueues::new();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        for i in 0..5u64 {
+            let tx = tx.clone();
+            assert!(queues.enqueue("http://t", "k".into(), async move {
+                // Earlier jobs sleep longer: only serialization keeps order
+                tokio::time::sleep(Duration::from_millis(10 * (5 - i))).await;
+                let _ = tx.send(i);
+            }));
+        }
+        assert_eq!(queues.depths(), vec![("http://t".to_string(), 5)]);
+
+        let mut seen = Vec::new();
+        for _ in 0..5 {
+            seen.push(rx.recv().await.unwrap());
+        }
+        assert_eq!(seen, vec![0, 1, 2, 3, 4]);
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(queues.depths(), vec![("http://t".to_string(), 0)]);
+    }
+
+    #[tokio::test]
+    async fn different_keys_run_concurrently() {
+        let queues = OrderedQueues::new();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let slow = tx.clone();
+        assert!(queues.enqueue("http://t", "a".into(), async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let _ = slow.send("a");
+        }));
+        assert!(queues.enqueue("http://t", "b".into(), async move {
+            let _ = tx.send("b");
+        }));
+
+        assert_eq!(rx.recv().await, Some("b"));
+        assert_eq!(rx.recv().await, Some("a"));
+    }
+
+    #[tokio::test]
+    async fn full_target_drops_jobs_across_keys() {
+        let queues = OrderedQueues::with_capacity(2);
+        let (release, wait) = tokio::sync::oneshot::channel::<()>();
+
+        assert!(queues.enqueue("http://t", "a".into(), async move {
+            let _ = wait.await;
+        }));
+        assert!(queues.enqueue("http://t", "b".into(), async {}));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        // "b" is done; "a" still holds its slot
+        assert!(queues.enqueue("http://t", "c".into(), async {}));
+        assert!(!queues.enqueue("http://t", "d".into(), async {}));
+        assert!(queues.enqueue("http://other", "a".into(), async {}));
+
+        let _ = release.send(());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(queues.depths()[1], ("http://t".to_string(), 0));
+    }
+
+    #[tokio::test]
+    async fn panicking_job_releases_its_slot() {
+        let queues = OrderedQueues::with_capacity(1);
+        assert!(queues.enqueue("http://t", "k".into(), async {
+            panic!("delivery failed");
+        }));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(queues.depths(), vec![("http://t".to_string(), 0)]);
+        assert!(queues.enqueue("http://t", "k".into(), async {}));
+    }
+}
@@ -90,7 +90,7 @@ pub fn match_route(
     best_match
 }
 
-fn method_matches(methods: &[String], method: &str) -> bool {
+pub fn method_matches(methods: &[String], method: &str) -> bool {
     methods
         .iter()
         .any(|m| m == "*" || m.eq_ignore_ascii_case(method))
@@ -110,11 +110,19 @@ mod tests {
             path: path.into(),
             methods: methods.iter().map(|s| s.to_string()).collect(),
             timeout: None,
+            forwarded_style: None,
             headers: Default::default(),
+            response_headers: Default::default(),
+            hedge: None,
+            dedup: None,
             targets: vec![Target {
                 url: "http://localhost:8080".into(),
                 primary: false,
                 timeout: None,
+                pool: None,
+                outbound_proxy: None,
+                ordering_key: None,
+                signing: None,
             }],
         }
     }
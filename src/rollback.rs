@@ -0,0 +1,199 @@
+//! Automatic rollback of config reloads that raise the error rate.
+//!
+//! After a reload, the new config is on probation for a watch window
+//! (`--rollback-window`). Its failure rate — requests answered with a 502
+//! because no target could be reached, over all proxied requests — is
+//! compared against the rate the previous config had while it was served.
+//! If it is higher by at least `--rollback-threshold` once enough requests
+//! have been seen, the refresh loop restores the previous config and
+//! rejects the new version until the source changes again.
+
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use crate::server::{LoadedConfig, Stats};
+
+/// When to roll a reload back. A zero window disables rollbacks.
+#[derive(Debug, Clone)]
+pub struct RollbackPolicy {
+    pub window: Duration,
+    /// The rise in failure rate (0 to 1) that triggers a rollback.
+    pub threshold: f64,
+    /// Requests to see under the new config before judging it.
+    pub min_requests: u64,
+}
+
+impl RollbackPolicy {
+    #[must_use]
+    pub fn enabled(&self) -> bool {
+        !self.window.is_zero()
+    }
+}
+
+/// Proxied request counters at a point in time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub forwarded: u64,
+    pub failed: u64,
+}
+
+impl Counts {
+    #[must_use]
+    pub fn read(stats: &Stats) -> Self {
+        Self {
+            forwarded: stats.forwarded.load(Ordering::Relaxed),
+            failed: stats.failed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The requests counted since `earlier`.
+    #[must_use]
+    pub const fn since(self, earlier: Self) -> Self {
+        Self {
+            forwarded: self.forwarded.saturating_sub(earlier.forwarded),
+            failed: self.failed.saturating_sub(earlier.failed),
+        }
+    }
+
+    #[must_use]
+    pub const fn total(self) -> u64 {
+        self.forwarded + self.failed
+    }
+
+    /// The share of requests that failed, or 0 without requests.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn failure_rate(self) -> f64 {
+        match self.total() {
+            0 => 0.0,
+            total => self.failed as f64 / total as f64,
+        }
+    }
+}
+
+/// A freshly reloaded config being watched.
+#[derive(Debug)]
+pub struct Probation {
+    /// The config to restore on rollback.
+    pub previous: LoadedConfig,
+    /// The previous config's failure rate.
+    pub baseline: f64,
+    start: Counts,
+    started: Instant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    /// Keep watching.
+    Pending,
+    /// The window passed without the threshold being breached.
+    Passed,
+    /// The failure rate rose past the threshold.
+    RollBack { failure_rate: f64 },
+}
+
+impl Probation {
+    /// Start watching a reload that replaced `previous`, which saw
+    /// `served` requests while it was loaded.
+    #[must_use]
+    pub fn new(previous: LoadedConfig, served: Counts, now: Counts) -> Self {
+        Self {
+            previous,
+            baseline: served.failure_rate(),
+            start: now,
+            started: Instant::now(),
+        }
+    }
+
+    /// Watch another reload that happened during the window: the window
+    /// starts over, still comparing against the config from before it.
+    pub fn restart(&mut self, now: Counts) {
+        self.start = now;
+        self.started = Instant::now();
+    }
+
+    #[must_use]
+    pub fn verdict(&self, now: Counts, policy: &RollbackPolicy) -> Verdict {
+        let counts = now.since(self.start);
+        if counts.total() >= policy.min_requests {
+            let failure_rate = counts.failure_rate();
+            if failure_rate - self.baseline >= policy.threshold {
+                return Verdict::RollBack { failure_rate };
+            }
+        }
+        if self.started.elapsed() >= policy.window {
+            Verdict::Passed
+        } else {
+            Verdict::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigVersion;
+
+    fn policy(window: Duration) -> RollbackPolicy {
+        RollbackPolicy {
+            window,
+            threshold: 0.5,
+            min_requests: 10,
+        }
+    }
+
+    fn previous() -> LoadedConfig {
+        LoadedConfig::new(
+            serde_json::from_str(r#"{"routes": []}"#).unwrap(),
+            ConfigVersion::Hash("old".into()),
+            "test".into(),
+            false,
+        )
+    }
+
+    const fn counts(forwarded: u64, failed: u64) -> Counts {
+        Counts { forwarded, failed }
+    }
+
+    #[test]
+    fn failure_rates() {
+        assert!(counts(0, 0).failure_rate().abs() < f64::EPSILON);
+        assert!((counts(3, 1).failure_rate() - 0.25).abs() < f64::EPSILON);
+        assert_eq!(counts(10, 4).since(counts(4, 1)), counts(6, 3));
+    }
+
+    #[test]
+    fn rolls_back_when_the_failure_rate_rises_past_the_threshold() {
+        let window = policy(Duration::from_secs(60));
+        // The previous config failed 1 request in 10
+        let probation = Probation::new(previous(), counts(9, 1), counts(100, 10));
+
+        // Too few requests to judge yet
+        assert_eq!(
+            probation.verdict(counts(100, 15), &window),
+            Verdict::Pending
+        );
+        // 50% failing is within 0.5 of the 10% baseline
+        assert_eq!(
+            probation.verdict(counts(105, 15), &window),
+            Verdict::Pending
+        );
+        assert_eq!(
+            probation.verdict(counts(102, 18), &window),
+            Verdict::RollBack { failure_rate: 0.8 }
+        );
+    }
+
+    #[test]
+    fn passes_once_the_window_is_over() {
+        let probation = Probation::new(previous(), counts(0, 0), counts(0, 0));
+        assert_eq!(
+            probation.verdict(counts(50, 1), &policy(Duration::ZERO)),
+            Verdict::Passed
+        );
+        assert_eq!(
+            probation.verdict(counts(0, 50), &policy(Duration::ZERO)),
+            Verdict::RollBack { failure_rate: 1.0 }
+        );
+    }
+}
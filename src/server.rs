@@ -1,34 +1,77 @@
 //! Axum server setup, shared application state, and graceful shutdown.
 //!
 //! Contains [`AppState`] (the `Arc`-shared state holding config, HTTP
-//! client, stats, and uptime), [`build_router`] for constructing the
-//! Axum router with middleware layers, [`build_http_client`] for the
-//! connection-pooled hyper client, and [`shutdown_signal`] for
-//! SIGTERM / Ctrl+C handling.
+//! clients, stats, and uptime), [`build_router`] for constructing the
+//! Axum router with middleware layers, [`HttpClients`] and
+//! [`build_http_client`] for the connection-pooled hyper clients, and
+//! [`shutdown_signal`] for SIGTERM / Ctrl+C handling.
 
+use std::collections::HashMap;
 use std::sync::atomic::AtomicU64;
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
-use crate::config::model::Config;
+use crate::config::diff::ChangeLog;
+use crate::config::model::{Config, OutboundProxy, PoolSettings};
 use crate::config::ConfigVersion;
 use crate::health::health_handler;
 use crate::proxy;
+use crate::proxy::client_ip::Cidr;
+pub use crate::proxy::dedup::{DedupStore, MemoryDedupStore};
+pub use crate::proxy::ordering::OrderedQueues;
+use crate::proxy::outbound::{self, ProxyConnector};
 use axum::routing::get;
 use axum::Router;
 use hyper_util::client::legacy::Client;
+use hyper_util::client::proxy::matcher::Matcher;
 use hyper_util::rt::TokioExecutor;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{OwnedSemaphorePermit, RwLock, Semaphore};
 use tower::ServiceBuilder;
 use tower_http::limit::RequestBodyLimitLayer;
 use tower_http::trace::TraceLayer;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LoadedConfig {
     pub config: Arc<Config>,
     pub version: ConfigVersion,
     pub source_name: String,
     pub loaded_at: Instant,
+    /// Whether the config is the last-known-good snapshot, served because
+    /// no config source could be loaded.
+    pub stale: bool,
+    /// `defaults.trusted_proxies`, parsed once per loaded config rather
+    /// than per request.
+    trusted_proxies: Arc<[Cidr]>,
+}
+
+impl LoadedConfig {
+    /// A config loaded from `source_name` just now.
+    #[must_use]
+    pub fn new(config: Config, version: ConfigVersion, source_name: String, stale: bool) -> Self {
+        // Validation rejects entries that do not parse, so none are dropped
+        // here for a served config
+        let trusted_proxies = config
+            .defaults
+            .trusted_proxies
+            .iter()
+            .filter_map(|cidr| cidr.parse().ok())
+            .collect();
+        Self {
+            config: Arc::new(config),
+            version,
+            source_name,
+            loaded_at: Instant::now(),
+            stale,
+            trusted_proxies,
+        }
+    }
+
+    /// The `defaults.trusted_proxies` ranges.
+    #[must_use]
+    pub const fn trusted_proxies(&self) -> &Arc<[Cidr]> {
+        &self.trusted_proxies
+    }
 }
 
 #[derive(Debug)]
@@ -37,6 +80,10 @@ pub struct Stats {
     pub failed: AtomicU64,
     pub active_requests: AtomicU64,
     pub config_reloads: AtomicU64,
+    /// Reloads undone because the failure rate rose (see [`crate::rollback`]).
+    pub config_rollbacks: AtomicU64,
+    pub hedged: AtomicU64,
+    pub deduplicated: AtomicU64,
 }
 
 impl Default for Stats {
@@ -53,6 +100,9 @@ impl Stats {
             failed: AtomicU64::new(0),
             active_requests: AtomicU64::new(0),
             config_reloads: AtomicU64::new(0),
+            config_rollbacks: AtomicU64::new(0),
+            hedged: AtomicU64::new(0),
+            deduplicated: AtomicU64::new(0),
         }
     }
 }
@@ -63,37 +113,205 @@ pub type LogReloadHandle = tracing_subscriber::reload::Handle<
     tracing_subscriber::Registry,
 >;
 
-pub type HttpsConnector =
-    hyper_rustls::HttpsConnector<hyper_util::client::legacy::connect::HttpConnector>;
+pub type HttpsConnector = hyper_rustls::HttpsConnector<ProxyConnector>;
 pub type HttpClient = Client<HttpsConnector, http_body_util::Full<bytes::Bytes>>;
 
+/// A pooled HTTP client plus its optional per-host in-flight request cap
+/// and outbound proxy rules.
+#[derive(Clone)]
+pub struct PooledClient {
+    client: HttpClient,
+    limit: Option<Arc<HostLimits>>,
+    proxy: Option<Arc<Matcher>>,
+}
+
+/// `max_connections` slots for each upstream authority, created on first
+/// use so targets sharing a client don't share a cap.
+struct HostLimits {
+    max: usize,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl HostLimits {
+    fn for_authority(&self, authority: &str) -> Arc<Semaphore> {
+        let mut hosts = self
+            .hosts
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        Arc::clone(
+            hosts
+                .entry(authority.to_ascii_lowercase())
+                .or_insert_with(|| Arc::new(Semaphore::new(self.max))),
+        )
+    }
+}
+
+impl PooledClient {
+    #[must_use]
+    pub const fn client(&self) -> &HttpClient {
+        &self.client
+    }
+
+    /// Wait for a free request slot towards the authority of `uri`.
+    /// Returns `None` when the client is uncapped; otherwise the slot is
+    /// held until the permit is dropped.
+    pub async fn acquire(&self, uri: &http::Uri) -> Option<OwnedSemaphorePermit> {
+        let limit = self.limit.as_deref()?;
+        let authority = uri.authority().map_or("", http::uri::Authority::as_str);
+        limit.for_authority(authority).acquire_owned().await.ok()
+    }
+
+    /// `Proxy-Authorization` to add to a request for `uri`, when it goes
+    /// through an authenticated proxy as a plain HTTP request.
+    #[must_use]
+    pub fn proxy_authorization(&self, uri: &http::Uri) -> Option<http::HeaderValue> {
+        self.proxy
+            .as_deref()
+            .and_then(|m| outbound::proxy_authorization(m, uri))
+    }
+}
+
+/// What makes two targets share a client: pool settings and proxy.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ClientKey {
+    pool: PoolSettings,
+    proxy: Option<OutboundProxy>,
+}
+
+/// Lazily-built HTTP clients keyed by [`PoolSettings`] and
+/// [`OutboundProxy`].
+///
+/// Targets with the same settings share a client (and its connection
+/// pool). A client survives config reloads as long as some target still
+/// uses its settings; [`HttpClients::retain_for`] drops the rest.
+#[derive(Default)]
+pub struct HttpClients {
+    clients: Mutex<HashMap<ClientKey, PooledClient>>,
+}
+
+impl HttpClients {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the client for `settings` and `proxy`, building it on first use.
+    pub fn get(&self, settings: &PoolSettings, proxy: Option<&OutboundProxy>) -> PooledClient {
+        let key = ClientKey {
+            pool: settings.clone(),
+            proxy: proxy.cloned(),
+        };
+        let mut clients = self
+            .clients
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        clients
+            .entry(key)
+            .or_insert_with(|| {
+                let matcher = outbound::matcher(proxy).map(Arc::new);
+                PooledClient {
+                    client: build_http_client(settings, matcher.clone()),
+                    limit: settings.max_connections.map(|max| {
+                        Arc::new(HostLimits {
+                            max,
+                            hosts: Mutex::default(),
+                        })
+                    }),
+                    proxy: matcher,
+                }
+            })
+            .clone()
+    }
+
+    /// Drop clients whose settings are no longer used by `config`.
+    ///
+    /// Call it after `config` is swapped in: a request still running on
+    /// the old config may otherwise rebuild a client dropped here, which
+    /// then lives until the next reload.
+    pub fn retain_for(&self, config: &Config) {
+        let targets = config
+            .routes
+            .iter()
+            .flat_map(|route| &route.targets)
+            .map(|target| ClientKey {
+                pool: target.pool_settings(&config.defaults).clone(),
+                proxy: target.outbound_proxy(&config.defaults).cloned(),
+            });
+        // Hedge requests go through the `defaults` client
+        let hedges = config
+            .routes
+            .iter()
+            .filter(|route| route.hedge.is_some())
+            .map(|_| ClientKey {
+                pool: config.defaults.pool.clone(),
+                proxy: config.defaults.outbound_proxy.clone(),
+            });
+        let in_use: std::collections::HashSet<ClientKey> = targets.chain(hedges).collect();
+        let mut clients = self
+            .clients
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        clients.retain(|key, _| in_use.contains(key));
+    }
+
+    /// Number of clients currently built.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.clients
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 pub struct AppState {
     pub config: RwLock<LoadedConfig>,
-    pub http_client: HttpClient,
+    pub http_clients: HttpClients,
+    pub ordered_queues: OrderedQueues,
+    pub dedup_store: Arc<dyn DedupStore>,
     pub start_time: Instant,
     pub namespace: String,
     pub stats: Stats,
+    /// Recent config reloads and rollbacks, with what they changed.
+    pub config_changes: ChangeLog,
     #[cfg(feature = "actuator")]
     pub log_reload_handle: Option<LogReloadHandle>,
     #[cfg(feature = "actuator")]
     pub current_log_level: RwLock<String>,
+    /// The database backend the config is loaded from, if it keeps versions.
+    #[cfg(feature = "actuator")]
+    pub config_store: Option<Arc<dyn crate::config::ConfigStore>>,
 }
 
 #[must_use]
-pub fn build_http_client() -> HttpClient {
+pub fn build_http_client(settings: &PoolSettings, proxy: Option<Arc<Matcher>>) -> HttpClient {
     // When multiple rustls crypto providers are compiled in (e.g. `--all-features`
     // enables both `ring` and `aws-lc-rs`), rustls cannot auto-detect which one
     // to use. Explicitly install `ring` as the default provider.
     let _ = rustls::crypto::ring::default_provider().install_default();
 
+    let mut http = hyper_util::client::legacy::connect::HttpConnector::new();
+    http.enforce_http(false);
+    http.set_nodelay(settings.nodelay);
+    http.set_keepalive(settings.tcp_keepalive.map(Duration::from_millis));
+
     let https = hyper_rustls::HttpsConnectorBuilder::new()
         .with_webpki_roots()
         .https_or_http()
         .enable_http1()
-        .build();
-    Client::builder(TokioExecutor::new())
-        .pool_idle_timeout(Duration::from_secs(30))
-        .build(https)
+        .wrap_connector(ProxyConnector::new(http, proxy));
+
+    let mut builder = Client::builder(TokioExecutor::new());
+    builder.pool_idle_timeout(Duration::from_millis(settings.idle_timeout));
+    if let Some(max_idle) = settings.max_idle_per_host {
+        builder.pool_max_idle_per_host(max_idle);
+    }
+    builder.build(https)
 }
 
 pub fn build_router(state: Arc<AppState>, max_body: usize) -> Router {
@@ -5,10 +5,11 @@ use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Instant;
 
+use switchboard::config::diff::ChangeLog;
 use switchboard::config::model::{
     ActuatorAuth, ActuatorConfig, Config, Defaults, HeaderRules, Route, Target,
 };
-use switchboard::config::ConfigVersion;
+use switchboard::config::{secrets, ConfigStore, ConfigVersion};
 use switchboard::server::{self, AppState, LoadedConfig, Stats};
 
 fn test_config(actuator_enabled: bool) -> Config {
@@ -30,20 +31,36 @@ fn test_config_with_auth(
             path: "/test".into(),
             methods: vec!["GET".into(), "POST".into()],
             timeout: Some(10_000),
+            forwarded_style: None,
             headers: HeaderRules::default(),
+            response_headers: HeaderRules::default(),
+            hedge: None,
+            dedup: None,
             targets: vec![
                 Target {
                     url: "http://primary:8080/test".into(),
                     primary: true,
                     timeout: None,
+                    pool: None,
+                    outbound_proxy: None,
+                    ordering_key: None,
+                    signing: None,
                 },
                 Target {
                     url: "http://secondary:9090/test".into(),
                     primary: false,
                     timeout: Some(2000),
+                    pool: None,
+                    outbound_proxy: None,
+                    ordering_key: None,
+                    signing: None,
                 },
             ],
         }],
+        include: Vec::new(),
+        profiles: Default::default(),
+        profile: None,
+        secrets: Default::default(),
     }
 }
 
@@ -56,19 +73,36 @@ async fn start_test_server_with_auth(
     password: &str,
 ) -> (SocketAddr, tokio::sync::oneshot::Sender<()>) {
     let config = test_config_with_auth(true, Some(username.into()), Some(password.into()));
+    start_server_with_config(config).await
+}
+
+async fn start_server_with_config(
+    config: Config,
+) -> (SocketAddr, tokio::sync::oneshot::Sender<()>) {
+    start_server_with_store(config, None).await
+}
+
+async fn start_server_with_store(
+    config: Config,
+    config_store: Option<Arc<dyn ConfigStore>>,
+) -> (SocketAddr, tokio::sync::oneshot::Sender<()>) {
     let state = Arc::new(AppState {
-        config: tokio::sync::RwLock::new(LoadedConfig {
-            config: Arc::new(config),
-            version: ConfigVersion::Hash("abcdef1234567890".into()),
-            source_name: "test".into(),
-            loaded_at: Instant::now(),
-        }),
-        http_client: server::build_http_client(),
+        config: tokio::sync::RwLock::new(LoadedConfig::new(
+            config,
+            ConfigVersion::Hash("abcdef1234567890".into()),
+            "test".into(),
+            false,
+        )),
+        http_clients: server::HttpClients::new(),
+        ordered_queues: server::OrderedQueues::new(),
+        dedup_store: Arc::new(server::MemoryDedupStore::default()),
         start_time: Instant::now(),
         namespace: "test".into(),
         stats: Stats::new(),
+        config_changes: ChangeLog::default(),
         log_reload_handle: None,
         current_log_level: tokio::sync::RwLock::new("INFO".into()),
+        config_store,
     });
 
     let router = server::build_router(state, 1_048_576);
@@ -98,18 +132,22 @@ async fn start_test_server_with(
 ) -> (SocketAddr, tokio::sync::oneshot::Sender<()>) {
     let config = test_config(actuator_enabled);
     let state = Arc::new(AppState {
-        config: tokio::sync::RwLock::new(LoadedConfig {
-            config: Arc::new(config),
-            version: ConfigVersion::Hash("abcdef1234567890".into()),
-            source_name: "test".into(),
-            loaded_at: Instant::now(),
-        }),
-        http_client: server::build_http_client(),
+        config: tokio::sync::RwLock::new(LoadedConfig::new(
+            config,
+            ConfigVersion::Hash("abcdef1234567890".into()),
+            "test".into(),
+            false,
+        )),
+        http_clients: server::HttpClients::new(),
+        ordered_queues: server::OrderedQueues::new(),
+        dedup_store: Arc::new(server::MemoryDedupStore::default()),
         start_time: Instant::now(),
         namespace: "test".into(),
         stats: Stats::new(),
+        config_changes: ChangeLog::default(),
         log_reload_handle: None,
         current_log_level: tokio::sync::RwLock::new("INFO".into()),
+        config_store: None,
     });
 
     let router = server::build_router(state, 1_048_576);
@@ -151,6 +189,8 @@ async fn actuator_index_returns_links() {
     assert!(links.get("env").is_some());
     assert!(links.get("metrics").is_some());
     assert!(links.get("configprops").is_some());
+    assert!(links.get("configversions").is_some());
+    assert!(links.get("configchanges").is_some());
     assert!(links.get("mappings").is_some());
     assert!(links.get("loggers").is_some());
 
@@ -221,6 +261,9 @@ async fn actuator_info_returns_build_metadata() {
     assert!(body["git"]["commit"].as_str().is_some());
     assert!(body["rust"]["version"].as_str().is_some());
     assert!(body["features"].as_array().is_some());
+    assert!(body["config"]["version"].as_str().is_some());
+    // Only git sources report a commit
+    assert!(body["config"].get("commit").is_none());
 
     let _ = shutdown.send(());
 }
@@ -251,6 +294,27 @@ async fn actuator_env_masks_sensitive_values() {
     let _ = shutdown.send(());
 }
 
+#[tokio::test]
+async fn actuator_env_reports_active_profile() {
+    let mut config = test_config(true);
+    config.profiles.insert(
+        "prod".into(),
+        serde_json::json!({"defaults": {"timeout": 1000}}),
+    );
+    let config = switchboard::config::profiles::apply(config, Some("prod")).unwrap();
+    let (addr, shutdown) = start_server_with_config(config).await;
+
+    let body: serde_json::Value = reqwest::get(format!("http://{addr}/actuator/env"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["activeProfiles"], serde_json::json!(["prod"]));
+
+    let _ = shutdown.send(());
+}
+
 #[tokio::test]
 async fn actuator_metrics_index_returns_names() {
     let (addr, shutdown) = start_test_server().await;
@@ -265,7 +329,9 @@ async fn actuator_metrics_index_returns_names() {
     assert!(names.contains(&serde_json::json!("requests.forwarded")));
     assert!(names.contains(&serde_json::json!("requests.failed")));
     assert!(names.contains(&serde_json::json!("requests.active")));
+    assert!(names.contains(&serde_json::json!("queue.depth")));
     assert!(names.contains(&serde_json::json!("config.reloads")));
+    assert!(names.contains(&serde_json::json!("config.rollbacks")));
     assert!(names.contains(&serde_json::json!("uptime.seconds")));
 
     let _ = shutdown.send(());
@@ -317,6 +383,122 @@ async fn actuator_configprops_returns_config() {
     let _ = shutdown.send(());
 }
 
+#[tokio::test]
+async fn actuator_configprops_masks_password_from_env() {
+    // Safety: env var mutation is unsafe since Rust 1.66 due to thread-safety
+    // concerns. Acceptable here because no other test reads these vars.
+    unsafe {
+        std::env::set_var("ACTUATOR_AUTH_USERNAME", "ops");
+        std::env::set_var("ACTUATOR_AUTH_PASSWORD", "from-env-secret");
+    }
+    let mut config = test_config(true);
+    config.apply_actuator_auth_env();
+    // Safety: see set_var above
+    unsafe {
+        std::env::remove_var("ACTUATOR_AUTH_USERNAME");
+        std::env::remove_var("ACTUATOR_AUTH_PASSWORD");
+    }
+
+    let (addr, shutdown) = start_server_with_config(config).await;
+    let resp = reqwest::Client::new()
+        .get(format!("http://{addr}/actuator/configprops"))
+        .basic_auth("ops", Some("from-env-secret"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["actuator"]["auth"]["username"], "ops");
+    assert_eq!(body["actuator"]["auth"]["password"], "******");
+    assert!(!body.to_string().contains("from-env-secret"));
+
+    let _ = shutdown.send(());
+}
+
+#[tokio::test]
+async fn actuator_configprops_masks_resolved_secrets() {
+    // Safety: env var mutation is unsafe since Rust 1.66 due to thread-safety
+    // concerns. Acceptable here because no other test reads this var.
+    unsafe { std::env::set_var("SWITCHBOARD_TEST_ACTUATOR_PASSWORD", "hunter2") };
+    let mut config = test_config_with_auth(
+        true,
+        Some("admin".into()),
+        Some("${env:SWITCHBOARD_TEST_ACTUATOR_PASSWORD}".into()),
+    );
+    config.routes[0].headers.add.insert(
+        "Authorization".into(),
+        "Bearer ${env:SWITCHBOARD_TEST_ACTUATOR_PASSWORD}".into(),
+    );
+    let config = secrets::interpolate(config).unwrap();
+    assert_eq!(config.actuator.auth.password.as_deref(), Some("hunter2"));
+
+    let (addr, shutdown) = start_server_with_config(config).await;
+    let resp = reqwest::Client::new()
+        .get(format!("http://{addr}/actuator/configprops"))
+        .basic_auth("admin", Some("hunter2"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["actuator"]["auth"]["password"], "******");
+    assert_eq!(
+        body["routes"][0]["headers"]["add"]["Authorization"],
+        "Bearer ******"
+    );
+    assert!(!body.to_string().contains("hunter2"));
+
+    let _ = shutdown.send(());
+}
+
+#[tokio::test]
+async fn actuator_configversions_without_store_returns_404() {
+    let (addr, shutdown) = start_test_server().await;
+
+    let resp = reqwest::get(format!("http://{addr}/actuator/configversions"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 404);
+
+    let _ = shutdown.send(());
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn actuator_configversions_without_history_returns_404() {
+    use switchboard::config::sources::sqlite_normalized::NormalizedSqliteSource;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let store = NormalizedSqliteSource::new(&tmp.path().join("sb.db"), "test")
+        .await
+        .unwrap();
+    let (addr, shutdown) = start_server_with_store(test_config(true), Some(Arc::new(store))).await;
+
+    let resp = reqwest::get(format!("http://{addr}/actuator/configversions"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 404);
+
+    let _ = shutdown.send(());
+}
+
+#[tokio::test]
+async fn actuator_configchanges_starts_empty() {
+    let (addr, shutdown) = start_test_server().await;
+
+    let resp = reqwest::get(format!("http://{addr}/actuator/configchanges"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["changes"], serde_json::json!([]));
+
+    let _ = shutdown.send(());
+}
+
 #[tokio::test]
 async fn actuator_mappings_returns_routes() {
     let (addr, shutdown) = start_test_server().await;
@@ -476,3 +658,23 @@ async fn actuator_without_auth_remains_open() {
 
     let _ = shutdown.send(());
 }
+
+#[tokio::test]
+async fn actuator_queue_depth_metric_has_target_tag() {
+    let (addr, shutdown) = start_test_server().await;
+
+    let resp = reqwest::get(format!(
+        "http://{addr}/actuator/metrics/queue.depth?tag=target:http://localhost:19999/echo"
+    ))
+    .await
+    .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["name"], "queue.depth");
+    assert_eq!(body["measurement"]["statistic"], "VALUE");
+    assert_eq!(body["measurement"]["value"], 0.0);
+    assert_eq!(body["availableTags"][0]["tag"], "target");
+
+    let _ = shutdown.send(());
+}
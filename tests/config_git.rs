@@ -0,0 +1,155 @@
+#![cfg(feature = "git")]
+//! Integration tests for the git repository config source, against local
+//! bare repositories.
+
+use std::path::Path;
+
+use git2::{Oid, Repository, Signature};
+use switchboard::config::sources::git_source::GitSource;
+use switchboard::config::{ConfigSource, ConfigVersion};
+
+const CONFIG_V1: &str = "routes:\n  - path: /a\n    targets:\n      - url: http://a:80\n";
+const CONFIG_V2: &str = "routes:\n  - path: /b\n    targets:\n      - url: http://b:80\n";
+
+/// Commit `content` as `path` on `refs/heads/main` of the bare repo.
+fn commit_file(repo: &Repository, path: &str, content: &str, author: &str) -> Oid {
+    let blob = repo.blob(content.as_bytes()).unwrap();
+    let mut tree = repo.treebuilder(None).unwrap();
+    tree.insert(path, blob, 0o100_644).unwrap();
+    let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+
+    let signature = Signature::now(author, &format!("{author}@example.com")).unwrap();
+    let parent = repo
+        .find_reference("refs/heads/main")
+        .ok()
+        .and_then(|r| r.peel_to_commit().ok());
+    let parents: Vec<_> = parent.iter().collect();
+    repo.commit(
+        Some("refs/heads/main"),
+        &signature,
+        &signature,
+        "update config",
+        &tree,
+        &parents,
+    )
+    .unwrap()
+}
+
+fn source(dir: &Path, reference: &str, file: &str) -> GitSource {
+    GitSource::new(
+        dir.join("origin.git").to_str().unwrap(),
+        reference,
+        Path::new(file),
+        Some(dir.join("mirror")),
+    )
+    .unwrap()
+}
+
+#[tokio::test]
+async fn git_source_follows_branch_commits() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let origin = Repository::init_bare(dir.join("origin.git")).unwrap();
+    let first = commit_file(&origin, "switchboard.yaml", CONFIG_V1, "alice");
+
+    let source = source(dir, "main", "switchboard.yaml");
+    let (config, version) = source.load().await.unwrap();
+    assert_eq!(config.routes[0].path, "/a");
+    assert_eq!(
+        version,
+        ConfigVersion::Commit {
+            sha: first.to_string(),
+            author: "alice <alice@example.com>".into(),
+        }
+    );
+    assert!(!source.has_changed(&version).await.unwrap());
+
+    let second = commit_file(&origin, "switchboard.yaml", CONFIG_V2, "bob");
+    assert!(source.has_changed(&version).await.unwrap());
+    let (config, version) = source.load().await.unwrap();
+    assert_eq!(config.routes[0].path, "/b");
+    assert_eq!(
+        version.commit(),
+        Some((second.to_string().as_str(), "bob <bob@example.com>"))
+    );
+}
+
+#[tokio::test]
+async fn git_source_resolves_tags_and_commits() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let origin = Repository::init_bare(dir.join("origin.git")).unwrap();
+    let first = commit_file(&origin, "switchboard.yaml", CONFIG_V1, "alice");
+    origin
+        .tag_lightweight("v1", &origin.find_object(first, None).unwrap(), false)
+        .unwrap();
+    commit_file(&origin, "switchboard.yaml", CONFIG_V2, "bob");
+
+    let (config, _) = source(dir, "v1", "switchboard.yaml").load().await.unwrap();
+    assert_eq!(config.routes[0].path, "/a");
+
+    let (config, _) = source(dir, &first.to_string(), "switchboard.yaml")
+        .load()
+        .await
+        .unwrap();
+    assert_eq!(config.routes[0].path, "/a");
+}
+
+#[tokio::test]
+async fn git_source_reports_missing_ref_and_file() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let origin = Repository::init_bare(dir.join("origin.git")).unwrap();
+    commit_file(&origin, "switchboard.yaml", CONFIG_V1, "alice");
+
+    let err = source(dir, "release", "switchboard.yaml")
+        .load()
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("ref 'release' not found"), "{err}");
+
+    let err = source(dir, "main", "config/prod.yaml")
+        .load()
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("config/prod.yaml"), "{err}");
+}
+
+#[tokio::test]
+async fn git_source_prunes_deleted_branches() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let origin = Repository::init_bare(dir.join("origin.git")).unwrap();
+    let first = commit_file(&origin, "switchboard.yaml", CONFIG_V1, "alice");
+    origin
+        .branch("release", &origin.find_commit(first).unwrap(), false)
+        .unwrap();
+
+    let source = source(dir, "release", "switchboard.yaml");
+    source.load().await.unwrap();
+
+    // Once the branch is gone upstream, the mirror must not keep serving it
+    origin
+        .find_branch("release", git2::BranchType::Local)
+        .unwrap()
+        .delete()
+        .unwrap();
+    let err = source.load().await.unwrap_err();
+    assert!(err.to_string().contains("ref 'release' not found"), "{err}");
+}
+
+#[tokio::test]
+async fn git_source_mirrors_into_a_private_temp_dir() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let origin = Repository::init_bare(dir.join("origin.git")).unwrap();
+    commit_file(&origin, "switchboard.yaml", CONFIG_V1, "alice");
+
+    let url = dir.join("origin.git");
+    let url = url.to_str().unwrap();
+    let first = GitSource::new(url, "main", Path::new("switchboard.yaml"), None).unwrap();
+    let second = GitSource::new(url, "main", Path::new("switchboard.yaml"), None).unwrap();
+    let (config, _) = first.load().await.unwrap();
+    assert_eq!(config.routes[0].path, "/a");
+    second.load().await.unwrap();
+}
@@ -0,0 +1,106 @@
+//! Integration tests for the HTTP(S) config source.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use switchboard::config::sources::http_source::{HttpAuth, HttpSource};
+use switchboard::config::ConfigSource;
+
+const CONFIG_V1: &str = "routes:\n  - path: /a\n    targets:\n      - url: http://a:80\n";
+const CONFIG_V2: &str = "routes:\n  - path: /b\n    targets:\n      - url: http://b:80\n";
+
+#[derive(Default)]
+struct Served {
+    body: Mutex<(String, &'static str)>,
+    full_responses: AtomicUsize,
+}
+
+async fn serve_config(State(served): State<Arc<Served>>, headers: HeaderMap) -> Response {
+    if headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        != Some("Bearer t0ken")
+    {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let (body, etag) = served.body.lock().unwrap().clone();
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        == Some(etag)
+    {
+        return StatusCode::NOT_MODIFIED.into_response();
+    }
+    served.full_responses.fetch_add(1, Ordering::Relaxed);
+    (
+        [
+            (header::CONTENT_TYPE, "application/yaml"),
+            (header::ETAG, etag),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+async fn start_config_server(served: Arc<Served>) -> SocketAddr {
+    let router = Router::new()
+        .route("/switchboard", get(serve_config))
+        .with_state(served);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    addr
+}
+
+#[tokio::test]
+async fn http_source_uses_conditional_requests() {
+    let served = Arc::new(Served::default());
+    *served.body.lock().unwrap() = (CONFIG_V1.into(), "\"v1\"");
+    let addr = start_config_server(served.clone()).await;
+
+    let auth = HttpAuth::Bearer("t0ken".into());
+    let source = HttpSource::new(&format!("http://{addr}/switchboard"), Some(auth)).unwrap();
+
+    // No extension in the URL: the format comes from Content-Type
+    let (config, version) = source.load().await.unwrap();
+    assert_eq!(config.routes[0].path, "/a");
+
+    assert!(!source.has_changed(&version).await.unwrap());
+    assert!(!source.has_changed(&version).await.unwrap());
+    assert_eq!(served.full_responses.load(Ordering::Relaxed), 1);
+
+    *served.body.lock().unwrap() = (CONFIG_V2.into(), "\"v2\"");
+    assert!(source.has_changed(&version).await.unwrap());
+    let (config, _) = source.load().await.unwrap();
+    assert_eq!(config.routes[0].path, "/b");
+}
+
+#[tokio::test]
+async fn http_source_sends_credentials() {
+    let served = Arc::new(Served::default());
+    *served.body.lock().unwrap() = (CONFIG_V1.into(), "\"v1\"");
+    let addr = start_config_server(served).await;
+
+    let source = HttpSource::new(&format!("http://{addr}/switchboard"), None).unwrap();
+    let err = source.load().await.unwrap_err();
+    assert!(err.to_string().contains("401"), "{err}");
+}
+
+#[tokio::test]
+async fn http_source_rejects_oversized_configs() {
+    let served = Arc::new(Served::default());
+    let padding = "#".repeat(9 * 1024 * 1024);
+    *served.body.lock().unwrap() = (format!("{CONFIG_V1}{padding}\n"), "\"v1\"");
+    let addr = start_config_server(served).await;
+
+    let auth = HttpAuth::Bearer("t0ken".into());
+    let source = HttpSource::new(&format!("http://{addr}/switchboard"), Some(auth)).unwrap();
+    let err = source.load().await.unwrap_err();
+    assert!(err.to_string().contains("larger than"), "{err}");
+}
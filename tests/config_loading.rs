@@ -1,8 +1,9 @@
 //! Integration tests for config loading across all file formats.
 
 use switchboard::config::model::Config;
-use switchboard::config::sources::parse_config_str;
+use switchboard::config::sources::{include, parse_config_str};
 use switchboard::config::validation::validate;
+use switchboard::error::SwitchboardError;
 
 fn load_example(name: &str) -> String {
     let path = format!("example/{name}");
@@ -66,6 +67,33 @@ fn all_formats_produce_equivalent_configs() {
     assert_eq!(yaml_config.routes[0].path, toml_config.routes[0].path);
 }
 
+#[cfg(all(feature = "json", feature = "toml"))]
+#[tokio::test]
+async fn inline_config_detects_every_format() {
+    use base64::Engine;
+    use switchboard::config::sources::inline::InlineSource;
+    use switchboard::config::ConfigSource;
+
+    let yaml = load_example("switchboard.yaml");
+    for content in [
+        yaml.clone(),
+        load_example("switchboard.json"),
+        load_example("switchboard.toml"),
+    ] {
+        let source = InlineSource::from_env(&content).unwrap();
+        assert_eq!(source.name(), "env");
+        let (config, _) = source.load().await.unwrap();
+        assert!(!config.routes.is_empty());
+    }
+
+    // The whole config may be base64-encoded, as one line
+    let encoded = base64::engine::general_purpose::STANDARD.encode(&yaml);
+    let source = InlineSource::from_env(&encoded).unwrap();
+    let (config, version) = source.load().await.unwrap();
+    assert!(!config.routes.is_empty());
+    assert!(!source.has_changed(&version).await.unwrap());
+}
+
 #[test]
 fn unsupported_format_returns_error() {
     let result = parse_config_str("xml", "{}", "test.xml");
@@ -90,3 +118,95 @@ fn config_total_targets_counts_correctly() {
     let config: Config = serde_json::from_str(json).unwrap();
     assert_eq!(config.total_targets(), 3);
 }
+
+fn parse_yaml(content: &str) -> Result<Config, SwitchboardError> {
+    parse_config_str("yaml", content, "switchboard.yaml")
+}
+
+#[test]
+fn include_merges_fragment_routes() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    std::fs::create_dir(dir.join("teams")).unwrap();
+    std::fs::write(
+        dir.join("switchboard.yaml"),
+        "include: [\"teams/*.yaml\"]\ndefaults:\n  timeout: 1000\nroutes:\n  - path: /root\n    targets:\n      - url: http://root:80\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("teams/b.yaml"),
+        "routes:\n  - path: /b\n    targets:\n      - url: http://b:80\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("teams/a.yaml"),
+        "routes:\n  - path: /a\n    targets:\n      - url: http://a:80\n",
+    )
+    .unwrap();
+
+    let root = dir.join("switchboard.yaml");
+    let (config, version) = include::load(&root, &parse_yaml, None).unwrap();
+    let paths: Vec<&str> = config.routes.iter().map(|r| r.path.as_str()).collect();
+    assert_eq!(paths, vec!["/root", "/a", "/b"]);
+    assert_eq!(config.defaults.timeout, 1000);
+
+    // Editing any fragment changes the version
+    std::fs::write(
+        dir.join("teams/a.yaml"),
+        "routes:\n  - path: /a2\n    targets:\n      - url: http://a:80\n",
+    )
+    .unwrap();
+    assert_ne!(
+        include::current_version(&root, &parse_yaml).unwrap(),
+        version
+    );
+}
+
+#[test]
+fn directory_errors_name_the_fragment() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    std::fs::create_dir(dir.join("teams")).unwrap();
+    std::fs::write(
+        dir.join("teams/orders.yaml"),
+        "routes:\n  - path: /orders\n    targets:\n      - url: http://orders:80\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("teams/users.yaml"),
+        "routes:\n  - path: users\n    targets:\n      - url: http://users:80\n",
+    )
+    .unwrap();
+
+    let err = include::load(&dir.join("teams"), &parse_yaml, None).unwrap_err();
+    let SwitchboardError::ConfigValidation { errors } = err else {
+        panic!("expected validation errors, got {err}");
+    };
+    assert_eq!(errors.len(), 1);
+    assert!(
+        errors[0].route.contains("users.yaml"),
+        "{}",
+        errors[0].route
+    );
+}
+
+#[test]
+fn defaults_may_only_be_set_once() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    std::fs::create_dir(dir.join("teams")).unwrap();
+    for name in ["a", "b"] {
+        std::fs::write(
+            dir.join(format!("teams/{name}.yaml")),
+            format!("defaults:\n  timeout: 100\nroutes:\n  - path: /{name}\n    targets:\n      - url: http://{name}:80\n"),
+        )
+        .unwrap();
+    }
+
+    let err = include::load(&dir.join("teams"), &parse_yaml, None).unwrap_err();
+    let SwitchboardError::ConfigValidation { errors } = err else {
+        panic!("expected validation errors, got {err}");
+    };
+    assert_eq!(errors[0].field, "defaults");
+    assert!(errors[0].message.contains("a.yaml") && errors[0].message.contains("b.yaml"));
+}
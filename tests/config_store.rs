@@ -0,0 +1,354 @@
+#![cfg(feature = "sqlite")]
+//! Integration tests for writing configs to a database backend, as done by
+//! `switchboard config push`, `history` and `rollback`, against a local
+//! `SQLite` file.
+
+use switchboard::cmd::config::prepare;
+use switchboard::config::payload::{decode_key, PayloadKeys, SigningKey};
+use switchboard::config::sources::sqlite::SqliteSource;
+use switchboard::config::sources::sqlite_normalized::NormalizedSqliteSource;
+use switchboard::config::{ConfigSource, ConfigStore, VersionMeta};
+use switchboard::error::SwitchboardError;
+
+fn meta(author: &str, comment: Option<&str>) -> VersionMeta {
+    VersionMeta {
+        author: author.into(),
+        comment: comment.map(Into::into),
+    }
+}
+
+#[tokio::test]
+async fn pushed_config_loads_from_sqlite() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let file = dir.join("switchboard.yaml");
+    std::fs::write(
+        &file,
+        "routes:\n  - path: /a\n    targets:\n      - url: http://a:80\n",
+    )
+    .unwrap();
+
+    let store = SqliteSource::new(&dir.join("sb.db"), "staging")
+        .await
+        .unwrap();
+    assert_eq!(store.read_json().await.unwrap(), None);
+    assert!(store.load().await.is_err());
+
+    let json = serde_json::to_string_pretty(&prepare(&file).unwrap()).unwrap();
+    let pushed = store.push_json(&json, &meta("ana", None)).await.unwrap();
+    assert_eq!(pushed, 1);
+    let stored = store.read_json().await.unwrap().unwrap();
+    assert_eq!(stored.json, json);
+    assert_eq!(stored.version, Some(1));
+    let (config, version) = store.load().await.unwrap();
+    assert_eq!(config.routes[0].path, "/a");
+
+    // A second push becomes the active version and is picked up as a change
+    std::fs::write(
+        &file,
+        "routes:\n  - path: /b\n    targets:\n      - url: http://b:80\n",
+    )
+    .unwrap();
+    let json = serde_json::to_string_pretty(&prepare(&file).unwrap()).unwrap();
+    let pushed = store
+        .push_json(&json, &meta("ben", Some("move to /b")))
+        .await
+        .unwrap();
+    assert_eq!(pushed, 2);
+    assert!(store.has_changed(&version).await.unwrap());
+    let (config, version) = store.load().await.unwrap();
+    assert_eq!(config.routes[0].path, "/b");
+
+    let history = store.history().await.unwrap();
+    let listed: Vec<_> = history
+        .iter()
+        .map(|v| (v.version, v.author.as_str(), v.comment.as_deref(), v.active))
+        .collect();
+    assert_eq!(
+        listed,
+        [
+            (2, "ben", Some("move to /b"), true),
+            (1, "ana", None, false)
+        ]
+    );
+
+    // Rolling back activates the old version without adding a new one
+    store.activate(1).await.unwrap();
+    assert!(store.has_changed(&version).await.unwrap());
+    let (config, _) = store.load().await.unwrap();
+    assert_eq!(config.routes[0].path, "/a");
+    let history = store.history().await.unwrap();
+    assert_eq!(history.len(), 2);
+    assert!(history[1].active && !history[0].active);
+    assert!(store.activate(3).await.is_err());
+
+    // Other namespaces are untouched
+    let other = SqliteSource::new(&dir.join("sb.db"), "prod").await.unwrap();
+    assert_eq!(other.read_json().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn concurrent_sqlite_pushes_get_distinct_versions() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("sb.db");
+    let file = tmp.path().join("switchboard.yaml");
+    std::fs::write(
+        &file,
+        "routes:\n  - path: /a\n    targets:\n      - url: http://a:80\n",
+    )
+    .unwrap();
+    let json = serde_json::to_string(&prepare(&file).unwrap()).unwrap();
+
+    // Separate sources have separate pools, like two `config push` runs
+    let mut stores = Vec::new();
+    for _ in 0..4 {
+        stores.push(SqliteSource::new(&path, "default").await.unwrap());
+    }
+    let mut pushes = tokio::task::JoinSet::new();
+    for store in stores {
+        let json = json.clone();
+        pushes.spawn(async move { store.push_json(&json, &meta("ana", None)).await });
+    }
+    let mut pushed: Vec<u64> = pushes
+        .join_all()
+        .await
+        .into_iter()
+        .map(Result::unwrap)
+        .collect();
+    pushed.sort_unstable();
+    assert_eq!(pushed, [1, 2, 3, 4]);
+
+    let store = SqliteSource::new(&path, "default").await.unwrap();
+    assert_eq!(store.history().await.unwrap().len(), 4);
+    let active = store.read_json().await.unwrap().unwrap().version;
+    assert!(active.is_some_and(|v| (1..=4).contains(&v)));
+}
+
+#[tokio::test]
+async fn unversioned_sqlite_config_becomes_version_one() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let path = dir.join("sb.db");
+    let json = r#"{"routes":[{"path":"/a","targets":[{"url":"http://a:80"}]}]}"#;
+
+    // The schema written before versioning existed
+    let options = sqlx::sqlite::SqliteConnectOptions::new()
+        .filename(&path)
+        .create_if_missing(true);
+    let pool = sqlx::SqlitePool::connect_with(options).await.unwrap();
+    sqlx::query(
+        "CREATE TABLE switchboard_config (namespace TEXT PRIMARY KEY, config_json TEXT NOT NULL)",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO switchboard_config VALUES ('default', ?)")
+        .bind(json)
+        .execute(&pool)
+        .await
+        .unwrap();
+    pool.close().await;
+
+    let store = SqliteSource::new(&path, "default").await.unwrap();
+    let stored = store.read_json().await.unwrap().unwrap();
+    assert_eq!(stored.json, json);
+    assert_eq!(stored.version, Some(1));
+    let history = store.history().await.unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].version, 1);
+    assert!(history[0].active);
+
+    let pushed = store
+        .push_json(json, &meta("ana", Some("same again")))
+        .await
+        .unwrap();
+    assert_eq!(pushed, 2);
+
+    // Migrating is idempotent
+    let store = SqliteSource::new(&path, "default").await.unwrap();
+    assert_eq!(store.history().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn signed_and_encrypted_configs_load_only_with_the_right_keys() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let json = r#"{"routes":[{"path":"/a","targets":[{"url":"http://a:80"}]}]}"#;
+    let pkcs8 =
+        ring::signature::Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
+    let signer = SigningKey::from_pkcs8(pkcs8.as_ref()).unwrap();
+    let keys = PayloadKeys::new(
+        Some(decode_key(&signer.public_key()).unwrap()),
+        Some([3; 32]),
+    );
+
+    let open = |keys: PayloadKeys| {
+        let path = dir.join("sb.db");
+        async move {
+            SqliteSource::new(&path, "default")
+                .await
+                .unwrap()
+                .with_payload_keys(keys)
+        }
+    };
+
+    let pusher = open(keys.clone().sealing(Some(signer), true).unwrap()).await;
+    pusher.push_json(json, &meta("ana", None)).await.unwrap();
+    let store = open(keys.clone()).await;
+    let (config, _) = store.load().await.unwrap();
+    assert_eq!(config.routes[0].path, "/a");
+    let stored = store.read_json().await.unwrap().unwrap();
+    assert!(!stored.json.contains("http://a:80"));
+
+    // Without the decryption key
+    let err = open(PayloadKeys::default()).await.load().await.unwrap_err();
+    assert!(
+        matches!(err, SwitchboardError::ConfigPayload { .. }),
+        "{err}"
+    );
+
+    // An unsigned push is rejected when a signature is required
+    let unsealed = open(PayloadKeys::default()).await;
+    unsealed.push_json(json, &meta("ben", None)).await.unwrap();
+    let err = store.load().await.unwrap_err();
+    assert!(err.to_string().contains("not signed"), "{err}");
+    let (config, _) = unsealed.load().await.unwrap();
+    assert_eq!(config.routes[0].path, "/a");
+
+    // Rolling back restores the version the config was signed as
+    store.activate(1).await.unwrap();
+    assert!(store.load().await.is_ok());
+
+    // Signed, then written to the database as another version, copied from
+    // another namespace, or edited
+    let resealed = |namespace: &str, version: u64, json: &str| {
+        let signer = SigningKey::from_pkcs8(pkcs8.as_ref()).unwrap();
+        PayloadKeys::default()
+            .sealing(Some(signer), false)
+            .unwrap()
+            .seal(json, namespace, version)
+            .unwrap()
+    };
+    let replayed = resealed("default", 1, json);
+    let pushed = unsealed
+        .push_json(&replayed, &meta("eve", None))
+        .await
+        .unwrap();
+    let err = store.load().await.unwrap_err();
+    assert!(
+        err.to_string().contains(&format!(
+            "signed as version 1, but version {pushed} is active"
+        )),
+        "{err}"
+    );
+
+    let copied = resealed("prod", pushed + 1, json);
+    unsealed
+        .push_json(&copied, &meta("eve", None))
+        .await
+        .unwrap();
+    let err = store.load().await.unwrap_err();
+    assert!(err.to_string().contains("namespace 'prod'"), "{err}");
+
+    let tampered = resealed("default", pushed + 2, json).replace("http://a:80", "http://evil:80");
+    unsealed
+        .push_json(&tampered, &meta("eve", None))
+        .await
+        .unwrap();
+    let err = store.load().await.unwrap_err();
+    assert!(err.to_string().contains("does not match"), "{err}");
+}
+
+#[tokio::test]
+async fn normalized_sqlite_rows_can_be_edited_with_sql() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let path = dir.join("sb.db");
+    let file = dir.join("switchboard.yaml");
+    std::fs::write(
+        &file,
+        "defaults:\n  headers:\n    add:\n      X-Env: prod\n\
+         routes:\n  - path: /orders\n    methods: [GET, POST]\n    targets:\n      \
+         - url: http://orders:80\n        primary: true\n      - url: http://audit:80\n  \
+         - path: /health\n    targets:\n      - url: http://health:80\n",
+    )
+    .unwrap();
+
+    let store = NormalizedSqliteSource::new(&path, "default").await.unwrap();
+    assert_eq!(store.read_json().await.unwrap(), None);
+
+    let json = serde_json::to_string_pretty(&prepare(&file).unwrap()).unwrap();
+    let pushed = store.push_json(&json, &meta("ana", None)).await.unwrap();
+    let (config, version) = store.load().await.unwrap();
+    assert_eq!(config.routes.len(), 2);
+    assert_eq!(config.routes[0].methods, ["GET", "POST"]);
+    assert_eq!(config.routes[0].targets[1].url, "http://audit:80");
+    assert_eq!(config.defaults.headers.add["X-Env"], "prod");
+    assert!(!store.has_changed(&version).await.unwrap());
+    assert!(store.history().await.is_err());
+
+    // Repoint one target with plain SQL
+    let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", path.display()))
+        .await
+        .unwrap();
+    sqlx::query(
+        "UPDATE switchboard_targets SET url = 'http://audit-v2:80' \
+         WHERE url = 'http://audit:80'",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    assert!(store.has_changed(&version).await.unwrap());
+    let (config, version) = store.load().await.unwrap();
+    assert_eq!(config.routes[0].targets[1].url, "http://audit-v2:80");
+
+    // Deleting a route drops its targets and header rules
+    sqlx::query("DELETE FROM switchboard_routes WHERE path = '/health'")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM switchboard_targets")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining, 2);
+    assert!(store.has_changed(&version).await.unwrap());
+    let (config, _) = store.load().await.unwrap();
+    assert_eq!(config.routes.len(), 1);
+
+    // A push replaces every row, and migrating again keeps them
+    let again = store.push_json(&json, &meta("ana", None)).await.unwrap();
+    assert!(again > pushed);
+    let store = NormalizedSqliteSource::new(&path, "default").await.unwrap();
+    let (config, version) = store.load().await.unwrap();
+    assert_eq!(config.routes.len(), 2);
+
+    // A namespace deleted and created again starts its counter over, but
+    // under a new epoch
+    let counter: i64 =
+        sqlx::query_scalar("SELECT version FROM switchboard_namespaces WHERE namespace = ?")
+            .bind("default")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    for table in [
+        "switchboard_header_rules",
+        "switchboard_routes",
+        "switchboard_defaults",
+        "switchboard_namespaces",
+    ] {
+        sqlx::query(&format!("DELETE FROM {table} WHERE namespace = 'default'"))
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+    let other = json.replace("/health", "/status");
+    store.push_json(&other, &meta("ben", None)).await.unwrap();
+    sqlx::query("UPDATE switchboard_namespaces SET version = ? WHERE namespace = 'default'")
+        .bind(counter)
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(store.has_changed(&version).await.unwrap());
+    assert_eq!(store.load().await.unwrap().0.routes[1].path, "/status");
+}
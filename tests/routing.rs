@@ -8,11 +8,19 @@ fn make_route(path: &str, methods: &[&str]) -> Route {
         path: path.into(),
         methods: methods.iter().map(|s| (*s).to_string()).collect(),
         timeout: None,
+        forwarded_style: None,
         headers: HeaderRules::default(),
+        response_headers: HeaderRules::default(),
+        hedge: None,
+        dedup: None,
         targets: vec![Target {
             url: "http://localhost:8080".into(),
             primary: false,
             timeout: None,
+            pool: None,
+            outbound_proxy: None,
+            ordering_key: None,
+            signing: None,
         }],
     }
 }
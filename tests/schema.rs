@@ -0,0 +1,77 @@
+#![cfg(feature = "schema")]
+//! Integration tests for the config JSON Schema.
+
+use serde_json::{json, Value};
+use switchboard::config::schema::config_schema;
+
+fn validator() -> jsonschema::Validator {
+    jsonschema::draft202012::new(&config_schema()).expect("schema should compile")
+}
+
+/// Parse an example file into a JSON document, if its format is enabled.
+fn load_example(name: &str) -> Option<Value> {
+    let content = std::fs::read_to_string(format!("example/{name}")).unwrap();
+    match name.rsplit_once('.').map(|(_, ext)| ext) {
+        #[cfg(feature = "yaml")]
+        Some("yaml" | "yml") => Some(serde_yml::from_str(&content).unwrap()),
+        #[cfg(feature = "json")]
+        Some("json") => Some(serde_json::from_str(&content).unwrap()),
+        #[cfg(feature = "toml")]
+        Some("toml") => Some(toml::from_str(&content).unwrap()),
+        _ => None,
+    }
+}
+
+#[test]
+fn schema_is_draft_2020_12() {
+    assert_eq!(
+        config_schema()["$schema"],
+        "https://json-schema.org/draft/2020-12/schema"
+    );
+}
+
+#[test]
+fn every_example_validates() {
+    let validator = validator();
+    let mut checked = 0;
+    for entry in std::fs::read_dir("example").unwrap() {
+        let name = entry.unwrap().file_name().to_string_lossy().into_owned();
+        let Some(instance) = load_example(&name) else {
+            continue;
+        };
+        let errors: Vec<String> = validator
+            .iter_errors(&instance)
+            .map(|e| format!("{}: {e}", e.instance_path))
+            .collect();
+        assert!(
+            errors.is_empty(),
+            "{name} does not match the schema: {errors:?}"
+        );
+        checked += 1;
+    }
+    assert!(checked > 0, "no example files checked");
+}
+
+#[test]
+fn unknown_fields_are_rejected() {
+    let validator = validator();
+    let config = json!({
+        "routes": [{"path": "/a", "targets": [{"url": "http://a:80", "primray": true}]}]
+    });
+    assert!(!validator.is_valid(&config));
+}
+
+#[test]
+fn defaults_and_enums_are_described() {
+    let schema = config_schema();
+    let defaults = &schema["$defs"]["Defaults"]["properties"];
+    assert_eq!(defaults["timeout"]["default"], 5000);
+    assert_eq!(defaults["forward_headers"]["default"], true);
+    assert!(defaults["timeout"]["description"].is_string());
+
+    let route = &schema["$defs"]["Route"]["properties"];
+    assert_eq!(route["methods"]["default"], json!(["*"]));
+    assert!(!validator().is_valid(&json!({
+        "routes": [{"path": "/a", "methods": ["FETCH"], "targets": [{"url": "http://a:80"}]}]
+    })));
+}
@@ -4,7 +4,11 @@ use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Instant;
 
-use switchboard::config::model::{Config, Defaults, HeaderRules, Route, Target};
+use switchboard::config::diff::ChangeLog;
+use switchboard::config::model::{
+    Config, Dedup, DedupKey, DedupMode, Defaults, HeaderRules, Hedge, OrderingKey, OutboundProxy,
+    PoolSettings, Route, SignatureEncoding, Signing, SigningAlgorithm, Target,
+};
 use switchboard::config::ConfigVersion;
 use switchboard::health::HealthResponse;
 use switchboard::server::{self, AppState, LoadedConfig, Stats};
@@ -17,47 +21,68 @@ fn test_config() -> Config {
             path: "/test".into(),
             methods: vec!["*".into()],
             timeout: None,
+            forwarded_style: None,
             headers: HeaderRules::default(),
+            response_headers: HeaderRules::default(),
+            hedge: None,
+            dedup: None,
             targets: vec![Target {
                 url: "http://localhost:19999/echo".into(),
                 primary: true,
                 timeout: None,
+                pool: None,
+                outbound_proxy: None,
+                ordering_key: None,
+                signing: None,
             }],
         }],
+        include: Vec::new(),
+        profiles: Default::default(),
+        profile: None,
+        secrets: Default::default(),
     }
 }
 
 async fn start_test_server() -> (SocketAddr, tokio::sync::oneshot::Sender<()>) {
-    let config = test_config();
+    start_server_with(test_config()).await
+}
 
+async fn start_server_with(config: Config) -> (SocketAddr, tokio::sync::oneshot::Sender<()>) {
     #[cfg(feature = "actuator")]
     let state = Arc::new(AppState {
-        config: tokio::sync::RwLock::new(LoadedConfig {
-            config: Arc::new(config),
-            version: ConfigVersion::Hash("test-hash".into()),
-            source_name: "test".into(),
-            loaded_at: Instant::now(),
-        }),
-        http_client: server::build_http_client(),
+        config: tokio::sync::RwLock::new(LoadedConfig::new(
+            config,
+            ConfigVersion::Hash("test-hash".into()),
+            "test".into(),
+            false,
+        )),
+        http_clients: server::HttpClients::new(),
+        ordered_queues: server::OrderedQueues::new(),
+        dedup_store: Arc::new(server::MemoryDedupStore::default()),
         start_time: Instant::now(),
         namespace: "test".into(),
         stats: Stats::new(),
+        config_changes: ChangeLog::default(),
         log_reload_handle: None,
         current_log_level: tokio::sync::RwLock::new("INFO".into()),
+        config_store: None,
     });
 
     #[cfg(not(feature = "actuator"))]
     let state = Arc::new(AppState {
-        config: tokio::sync::RwLock::new(LoadedConfig {
-            config: Arc::new(config),
-            version: ConfigVersion::Hash("test-hash".into()),
-            source_name: "test".into(),
-            loaded_at: Instant::now(),
-        }),
-        http_client: server::build_http_client(),
+        config: tokio::sync::RwLock::new(LoadedConfig::new(
+            config,
+            ConfigVersion::Hash("test-hash".into()),
+            "test".into(),
+            false,
+        )),
+        http_clients: server::HttpClients::new(),
+        ordered_queues: server::OrderedQueues::new(),
+        dedup_store: Arc::new(server::MemoryDedupStore::default()),
         start_time: Instant::now(),
         namespace: "test".into(),
         stats: Stats::new(),
+        config_changes: ChangeLog::default(),
     });
 
     let router = server::build_router(state, 1_048_576);
@@ -112,6 +137,42 @@ async fn unmatched_route_returns_404() {
     let _ = shutdown.send(());
 }
 
+#[tokio::test]
+async fn response_header_rules_apply_to_generated_responses() {
+    let mut config = test_config();
+    config.defaults.response_headers.add.insert(
+        "strict-transport-security".into(),
+        "max-age=63072000".into(),
+    );
+    config.routes[0]
+        .response_headers
+        .add
+        .insert("x-route".into(), "test".into());
+    let (addr, shutdown) = start_server_with(config).await;
+
+    // Nothing listens on the target, so the primary never answers
+    let resp = reqwest::get(format!("http://{addr}/test")).await.unwrap();
+    assert_eq!(resp.status(), 502);
+    assert_eq!(
+        resp.headers()["strict-transport-security"],
+        "max-age=63072000"
+    );
+    assert_eq!(resp.headers()["x-route"], "test");
+    assert!(resp.headers().contains_key("x-correlation-id"));
+
+    let resp = reqwest::get(format!("http://{addr}/nonexistent"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 404);
+    assert_eq!(
+        resp.headers()["strict-transport-security"],
+        "max-age=63072000"
+    );
+    assert!(!resp.headers().contains_key("x-route"));
+
+    let _ = shutdown.send(());
+}
+
 #[tokio::test]
 async fn health_version_matches_crate() {
     let (addr, shutdown) = start_test_server().await;
@@ -141,3 +202,593 @@ async fn graceful_shutdown_works() {
     let result = reqwest::get(&url).await;
     assert!(result.is_err());
 }
+
+#[tokio::test]
+async fn http_clients_are_shared_by_settings_and_pruned_on_reload() {
+    let clients = server::HttpClients::new();
+    let mut config = test_config();
+
+    let defaults = config.defaults.pool.clone();
+    clients.get(&defaults, None);
+    clients.get(&defaults, None);
+    assert_eq!(clients.len(), 1);
+
+    let isolated = PoolSettings {
+        max_idle_per_host: Some(4),
+        max_connections: Some(16),
+        ..PoolSettings::default()
+    };
+    clients.get(&isolated, None);
+    assert_eq!(clients.len(), 2);
+
+    // Only the default settings are in use: the isolated client is dropped
+    clients.retain_for(&config);
+    assert_eq!(clients.len(), 1);
+
+    // Switching the target to its own settings drops the default client
+    config.routes[0].targets[0].pool = Some(isolated.clone());
+    clients.get(&isolated, None);
+    clients.retain_for(&config);
+    assert_eq!(clients.len(), 1);
+
+    // ...unless a hedge still needs it
+    config.routes[0].hedge = Some(Hedge {
+        url: "http://localhost:19998".into(),
+        delay: 50,
+        methods: vec!["GET".into()],
+        timeout: None,
+    });
+    clients.get(&defaults, None);
+    clients.retain_for(&config);
+    assert_eq!(clients.len(), 2);
+}
+
+#[tokio::test]
+async fn pooled_client_caps_in_flight_requests() {
+    let clients = server::HttpClients::new();
+    let capped = clients.get(
+        &PoolSettings {
+            max_connections: Some(1),
+            ..PoolSettings::default()
+        },
+        None,
+    );
+    let uri: http::Uri = "http://localhost:19999/".parse().unwrap();
+
+    let permit = capped.acquire(&uri).await;
+    assert!(permit.is_some());
+    let second =
+        tokio::time::timeout(std::time::Duration::from_millis(50), capped.acquire(&uri)).await;
+    assert!(
+        second.is_err(),
+        "second request should wait for a free slot"
+    );
+
+    drop(permit);
+    assert!(capped.acquire(&uri).await.is_some());
+
+    let uncapped = clients.get(&PoolSettings::default(), None);
+    assert!(uncapped.acquire(&uri).await.is_none());
+}
+
+#[tokio::test]
+async fn pooled_client_caps_each_host_separately() {
+    let clients = server::HttpClients::new();
+    let settings = PoolSettings {
+        max_connections: Some(1),
+        ..PoolSettings::default()
+    };
+    let first: http::Uri = "http://localhost:19998/".parse().unwrap();
+    let second: http::Uri = "http://localhost:19999/".parse().unwrap();
+
+    // Both targets share one client...
+    let a = clients.get(&settings, None);
+    let b = clients.get(&settings, None);
+    assert_eq!(clients.len(), 1);
+
+    // ...but saturating the first host leaves the second unblocked
+    let _held = a.acquire(&first).await;
+    let other =
+        tokio::time::timeout(std::time::Duration::from_millis(50), b.acquire(&second)).await;
+    assert!(other.unwrap().is_some());
+    let same = tokio::time::timeout(std::time::Duration::from_millis(50), b.acquire(&first)).await;
+    assert!(same.is_err(), "the first host should still be capped");
+}
+
+/// A one-shot HTTP proxy stand-in: captures the request head it receives
+/// and answers with `response`.
+async fn fake_proxy(
+    response: &'static str,
+) -> (SocketAddr, tokio::sync::oneshot::Receiver<String>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (head_tx, head_rx) = tokio::sync::oneshot::channel();
+
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut head = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = stream.read(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            head.extend_from_slice(&buf[..n]);
+        }
+        let _ = head_tx.send(String::from_utf8_lossy(&head).into_owned());
+        stream.write_all(response.as_bytes()).await.unwrap();
+        let _ = stream.shutdown().await;
+    });
+
+    (addr, head_rx)
+}
+
+fn proxied_config(target: &str, proxy: SocketAddr) -> Config {
+    let mut config = test_config();
+    config.routes[0].targets[0].url = target.into();
+    config.defaults.outbound_proxy = Some(OutboundProxy {
+        url: Some(format!("http://user:secret@{proxy}")),
+        no_proxy: Vec::new(),
+        enabled: true,
+    });
+    config
+}
+
+#[tokio::test]
+async fn http_target_is_sent_through_outbound_proxy() {
+    let (proxy, head) =
+        fake_proxy("HTTP/1.1 200 OK\r\ncontent-length: 7\r\nconnection: close\r\n\r\nproxied")
+            .await;
+    let (addr, shutdown) =
+        start_server_with(proxied_config("http://upstream.invalid/echo", proxy)).await;
+
+    let resp = reqwest::get(format!("http://{addr}/test")).await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text().await.unwrap(), "proxied");
+
+    let head = head.await.unwrap();
+    assert!(
+        head.starts_with("GET http://upstream.invalid/echo HTTP/1.1\r\n"),
+        "expected absolute-form request, got: {head}"
+    );
+    // base64("user:secret")
+    assert!(head
+        .to_ascii_lowercase()
+        .contains("proxy-authorization: basic dxnlcjpzzwnyzxq="));
+
+    let _ = shutdown.send(());
+}
+
+#[tokio::test]
+async fn https_target_is_tunnelled_through_outbound_proxy() {
+    let (proxy, head) = fake_proxy("HTTP/1.1 403 Forbidden\r\n\r\n").await;
+    let (addr, shutdown) =
+        start_server_with(proxied_config("https://upstream.invalid/echo", proxy)).await;
+
+    let resp = reqwest::get(format!("http://{addr}/test")).await.unwrap();
+    assert_eq!(resp.status(), 502);
+
+    let head = head.await.unwrap();
+    assert!(
+        head.starts_with("CONNECT upstream.invalid:443 HTTP/1.1\r\n"),
+        "expected CONNECT, got: {head}"
+    );
+    assert!(head
+        .to_ascii_lowercase()
+        .contains("proxy-authorization: basic dxnlcjpzzwnyzxq="));
+
+    let _ = shutdown.send(());
+}
+
+/// An upstream that answers every request with `body` after `delay`.
+async fn start_upstream(body: &'static str, delay: std::time::Duration) -> SocketAddr {
+    let app = axum::Router::new().fallback(move || async move {
+        tokio::time::sleep(delay).await;
+        body
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    addr
+}
+
+async fn hedged_server() -> (SocketAddr, tokio::sync::oneshot::Sender<()>) {
+    let slow = start_upstream("primary", std::time::Duration::from_millis(500)).await;
+    let fast = start_upstream("hedge", std::time::Duration::ZERO).await;
+
+    let mut config = test_config();
+    config.routes[0].targets[0].url = format!("http://{slow}/echo");
+    config.routes[0].hedge = Some(Hedge {
+        url: format!("http://{fast}/echo"),
+        delay: 50,
+        methods: vec!["GET".into()],
+        timeout: None,
+    });
+    start_server_with(config).await
+}
+
+#[tokio::test]
+async fn slow_primary_is_hedged() {
+    let (addr, shutdown) = hedged_server().await;
+
+    let resp = reqwest::get(format!("http://{addr}/test")).await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text().await.unwrap(), "hedge");
+
+    let _ = shutdown.send(());
+}
+
+#[tokio::test]
+async fn hedge_is_limited_to_configured_methods() {
+    let (addr, shutdown) = hedged_server().await;
+
+    let resp = reqwest::Client::new()
+        .post(format!("http://{addr}/test"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text().await.unwrap(), "primary");
+
+    let _ = shutdown.send(());
+}
+
+#[tokio::test]
+async fn ordered_secondary_receives_same_key_in_order() {
+    let primary = start_upstream("ok", std::time::Duration::ZERO).await;
+
+    // Earlier requests take longer: unordered delivery would finish last-first
+    let (seen_tx, mut seen_rx) = tokio::sync::mpsc::unbounded_channel::<u64>();
+    let app = axum::Router::new().fallback(move |body: String| {
+        let seen_tx = seen_tx.clone();
+        async move {
+            let n: u64 = body.parse().unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(20 * (5 - n))).await;
+            let _ = seen_tx.send(n);
+        }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let secondary = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let mut config = test_config();
+    config.routes[0].targets[0].url = format!("http://{primary}/echo");
+    config.routes[0].targets.push(Target {
+        url: format!("http://{secondary}/audit"),
+        primary: false,
+        timeout: None,
+        pool: None,
+        outbound_proxy: None,
+        ordering_key: Some(OrderingKey::Header("x-order-id".into())),
+        signing: None,
+    });
+    let (addr, shutdown) = start_server_with(config).await;
+
+    let client = reqwest::Client::new();
+    for n in 0..5 {
+        let resp = client
+            .post(format!("http://{addr}/test"))
+            .header("x-order-id", "order-1")
+            .body(n.to_string())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+    }
+
+    let mut seen = Vec::new();
+    for _ in 0..5 {
+        seen.push(seen_rx.recv().await.unwrap());
+    }
+    assert_eq!(seen, vec![0, 1, 2, 3, 4]);
+
+    let _ = shutdown.send(());
+}
+
+/// An upstream that counts requests and answers with the running count.
+async fn start_counter() -> (SocketAddr, Arc<std::sync::atomic::AtomicU64>) {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    let hits = Arc::new(AtomicU64::new(0));
+    let counter = hits.clone();
+    let app = axum::Router::new().fallback(move || {
+        let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+        async move { n.to_string() }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    (addr, hits)
+}
+
+async fn dedup_server(
+    mode: DedupMode,
+) -> (
+    SocketAddr,
+    tokio::sync::oneshot::Sender<()>,
+    Arc<std::sync::atomic::AtomicU64>,
+    Arc<std::sync::atomic::AtomicU64>,
+) {
+    let (primary, primary_hits) = start_counter().await;
+    let (secondary, secondary_hits) = start_counter().await;
+
+    let mut config = test_config();
+    config.routes[0].targets[0].url = format!("http://{primary}/echo");
+    config.routes[0].targets.push(Target {
+        url: format!("http://{secondary}/audit"),
+        primary: false,
+        timeout: None,
+        pool: None,
+        outbound_proxy: None,
+        ordering_key: None,
+        signing: None,
+    });
+    config.routes[0].dedup = Some(Dedup {
+        key: DedupKey::Header,
+        header: "Idempotency-Key".into(),
+        ttl: 60_000,
+        mode,
+    });
+    let (addr, shutdown) = start_server_with(config).await;
+    (addr, shutdown, primary_hits, secondary_hits)
+}
+
+#[tokio::test]
+async fn duplicate_request_replays_primary_response() {
+    use std::sync::atomic::Ordering;
+
+    let (addr, shutdown, primary_hits, secondary_hits) = dedup_server(DedupMode::Replay).await;
+    let client = reqwest::Client::new();
+    let post = |key: &'static str| {
+        client
+            .post(format!("http://{addr}/test"))
+            .header("idempotency-key", key)
+            .send()
+    };
+
+    let first = post("k1").await.unwrap();
+    assert!(first.headers().get("idempotent-replayed").is_none());
+    assert_eq!(first.text().await.unwrap(), "1");
+
+    let retry = post("k1").await.unwrap();
+    assert_eq!(retry.headers()["idempotent-replayed"], "true");
+    assert_eq!(retry.text().await.unwrap(), "1");
+
+    let other = post("k2").await.unwrap();
+    assert_eq!(other.text().await.unwrap(), "2");
+
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert_eq!(primary_hits.load(Ordering::SeqCst), 2);
+    assert_eq!(secondary_hits.load(Ordering::SeqCst), 2);
+
+    let _ = shutdown.send(());
+}
+
+#[tokio::test]
+async fn duplicate_of_in_flight_request_is_rejected() {
+    use std::sync::atomic::Ordering;
+
+    let slow = start_upstream("done", std::time::Duration::from_millis(300)).await;
+    let (secondary, secondary_hits) = start_counter().await;
+    let mut config = test_config();
+    config.routes[0].targets[0].url = format!("http://{slow}/echo");
+    config.routes[0].targets.push(Target {
+        url: format!("http://{secondary}/audit"),
+        primary: false,
+        timeout: None,
+        pool: None,
+        outbound_proxy: None,
+        ordering_key: None,
+        signing: None,
+    });
+    config.routes[0].dedup = Some(Dedup {
+        key: DedupKey::Header,
+        header: "Idempotency-Key".into(),
+        ttl: 60_000,
+        mode: DedupMode::Replay,
+    });
+    let (addr, shutdown) = start_server_with(config).await;
+    let client = reqwest::Client::new();
+    let post = move || {
+        client
+            .post(format!("http://{addr}/test"))
+            .header("idempotency-key", "k1")
+            .send()
+    };
+
+    let first = tokio::spawn(post());
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    let retry = post().await.unwrap();
+    assert_eq!(retry.status(), 409);
+    assert_eq!(retry.headers()["retry-after"], "1");
+
+    let first = first.await.unwrap().unwrap();
+    assert_eq!(first.text().await.unwrap(), "done");
+    let retry = post().await.unwrap();
+    assert_eq!(retry.headers()["idempotent-replayed"], "true");
+
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert_eq!(secondary_hits.load(Ordering::SeqCst), 1);
+
+    let _ = shutdown.send(());
+}
+
+#[tokio::test]
+async fn failed_request_releases_its_idempotency_key() {
+    let mut config = test_config();
+    config.routes[0].dedup = Some(Dedup {
+        key: DedupKey::Header,
+        header: "Idempotency-Key".into(),
+        ttl: 60_000,
+        mode: DedupMode::Replay,
+    });
+    let (addr, shutdown) = start_server_with(config).await;
+    let client = reqwest::Client::new();
+
+    // Nothing listens on the primary: every attempt is forwarded again
+    for _ in 0..2 {
+        let resp = client
+            .post(format!("http://{addr}/test"))
+            .header("idempotency-key", "k1")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 502);
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+
+    let _ = shutdown.send(());
+}
+
+#[tokio::test]
+async fn duplicate_request_can_skip_secondaries() {
+    use std::sync::atomic::Ordering;
+
+    let (addr, shutdown, primary_hits, secondary_hits) =
+        dedup_server(DedupMode::SkipSecondaries).await;
+    let client = reqwest::Client::new();
+
+    for expected in ["1", "2"] {
+        let resp = client
+            .post(format!("http://{addr}/test"))
+            .header("idempotency-key", "k1")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.text().await.unwrap(), expected);
+    }
+
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert_eq!(primary_hits.load(Ordering::SeqCst), 2);
+    assert_eq!(secondary_hits.load(Ordering::SeqCst), 1);
+
+    let _ = shutdown.send(());
+}
+
+#[tokio::test]
+async fn signed_secondary_receives_hmac_header() {
+    let primary = start_upstream("ok", std::time::Duration::ZERO).await;
+
+    let (seen_tx, mut seen_rx) = tokio::sync::mpsc::unbounded_channel();
+    let app = axum::Router::new().fallback(move |headers: axum::http::HeaderMap, body: String| {
+        let seen_tx = seen_tx.clone();
+        async move {
+            let _ = seen_tx.send((headers, body));
+        }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let secondary = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let signing = Signing {
+        algorithm: SigningAlgorithm::HmacSha256,
+        secret: "whsec".into(),
+        header: "X-Hub-Signature-256".into(),
+        timestamp_header: "X-Signature-Timestamp".into(),
+        template: "{timestamp}.{method}.{path}.{body}".into(),
+        encoding: SignatureEncoding::Hex,
+        prefix: "sha256=".into(),
+    };
+    let mut config = test_config();
+    config.routes[0].targets[0].url = format!("http://{primary}/echo");
+    config.routes[0].targets.push(Target {
+        url: format!("http://{secondary}/hooks"),
+        primary: false,
+        timeout: None,
+        pool: None,
+        outbound_proxy: None,
+        ordering_key: None,
+        signing: Some(signing.clone()),
+    });
+    let (addr, shutdown) = start_server_with(config).await;
+
+    let resp = reqwest::Client::new()
+        .post(format!("http://{addr}/test?x=1"))
+        .body("{\"event\":\"created\"}")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let (headers, body) = seen_rx.recv().await.unwrap();
+    let timestamp = headers["x-signature-timestamp"].to_str().unwrap();
+    let payload = format!("{timestamp}.POST./hooks.{body}");
+    let expected = switchboard::proxy::signing::signature(&signing, b"whsec", payload.as_bytes());
+    assert_eq!(headers["x-hub-signature-256"], expected.as_str());
+
+    let _ = shutdown.send(());
+}
+
+#[tokio::test]
+async fn queued_requests_are_signed_when_sent() {
+    let primary = start_upstream("ok", std::time::Duration::ZERO).await;
+    let (seen_tx, mut seen_rx) = tokio::sync::mpsc::unbounded_channel();
+    let app = axum::Router::new().fallback(move |headers: axum::http::HeaderMap| {
+        let seen_tx = seen_tx.clone();
+        async move {
+            let _ = seen_tx.send(headers);
+            tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let secondary = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let mut config = test_config();
+    config.routes[0].targets[0].url = format!("http://{primary}/echo");
+    config.routes[0].targets.push(Target {
+        url: format!("http://{secondary}/hooks"),
+        primary: false,
+        timeout: None,
+        pool: None,
+        outbound_proxy: None,
+        ordering_key: Some(OrderingKey::Header("x-order-id".into())),
+        signing: Some(Signing {
+            algorithm: SigningAlgorithm::HmacSha256,
+            secret: "whsec".into(),
+            header: "X-Signature".into(),
+            timestamp_header: "X-Signature-Timestamp".into(),
+            template: "{timestamp}.{body}".into(),
+            encoding: SignatureEncoding::Hex,
+            prefix: String::new(),
+        }),
+    });
+    let (addr, shutdown) = start_server_with(config).await;
+
+    // The second request waits in the queue until the slow first one is done
+    let client = reqwest::Client::new();
+    for _ in 0..2 {
+        let resp = client
+            .post(format!("http://{addr}/test"))
+            .header("x-order-id", "o1")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+    }
+
+    let timestamp = |headers: axum::http::HeaderMap| -> u64 {
+        headers["x-signature-timestamp"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap()
+    };
+    let first = timestamp(seen_rx.recv().await.unwrap());
+    let second = timestamp(seen_rx.recv().await.unwrap());
+    assert!(second > first, "queued request kept a stale timestamp");
+
+    let _ = shutdown.send(());
+}